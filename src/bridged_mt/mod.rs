@@ -1,9 +1,15 @@
 use crate::{
-	poseidon::{builder::Poseidon, Poseidon_hash_2_constraints, Poseidon_hash_4_constraints},
-	utils::{constrain_lc_with_scalar, AllocatedScalar},
+	poseidon::{
+		builder::Poseidon, Poseidon_hash_2_constraints,
+		Poseidon_hash_4_constraints,
+	},
+	smt::merkle_path_root_constraints,
+	utils::{constrain_lc_with_scalar, scalar_bits_gadget, AllocatedScalar},
 };
-use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError, Variable};
 use alloc::vec::Vec;
+use bulletproofs::r1cs::{
+	ConstraintSystem, LinearCombination, R1CSError, Variable,
+};
 use curve25519_dalek::scalar::Scalar;
 
 #[cfg(test)]
@@ -19,7 +25,7 @@ pub struct BridgeTx {
 	r: AllocatedScalar,
 	nullifier: AllocatedScalar,
 	leaf_cm_val: AllocatedScalar,
-	leaf_index_bits: Vec<AllocatedScalar>,
+	leaf_index: AllocatedScalar,
 	leaf_proof_nodes: Vec<AllocatedScalar>,
 	diff_vars: Vec<AllocatedScalar>,
 	// public
@@ -57,55 +63,32 @@ pub fn set_membership_verif_gadget<CS: ConstraintSystem>(
 	Ok(())
 }
 
-/// left = (1-leaf_side) * leaf + (leaf_side * proof_node)
-/// right = leaf_side * leaf + ((1-leaf_side) * proof_node))
+/// Membership of `leaf_val` at the committed `leaf_index` under one of
+/// `roots`. The index is decomposed into `depth` bits in circuit and the
+/// proof nodes can be allocated with `allocate_scalars`.
 pub fn one_of_many_merkle_tree_verif_gadget<CS: ConstraintSystem>(
 	cs: &mut CS,
 	depth: usize,
 	roots: &[Scalar],
 	leaf_val: AllocatedScalar,
-	leaf_index_bits: Vec<AllocatedScalar>,
+	leaf_index: AllocatedScalar,
 	proof_nodes: Vec<AllocatedScalar>,
 	diff_vars: Vec<AllocatedScalar>,
 	statics: Vec<AllocatedScalar>,
 	poseidon_params: &Poseidon,
 ) -> Result<(), R1CSError> {
-	let mut prev_hash = LinearCombination::default();
-
 	let statics: Vec<LinearCombination> =
 		statics.iter().map(|s| s.variable.into()).collect();
 
-	for i in 0..depth {
-		let leaf_val_lc = if i == 0 {
-			LinearCombination::from(leaf_val.variable)
-		} else {
-			prev_hash.clone()
-		};
-		let one_minus_leaf_side: LinearCombination =
-			(Variable::One() - leaf_index_bits[i].variable).into();
-
-		let (_, _, left_1) =
-			cs.multiply(one_minus_leaf_side.clone(), leaf_val_lc.clone());
-		let (_, _, left_2) = cs.multiply(
-			leaf_index_bits[i].variable.into(),
-			proof_nodes[i].variable.into(),
-		);
-		let left = left_1 + left_2;
-
-		let (_, _, right_1) =
-			cs.multiply(leaf_index_bits[i].variable.into(), leaf_val_lc);
-		let (_, _, right_2) =
-			cs.multiply(one_minus_leaf_side, proof_nodes[i].variable.into());
-		let right = right_1 + right_2;
-
-		prev_hash = Poseidon_hash_2_constraints::<CS>(
-			cs,
-			left,
-			right,
-			statics.clone(),
-			poseidon_params,
-		)?;
-	}
+	let leaf_index_bits = scalar_bits_gadget(cs, leaf_index, depth)?;
+	let prev_hash = merkle_path_root_constraints(
+		cs,
+		leaf_val.variable.into(),
+		&leaf_index_bits,
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
 
 	// verify that computed root is a member of a list of merkle roots
 	set_membership_verif_gadget(cs, prev_hash, diff_vars, roots)?;
@@ -160,7 +143,7 @@ pub fn bridged_tree_verif_gadget<CS: ConstraintSystem>(
 		depth,
 		roots,
 		tx.leaf_cm_val,
-		tx.leaf_index_bits,
+		tx.leaf_index,
 		tx.leaf_proof_nodes,
 		tx.diff_vars,
 		statics_2,
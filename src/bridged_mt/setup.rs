@@ -5,8 +5,8 @@ use crate::{
 		allocate_statics_for_prover, allocate_statics_for_verifier,
 		builder::Poseidon,
 	},
-	smt::{builder::DEFAULT_TREE_DEPTH, VanillaSparseMerkleTree},
-	utils::{get_bits, AllocatedScalar},
+	smt::VanillaSparseMerkleTree,
+	utils::{allocate_scalars, AllocatedScalar},
 };
 use bulletproofs::{
 	r1cs::{Prover, R1CSProof, Verifier},
@@ -33,8 +33,6 @@ pub struct BridgeTxInputs {
 #[derive(Debug, Clone)]
 pub struct BridgeTxComms {
 	input_comms: Vec<CompressedRistretto>,
-	leaf_index_comms: Vec<CompressedRistretto>,
-	proof_comms: Vec<CompressedRistretto>,
 	diff_comms: Vec<CompressedRistretto>,
 }
 
@@ -84,36 +82,20 @@ pub fn setup_prover<T: RngCore + CryptoRng>(
 		};
 		input_comms.push(leaf_com);
 
-		let mut leaf_index_comms = vec![];
-		let mut leaf_index_vars = vec![];
-		let mut leaf_index_alloc_scalars = vec![];
-		for b in get_bits(&inputs.index, DEFAULT_TREE_DEPTH)
-			.iter()
-			.take(tree.depth)
-		{
-			let val: Scalar = Scalar::from(*b as u8);
-			let (c, v) =
-				prover.commit(val.clone(), Scalar::random(&mut test_rng));
-			leaf_index_comms.push(c);
-			leaf_index_vars.push(v);
-			leaf_index_alloc_scalars.push(AllocatedScalar {
-				variable: v,
-				assignment: Some(val),
-			});
-		}
+		let (leaf_index_com, leaf_index_var) =
+			prover.commit(inputs.index, Scalar::random(&mut test_rng));
+		let alloc_leaf_index = AllocatedScalar {
+			variable: leaf_index_var,
+			assignment: Some(inputs.index),
+		};
+		input_comms.push(leaf_index_com);
 
-		let mut proof_comms = vec![];
-		let mut proof_vars = vec![];
-		let mut proof_alloc_scalars = vec![];
-		for p in inputs.merkle_proof_vec.iter() {
-			let (c, v) = prover.commit(*p, Scalar::random(&mut test_rng));
-			proof_comms.push(c);
-			proof_vars.push(v);
-			proof_alloc_scalars.push(AllocatedScalar {
-				variable: v,
-				assignment: Some(*p),
-			});
-		}
+		let proof_alloc_scalars = allocate_scalars(
+			&mut prover,
+			tree.depth,
+			Some(&inputs.merkle_proof_vec),
+		)
+		.unwrap();
 
 		let set = inputs.roots.clone();
 		let mut diff_comms = vec![];
@@ -138,7 +120,7 @@ pub fn setup_prover<T: RngCore + CryptoRng>(
 			r: alloc_input_r,
 			nullifier: alloc_input_nullifier,
 			leaf_cm_val: alloc_leaf_val,
-			leaf_index_bits: leaf_index_alloc_scalars,
+			leaf_index: alloc_leaf_index,
 			leaf_proof_nodes: proof_alloc_scalars,
 			diff_vars,
 			chain_id: inputs.chain_id,
@@ -182,8 +164,6 @@ pub fn setup_prover<T: RngCore + CryptoRng>(
 			proof,
 			BridgeTxComms {
 				input_comms,
-				leaf_index_comms,
-				proof_comms,
 				diff_comms,
 			},
 		)
@@ -231,23 +211,14 @@ pub fn setup_verifier<T: RngCore + CryptoRng>(
 		assignment: None,
 	};
 
-	let mut leaf_index_alloc_scalars = vec![];
-	for l in bridge_comms.leaf_index_comms {
-		let v = verifier.commit(l);
-		leaf_index_alloc_scalars.push(AllocatedScalar {
-			variable: v,
-			assignment: None,
-		});
-	}
+	let var_leaf_index = verifier.commit(bridge_comms.input_comms[4]);
+	let leaf_index_alloc_scalar = AllocatedScalar {
+		variable: var_leaf_index,
+		assignment: None,
+	};
 
-	let mut proof_alloc_scalars = vec![];
-	for p in bridge_comms.proof_comms {
-		let v = verifier.commit(p);
-		proof_alloc_scalars.push(AllocatedScalar {
-			variable: v,
-			assignment: None,
-		});
-	}
+	let proof_alloc_scalars =
+		allocate_scalars(&mut verifier, depth, None).unwrap();
 
 	let mut diff_alloc_scalars: Vec<AllocatedScalar> = vec![];
 
@@ -266,7 +237,7 @@ pub fn setup_verifier<T: RngCore + CryptoRng>(
 		r: r_alloc,
 		nullifier: nullifier_alloc,
 		leaf_cm_val: leaf_alloc_scalar,
-		leaf_index_bits: leaf_index_alloc_scalars,
+		leaf_index: leaf_index_alloc_scalar,
 		leaf_proof_nodes: proof_alloc_scalars,
 		diff_vars: diff_alloc_scalars,
 		// public
@@ -50,7 +50,7 @@ fn test_bridged_mt_gadget_verification() {
 			index
 		};
 
-		origin_deposit_tree.update(index, s);
+		origin_deposit_tree.update(index, s).unwrap();
 	}
 
 	let mut origin_merkle_proof_vec = Vec::<Scalar>::new();
@@ -58,25 +58,22 @@ fn test_bridged_mt_gadget_verification() {
 	let k = Scalar::from(7u32);
 	assert_eq!(
 		origin_expected_output,
-		origin_deposit_tree.get(
-			k,
-			origin_deposit_tree.root,
-			&mut origin_merkle_proof
-		)
+		origin_deposit_tree
+			.get(k, origin_deposit_tree.root, &mut origin_merkle_proof)
+			.unwrap()
 	);
 	origin_merkle_proof_vec = origin_merkle_proof.unwrap();
-	assert!(origin_deposit_tree.verify_proof(
-		k,
-		origin_expected_output,
-		&origin_merkle_proof_vec,
-		None
-	));
-	assert!(origin_deposit_tree.verify_proof(
-		k,
-		origin_expected_output,
-		&origin_merkle_proof_vec,
-		Some(&origin_deposit_tree.root)
-	));
+	assert!(origin_deposit_tree
+		.verify_proof(k, origin_expected_output, &origin_merkle_proof_vec, None)
+		.unwrap());
+	assert!(origin_deposit_tree
+		.verify_proof(
+			k,
+			origin_expected_output,
+			&origin_merkle_proof_vec,
+			Some(&origin_deposit_tree.root)
+		)
+		.unwrap());
 
 	// MAKE THE DESTINATION CHAIN TREE
 	let mut destination_deposit_tree = SparseMerkleTreeBuilder::new()
@@ -85,7 +82,9 @@ fn test_bridged_mt_gadget_verification() {
 
 	for i in 1..=10 {
 		let index = Scalar::from(i as u32);
-		destination_deposit_tree.update(index, Scalar::random(&mut test_rng));
+		destination_deposit_tree
+			.update(index, Scalar::random(&mut test_rng))
+			.unwrap();
 	}
 
 	let pc_gens = PedersenGens::default();
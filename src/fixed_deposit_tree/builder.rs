@@ -1,14 +1,18 @@
 use crate::{
-	fixed_deposit_tree::mixer_verif_gadget,
+	fixed_deposit_tree::mixer_committed_index_verif_gadget,
 	poseidon::{
 		allocate_statics_for_prover, builder::Poseidon, sbox::PoseidonSbox,
 		PoseidonBuilder, Poseidon_hash_2,
 	},
 	smt::{
-		builder::{SparseMerkleTreeBuilder, DEFAULT_TREE_DEPTH},
+		builder::{
+			SparseMerkleTreeBuilder, DEFAULT_ROOT_HISTORY_SIZE,
+			DEFAULT_TREE_DEPTH,
+		},
+		error::TreeError,
 		smt::VanillaSparseMerkleTree,
 	},
-	utils::{get_bits, AllocatedScalar, ScalarBytes},
+	utils::{allocate_scalars, AllocatedScalar, ScalarBytes},
 };
 use alloc::vec::Vec;
 use bulletproofs::{
@@ -20,6 +24,9 @@ use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
 use rand_core::OsRng;
 use sp_std::collections::btree_map::BTreeMap;
 
+/// Commitments to r, nullifier, leaf and leaf index, and the nullifier hash
+pub type MixerProofComms = (Vec<CompressedRistretto>, Scalar);
+
 #[derive(Clone)]
 pub struct FixedDepositTree {
 	secrets: BTreeMap<ScalarBytes, (Scalar, Scalar, Scalar)>,
@@ -66,12 +73,40 @@ impl FixedDepositTree {
 		(r, nullifier, nullifier_hash, leaf)
 	}
 
-	pub fn get_secrets(&self, leaf: Scalar) -> (Scalar, Scalar, Scalar) {
-		let (r, nullifier, nullifier_hash) =
-			self.secrets.get(&leaf.to_bytes()).unwrap();
-		(*r, *nullifier, *nullifier_hash)
+	/// See `VanillaSparseMerkleTree::is_known_root`
+	pub fn is_known_root(&self, root: &Scalar) -> bool {
+		self.tree.is_known_root(root)
+	}
+
+	/// See `VanillaSparseMerkleTree::checkpoint`
+	pub fn checkpoint(&mut self, id: u64) -> Result<(), TreeError> {
+		self.tree.checkpoint(id)
+	}
+
+	/// See `VanillaSparseMerkleTree::rollback_to`. Secrets of the deposits
+	/// that were rolled back are kept, so they can be deposited again.
+	pub fn rollback_to(&mut self, id: u64) -> Result<(), TreeError> {
+		self.tree.rollback_to(id)
+	}
+
+	/// See `VanillaSparseMerkleTree::discard_checkpoints_before`
+	pub fn discard_checkpoints_before(&mut self, id: u64) {
+		self.tree.discard_checkpoints_before(id)
 	}
 
+	pub fn get_secrets(
+		&self,
+		leaf: Scalar,
+	) -> Result<(Scalar, Scalar, Scalar), TreeError> {
+		let (r, nullifier, nullifier_hash) = self
+			.secrets
+			.get(&leaf.to_bytes())
+			.ok_or(TreeError::UnknownLeaf)?;
+		Ok((*r, *nullifier, *nullifier_hash))
+	}
+
+	/// Prove the withdrawal of `leaf` under `root`. If the leaf was deposited
+	/// more than once, the first index is used, see `prove_zk_at`.
 	#[cfg(feature = "std")]
 	pub fn prove_zk(
 		&self,
@@ -80,25 +115,31 @@ impl FixedDepositTree {
 		recipient: Scalar,
 		relayer: Scalar,
 		bp_gens: &BulletproofGens,
+		prover: Prover,
+	) -> Result<(R1CSProof, MixerProofComms), TreeError> {
+		let k = self.tree.index_of(leaf, root)?;
+		self.prove_zk_at(root, k, recipient, relayer, bp_gens, prover)
+	}
+
+	/// Prove the withdrawal of the leaf at `index` under `root`
+	#[cfg(feature = "std")]
+	pub fn prove_zk_at(
+		&self,
+		root: Scalar,
+		index: Scalar,
+		recipient: Scalar,
+		relayer: Scalar,
+		bp_gens: &BulletproofGens,
 		mut prover: Prover,
-	) -> (
-		R1CSProof,
-		(
-			Vec<CompressedRistretto>,
-			Scalar,
-			Vec<CompressedRistretto>,
-			Vec<CompressedRistretto>,
-		),
-	) {
+	) -> Result<(R1CSProof, MixerProofComms), TreeError> {
 		let mut rng: OsRng = OsRng::default();
 		let mut merkle_proof_vec = Vec::<Scalar>::new();
 		let mut merkle_proof = Some(merkle_proof_vec);
 
-		let k = self.tree.leaf_indices.get(&leaf.to_bytes()).unwrap();
-		let leaf = self.tree.get(*k, root, &mut merkle_proof);
-		merkle_proof_vec = merkle_proof.unwrap();
+		let leaf = self.tree.get(index, root, &mut merkle_proof)?;
+		merkle_proof_vec = merkle_proof.unwrap_or_default();
 
-		let (r, nullifier, nullifier_hash) = self.get_secrets(leaf);
+		let (r, nullifier, nullifier_hash) = self.get_secrets(leaf)?;
 
 		let mut comms = vec![];
 		let (com_r, var_r) = prover.commit(r, Scalar::random(&mut rng));
@@ -124,63 +165,48 @@ impl FixedDepositTree {
 		};
 		comms.push(com_leaf);
 
-		let mut leaf_index_comms = vec![];
-		let mut leaf_index_alloc_scalars = vec![];
-		for b in get_bits(&k, DEFAULT_TREE_DEPTH)
-			.iter()
-			.take(self.tree.depth)
-		{
-			let val: Scalar = Scalar::from(*b as u8);
-			let (c, v) = prover.commit(val, Scalar::random(&mut rng));
-			leaf_index_comms.push(c);
-			leaf_index_alloc_scalars.push(AllocatedScalar {
-				variable: v,
-				assignment: Some(val),
-			});
-		}
+		let (com_leaf_index, var_leaf_index) =
+			prover.commit(index, Scalar::random(&mut rng));
+		let leaf_index_alloc_scalar = AllocatedScalar {
+			variable: var_leaf_index,
+			assignment: Some(index),
+		};
+		comms.push(com_leaf_index);
 
-		let mut proof_comms = vec![];
-		let mut proof_alloc_scalars = vec![];
-		for p in merkle_proof_vec.iter() {
-			let (c, v) = prover.commit(*p, Scalar::random(&mut rng));
-			proof_comms.push(c);
-			proof_alloc_scalars.push(AllocatedScalar {
-				variable: v,
-				assignment: Some(*p),
-			});
-		}
+		let proof_alloc_scalars = allocate_scalars(
+			&mut prover,
+			self.tree.depth,
+			Some(&merkle_proof_vec),
+		)?;
 
 		let num_statics = 4;
 		let statics = allocate_statics_for_prover(&mut prover, num_statics);
 
-		assert!(mixer_verif_gadget(
+		mixer_committed_index_verif_gadget(
 			&mut prover,
 			&recipient,
 			&relayer,
 			self.tree.depth,
-			&self.tree.root,
+			&root,
 			&nullifier_hash,
 			r_alloc,
 			nullifier_alloc,
 			leaf_alloc_scalar,
-			leaf_index_alloc_scalars,
+			leaf_index_alloc_scalar,
 			proof_alloc_scalars,
 			statics,
-			&self.hash_params
-		)
-		.is_ok());
+			&self.hash_params,
+		)?;
 
-		let proof = prover.prove_with_rng(bp_gens, &mut rng).unwrap();
-		(
-			proof,
-			(comms, nullifier_hash, leaf_index_comms, proof_comms),
-		)
+		let proof = prover.prove_with_rng(bp_gens, &mut rng)?;
+		Ok((proof, (comms, nullifier_hash)))
 	}
 }
 
 pub struct FixedDepositTreeBuilder {
 	depth: Option<usize>,
 	hash_params: Option<Poseidon>,
+	root_history_size: Option<usize>,
 	tree: Option<VanillaSparseMerkleTree>,
 }
 
@@ -193,6 +219,7 @@ impl FixedDepositTreeBuilder {
 		Self {
 			depth: None,
 			hash_params: None,
+			root_history_size: None,
 			tree: None,
 		}
 	}
@@ -207,6 +234,11 @@ impl FixedDepositTreeBuilder {
 		self
 	}
 
+	pub fn root_history_size(mut self, root_history_size: usize) -> Self {
+		self.root_history_size = Some(root_history_size);
+		self
+	}
+
 	pub fn merkle_tree(mut self, tree: VanillaSparseMerkleTree) -> Self {
 		self.tree = Some(tree);
 		self
@@ -218,10 +250,13 @@ impl FixedDepositTreeBuilder {
 			PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build(),
 		);
 		let secrets = BTreeMap::new();
+		let root_history_size =
+			self.root_history_size.unwrap_or(DEFAULT_ROOT_HISTORY_SIZE);
 		let tree = self.tree.unwrap_or(
 			SparseMerkleTreeBuilder::new()
 				.depth(depth)
 				.hash_params(hash_params.clone())
+				.root_history_size(root_history_size)
 				.build(),
 		);
 
@@ -5,7 +5,10 @@ pub mod tests;
 
 use crate::{
 	poseidon::{builder::Poseidon, Poseidon_hash_2_constraints},
-	smt::smt::vanilla_merkle_merkle_tree_verif_gadget,
+	smt::smt::{
+		vanilla_merkle_merkle_tree_committed_index_verif_gadget,
+		vanilla_merkle_merkle_tree_verif_gadget,
+	},
 	utils::{constrain_lc_with_scalar, AllocatedScalar},
 };
 use alloc::vec::Vec;
@@ -24,6 +27,79 @@ pub fn fixed_deposit_tree_verif_gadget<CS: ConstraintSystem>(
 	proof_nodes: Vec<AllocatedScalar>,
 	statics: Vec<AllocatedScalar>,
 	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	deposit_leaf_constraints(
+		cs,
+		nullifier_hash,
+		r,
+		nullifier,
+		leaf_val,
+		&statics,
+		poseidon_params,
+	)?;
+	// if all is successful, constrain gadget by merkle root construction with
+	// merkle proof path
+	vanilla_merkle_merkle_tree_verif_gadget(
+		cs,
+		depth,
+		root,
+		leaf_val,
+		leaf_index_bits,
+		proof_nodes,
+		statics,
+		poseidon_params,
+	)?;
+	Ok(())
+}
+
+/// Same as `fixed_deposit_tree_verif_gadget` with a committed leaf index and
+/// proof nodes that don't need to be committed, see
+/// `vanilla_merkle_merkle_tree_committed_index_verif_gadget`
+pub fn fixed_deposit_tree_committed_index_verif_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	root: &Scalar,
+	nullifier_hash: &Scalar,
+	r: AllocatedScalar,
+	nullifier: AllocatedScalar,
+	leaf_val: AllocatedScalar,
+	leaf_index: AllocatedScalar,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	deposit_leaf_constraints(
+		cs,
+		nullifier_hash,
+		r,
+		nullifier,
+		leaf_val,
+		&statics,
+		poseidon_params,
+	)?;
+	vanilla_merkle_merkle_tree_committed_index_verif_gadget(
+		cs,
+		depth,
+		root,
+		leaf_val,
+		leaf_index,
+		proof_nodes,
+		statics,
+		poseidon_params,
+	)?;
+	Ok(())
+}
+
+/// The leaf is `H(r, nullifier)` and the nullifier hash `H(nullifier,
+/// nullifier)`
+fn deposit_leaf_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	nullifier_hash: &Scalar,
+	r: AllocatedScalar,
+	nullifier: AllocatedScalar,
+	leaf_val: AllocatedScalar,
+	statics: &[AllocatedScalar],
+	poseidon_params: &Poseidon,
 ) -> Result<(), R1CSError> {
 	let statics_lc: Vec<LinearCombination> =
 		statics.iter().map(|s| s.variable.into()).collect();
@@ -46,22 +122,47 @@ pub fn fixed_deposit_tree_verif_gadget<CS: ConstraintSystem>(
 		poseidon_params,
 	)?;
 	constrain_lc_with_scalar::<CS>(cs, computed_nullifier_hash, nullifier_hash);
-	// if all is successful, constrain gadget by merkle root construction with
-	// merkle proof path
-	vanilla_merkle_merkle_tree_verif_gadget(
+	Ok(())
+}
+
+pub fn mixer_verif_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	relayer: &Scalar,
+	recipient: &Scalar,
+	depth: usize,
+	root: &Scalar,
+	nullifier_hash: &Scalar,
+	r: AllocatedScalar,
+	nullifier: AllocatedScalar,
+	leaf_val: AllocatedScalar,
+	leaf_index_bits: Vec<AllocatedScalar>,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	fixed_deposit_tree_verif_gadget(
 		cs,
 		depth,
 		root,
+		nullifier_hash,
+		r,
+		nullifier,
 		leaf_val,
 		leaf_index_bits,
 		proof_nodes,
 		statics,
 		poseidon_params,
 	)?;
+	// hidden signals for relayer and recipient commitments
+	let (_, _, _) = cs.multiply(relayer.clone().into(), relayer.clone().into());
+	let (_, _, _) =
+		cs.multiply(recipient.clone().into(), recipient.clone().into());
 	Ok(())
 }
 
-pub fn mixer_verif_gadget<CS: ConstraintSystem>(
+/// Same as `mixer_verif_gadget` with a committed leaf index, see
+/// `fixed_deposit_tree_committed_index_verif_gadget`
+pub fn mixer_committed_index_verif_gadget<CS: ConstraintSystem>(
 	cs: &mut CS,
 	relayer: &Scalar,
 	recipient: &Scalar,
@@ -71,12 +172,12 @@ pub fn mixer_verif_gadget<CS: ConstraintSystem>(
 	r: AllocatedScalar,
 	nullifier: AllocatedScalar,
 	leaf_val: AllocatedScalar,
-	leaf_index_bits: Vec<AllocatedScalar>,
+	leaf_index: AllocatedScalar,
 	proof_nodes: Vec<AllocatedScalar>,
 	statics: Vec<AllocatedScalar>,
 	poseidon_params: &Poseidon,
 ) -> Result<(), R1CSError> {
-	fixed_deposit_tree_verif_gadget(
+	fixed_deposit_tree_committed_index_verif_gadget(
 		cs,
 		depth,
 		root,
@@ -84,7 +185,7 @@ pub fn mixer_verif_gadget<CS: ConstraintSystem>(
 		r,
 		nullifier,
 		leaf_val,
-		leaf_index_bits,
+		leaf_index,
 		proof_nodes,
 		statics,
 		poseidon_params,
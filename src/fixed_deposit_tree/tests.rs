@@ -1,11 +1,17 @@
 use crate::{
-	fixed_deposit_tree::{fixed_deposit_tree_verif_gadget, mixer_verif_gadget},
+	fixed_deposit_tree::{
+		builder::FixedDepositTreeBuilder, fixed_deposit_tree_verif_gadget,
+		mixer_committed_index_verif_gadget, mixer_verif_gadget,
+	},
 	poseidon::{
 		allocate_statics_for_prover, allocate_statics_for_verifier,
 		sbox::PoseidonSbox, PoseidonBuilder, Poseidon_hash_2,
 	},
-	smt::builder::{SparseMerkleTreeBuilder, DEFAULT_TREE_DEPTH},
-	utils::{get_bits, AllocatedScalar},
+	smt::{
+		builder::{SparseMerkleTreeBuilder, DEFAULT_TREE_DEPTH},
+		TreeError,
+	},
+	utils::{allocate_scalars, get_bits, AllocatedScalar},
 };
 use bulletproofs::{
 	r1cs::{Prover, Verifier},
@@ -41,21 +47,23 @@ fn test_fixed_deposit_tree_verification() {
 		let index = Scalar::from(i as u32);
 		let s = if i == 7 { expected_output } else { index };
 
-		tree.update(index, s);
+		tree.update(index, s).unwrap();
 	}
 
 	let mut merkle_proof_vec = Vec::<Scalar>::new();
 	let mut merkle_proof = Some(merkle_proof_vec);
 	let k = Scalar::from(7u32);
-	assert_eq!(expected_output, tree.get(k, tree.root, &mut merkle_proof));
-	merkle_proof_vec = merkle_proof.unwrap();
-	assert!(tree.verify_proof(k, expected_output, &merkle_proof_vec, None));
-	assert!(tree.verify_proof(
-		k,
+	assert_eq!(
 		expected_output,
-		&merkle_proof_vec,
-		Some(&tree.root)
-	));
+		tree.get(k, tree.root, &mut merkle_proof).unwrap()
+	);
+	merkle_proof_vec = merkle_proof.unwrap();
+	assert!(tree
+		.verify_proof(k, expected_output, &merkle_proof_vec, None)
+		.unwrap());
+	assert!(tree
+		.verify_proof(k, expected_output, &merkle_proof_vec, Some(&tree.root))
+		.unwrap());
 
 	let pc_gens = PedersenGens::default();
 	let bp_gens = BulletproofGens::new(16500, 1);
@@ -240,21 +248,23 @@ fn test_mixer_verif() {
 		let index = Scalar::from(i as u32);
 		let s = if i == 7 { expected_output } else { index };
 
-		tree.update(index, s);
+		tree.update(index, s).unwrap();
 	}
 
 	let mut merkle_proof_vec = Vec::<Scalar>::new();
 	let mut merkle_proof = Some(merkle_proof_vec);
 	let k = Scalar::from(7u32);
-	assert_eq!(expected_output, tree.get(k, tree.root, &mut merkle_proof));
-	merkle_proof_vec = merkle_proof.unwrap();
-	assert!(tree.verify_proof(k, expected_output, &merkle_proof_vec, None));
-	assert!(tree.verify_proof(
-		k,
+	assert_eq!(
 		expected_output,
-		&merkle_proof_vec,
-		Some(&tree.root)
-	));
+		tree.get(k, tree.root, &mut merkle_proof).unwrap()
+	);
+	merkle_proof_vec = merkle_proof.unwrap();
+	assert!(tree
+		.verify_proof(k, expected_output, &merkle_proof_vec, None)
+		.unwrap());
+	assert!(tree
+		.verify_proof(k, expected_output, &merkle_proof_vec, Some(&tree.root))
+		.unwrap());
 
 	let pc_gens = PedersenGens::default();
 	let bp_gens = BulletproofGens::new(16500, 1);
@@ -497,3 +507,111 @@ fn test_mixer_verif() {
 		println!("Verification time is {:?}", end);
 	}
 }
+
+#[test]
+fn test_fixed_deposit_tree_errors() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let mut ftree = FixedDepositTreeBuilder::new().build();
+
+	let leaf = ftree.generate_secrets();
+	ftree.tree.add_leaves(vec![leaf.to_bytes()], None).unwrap();
+	assert!(ftree.get_secrets(leaf).is_ok());
+	assert_eq!(
+		ftree.get_secrets(Scalar::random(&mut test_rng)),
+		Err(TreeError::UnknownLeaf)
+	);
+
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	let mut prover_transcript = Transcript::new(b"zk_membership_proof");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let res = ftree.prove_zk(
+		Scalar::random(&mut test_rng),
+		leaf,
+		Scalar::zero(),
+		Scalar::zero(),
+		&bp_gens,
+		prover,
+	);
+	assert_eq!(res.err(), Some(TreeError::UnknownRoot));
+
+	// Deposits of a block that was reorged out are forgotten
+	ftree.checkpoint(1).unwrap();
+	let root = ftree.tree.root;
+	let reorged = ftree.generate_secrets();
+	ftree
+		.tree
+		.add_leaves(vec![reorged.to_bytes()], None)
+		.unwrap();
+	let reorged_root = ftree.tree.root;
+	ftree.rollback_to(1).unwrap();
+	assert_eq!(ftree.tree.root, root);
+	assert!(!ftree.is_known_root(&reorged_root));
+	assert_eq!(
+		ftree.tree.index_of(reorged, root),
+		Err(TreeError::UnknownLeaf)
+	);
+	assert!(ftree.get_secrets(reorged).is_ok());
+	ftree.discard_checkpoints_before(2);
+	assert_eq!(ftree.rollback_to(1), Err(TreeError::UnknownCheckpoint));
+}
+
+#[test]
+fn test_fixed_deposit_tree_prove_zk() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let mut ftree = FixedDepositTreeBuilder::new().build();
+
+	let leaves: Vec<Scalar> =
+		(0..5).map(|_| ftree.generate_secrets()).collect();
+	let leaf_bytes = leaves.iter().map(|l| l.to_bytes()).collect();
+	ftree.tree.add_leaves(leaf_bytes, None).unwrap();
+	let root = ftree.tree.root;
+	let leaf = leaves[3];
+	let (_, _, nullifier_hash) = ftree.get_secrets(leaf).unwrap();
+	let recipient = Scalar::from(3u32);
+	let relayer = Scalar::from(4u32);
+
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	let mut prover_transcript = Transcript::new(b"zk_membership_proof");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let (proof, (comms, proof_nullifier_hash)) = ftree
+		.prove_zk(root, leaf, recipient, relayer, &bp_gens, prover)
+		.unwrap();
+	// r, nullifier, leaf and leaf index, regardless of the tree depth
+	assert_eq!(comms.len(), 4);
+	assert_eq!(proof_nullifier_hash, nullifier_hash);
+
+	let mut verifier_transcript = Transcript::new(b"zk_membership_proof");
+	let mut verifier = Verifier::new(&mut verifier_transcript);
+	let allocs: Vec<AllocatedScalar> = comms
+		.iter()
+		.map(|c| AllocatedScalar {
+			variable: verifier.commit(*c),
+			assignment: None,
+		})
+		.collect();
+	let proof_alloc_scalars =
+		allocate_scalars(&mut verifier, ftree.tree.depth, None).unwrap();
+	let statics = allocate_statics_for_verifier(&mut verifier, 4, &pc_gens);
+
+	assert!(mixer_committed_index_verif_gadget(
+		&mut verifier,
+		&recipient,
+		&relayer,
+		ftree.tree.depth,
+		&root,
+		&nullifier_hash,
+		allocs[0],
+		allocs[1],
+		allocs[2],
+		allocs[3],
+		proof_alloc_scalars,
+		statics,
+		&ftree.hash_params,
+	)
+	.is_ok());
+	assert!(verifier
+		.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+		.is_ok());
+}
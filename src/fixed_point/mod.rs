@@ -0,0 +1,246 @@
+use crate::utils::{
+	allocate_quantity, lc_bits_gadget, positive_no_gadget, AllocatedQuantity,
+};
+use alloc::string::String;
+use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
+use core::convert::TryFrom;
+use curve25519_dalek::scalar::Scalar;
+
+#[cfg(test)]
+pub mod tests;
+
+/// Raw values, quotients and remainders are range checked to this many bits
+pub const FIXED_POINT_BITS: usize = 64;
+/// `10^MAX_DECIMALS` is the largest power of ten that fits in 64 bits
+pub const MAX_DECIMALS: u32 = 19;
+
+/// A scaled integer, the number `raw / 10^decimals`, with at most
+/// `MAX_DECIMALS` decimals
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FixedPoint {
+	raw: u64,
+	decimals: u32,
+}
+
+impl FixedPoint {
+	/// None if there are more than `MAX_DECIMALS` decimals
+	pub fn new(raw: u64, decimals: u32) -> Option<FixedPoint> {
+		if decimals > MAX_DECIMALS {
+			return None;
+		}
+		Some(FixedPoint { raw, decimals })
+	}
+
+	/// The integer `n` with `decimals`, or None if it does not fit
+	pub fn from_integer(n: u64, decimals: u32) -> Option<FixedPoint> {
+		let raw = n.checked_mul(scale(decimals)?)?;
+		FixedPoint::new(raw, decimals)
+	}
+
+	pub fn raw(&self) -> u64 { self.raw }
+
+	pub fn decimals(&self) -> u32 { self.decimals }
+
+	/// `10^decimals`, the raw value of one
+	pub fn scale(&self) -> u64 { 10u64.pow(self.decimals) }
+
+	/// The integer part, rounded down
+	pub fn floor(&self) -> u64 { self.raw / self.scale() }
+
+	/// `self * other` with the decimals of `self`, rounded down, or None if
+	/// it does not fit in 64 bits
+	pub fn mul(&self, other: &FixedPoint) -> Option<FixedPoint> {
+		let product = self.raw as u128 * other.raw as u128;
+		let (quotient, _) = div_rem(product, other.scale())?;
+		FixedPoint::new(quotient, self.decimals)
+	}
+
+	/// `self / other` with the decimals of `self`, rounded down, or None if
+	/// `other` is zero or the quotient does not fit in 64 bits
+	pub fn div(&self, other: &FixedPoint) -> Option<FixedPoint> {
+		let scaled = self.raw as u128 * other.scale() as u128;
+		let (quotient, _) = div_rem(scaled, other.raw)?;
+		FixedPoint::new(quotient, self.decimals)
+	}
+}
+
+/// A fixed point number allocated in a constraint system. The raw value
+/// is range checked by the gadgets using it.
+#[derive(Clone, Copy, Debug)]
+pub struct AllocatedFixedPoint {
+	pub raw: AllocatedQuantity,
+	pub decimals: u32,
+}
+
+impl AllocatedFixedPoint {
+	pub fn assignment(&self) -> Option<FixedPoint> {
+		self.raw
+			.assignment
+			.and_then(|raw| FixedPoint::new(raw, self.decimals))
+	}
+}
+
+/// `10^decimals`, or None above `MAX_DECIMALS`
+fn scale(decimals: u32) -> Option<u64> {
+	if decimals > MAX_DECIMALS {
+		return None;
+	}
+	Some(10u64.pow(decimals))
+}
+
+/// Same as `scale` for the gadgets, whose decimals are not checked when
+/// allocated
+fn gadget_scale(decimals: u32) -> Result<u64, R1CSError> {
+	scale(decimals).ok_or_else(|| R1CSError::GadgetError {
+		description: String::from(
+			"Fixed point numbers have at most 19 decimals",
+		),
+	})
+}
+
+/// The quotient and the remainder, or None if the divisor is zero or the
+/// quotient does not fit in 64 bits
+fn div_rem(dividend: u128, divisor: u64) -> Option<(u64, u64)> {
+	if divisor == 0 {
+		return None;
+	}
+	let quotient = u64::try_from(dividend / divisor as u128).ok()?;
+	Some((quotient, (dividend % divisor as u128) as u64))
+}
+
+/// Returns the quotient and the remainder of `dividend / divisor`, which
+/// are constrained by `dividend = quotient * divisor + remainder` and range
+/// checked with `remainder < divisor`. The divisor is range checked as
+/// well, so that `quotient * divisor + remainder` is below `2^128` and
+/// can't wrap around the field.
+pub fn div_rem_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	dividend: AllocatedQuantity,
+	divisor: AllocatedQuantity,
+) -> Result<(AllocatedQuantity, AllocatedQuantity), R1CSError> {
+	positive_no_gadget(cs, dividend, FIXED_POINT_BITS)?;
+	positive_no_gadget(cs, divisor, FIXED_POINT_BITS)?;
+	div_rem_constraints(
+		cs,
+		dividend.variable.into(),
+		dividend.assignment.map(u128::from),
+		divisor.variable.into(),
+		divisor.assignment,
+	)
+}
+
+/// `a * b` with the decimals of `a`, rounded down. The product of the raw
+/// values is divided by the scale of `b` and the remainder is discarded.
+pub fn fixed_mul_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	a: AllocatedFixedPoint,
+	b: AllocatedFixedPoint,
+) -> Result<AllocatedFixedPoint, R1CSError> {
+	positive_no_gadget(cs, a.raw, FIXED_POINT_BITS)?;
+	positive_no_gadget(cs, b.raw, FIXED_POINT_BITS)?;
+	let (_, _, product) =
+		cs.multiply(a.raw.variable.into(), b.raw.variable.into());
+	let product_value = a.raw.assignment.and_then(|a_raw| {
+		b.raw.assignment.map(|b_raw| a_raw as u128 * b_raw as u128)
+	});
+	let b_scale = gadget_scale(b.decimals)?;
+	let (quotient, _) = div_rem_constraints(
+		cs,
+		product.into(),
+		product_value,
+		Scalar::from(b_scale).into(),
+		Some(b_scale),
+	)?;
+	Ok(AllocatedFixedPoint {
+		raw: quotient,
+		decimals: a.decimals,
+	})
+}
+
+/// Same as `fixed_mul_gadget` with a public `b`, such as a reward rate or
+/// a fee percentage, which saves the multiplication
+pub fn fixed_mul_constant_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	a: AllocatedFixedPoint,
+	b: &FixedPoint,
+) -> Result<AllocatedFixedPoint, R1CSError> {
+	positive_no_gadget(cs, a.raw, FIXED_POINT_BITS)?;
+	let product = a.raw.variable * Scalar::from(b.raw);
+	let (quotient, _) = div_rem_constraints(
+		cs,
+		product,
+		a.raw.assignment.map(|raw| raw as u128 * b.raw as u128),
+		Scalar::from(b.scale()).into(),
+		Some(b.scale()),
+	)?;
+	Ok(AllocatedFixedPoint {
+		raw: quotient,
+		decimals: a.decimals,
+	})
+}
+
+/// `a / b` with the decimals of `a`, rounded down. The raw value of `a` is
+/// scaled by `b` before it is divided by the raw value of `b`, which can't
+/// be zero.
+pub fn fixed_div_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	a: AllocatedFixedPoint,
+	b: AllocatedFixedPoint,
+) -> Result<AllocatedFixedPoint, R1CSError> {
+	positive_no_gadget(cs, a.raw, FIXED_POINT_BITS)?;
+	positive_no_gadget(cs, b.raw, FIXED_POINT_BITS)?;
+	let b_scale = gadget_scale(b.decimals)?;
+	let (quotient, _) = div_rem_constraints(
+		cs,
+		a.raw.variable * Scalar::from(b_scale),
+		a.raw.assignment.map(|raw| raw as u128 * b_scale as u128),
+		b.raw.variable.into(),
+		b.raw.assignment,
+	)?;
+	Ok(AllocatedFixedPoint {
+		raw: quotient,
+		decimals: a.decimals,
+	})
+}
+
+/// `dividend = quotient * divisor + remainder` with the quotient and the
+/// remainder range checked to 64 bits and `remainder < divisor`. The
+/// divisor must be range checked to 64 bits, it can't be zero as the
+/// remainder would be negative. The dividend must be below `2^128`, such as
+/// the product of two 64 bit values.
+pub(crate) fn div_rem_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	dividend: LinearCombination,
+	dividend_value: Option<u128>,
+	divisor: LinearCombination,
+	divisor_value: Option<u64>,
+) -> Result<(AllocatedQuantity, AllocatedQuantity), R1CSError> {
+	let division = match (dividend_value, divisor_value) {
+		(Some(dividend), Some(divisor)) => {
+			Some(div_rem(dividend, divisor).ok_or_else(|| {
+				R1CSError::GadgetError {
+					description: String::from(
+						"Division by zero or quotient does not fit in 64 bits",
+					),
+				}
+			})?)
+		},
+		_ => None,
+	};
+	let quotient = allocate_quantity(cs, division.map(|(q, _)| q))?;
+	let remainder = allocate_quantity(cs, division.map(|(_, r)| r))?;
+	let (_, _, divided) =
+		cs.multiply(quotient.variable.into(), divisor.clone());
+	cs.constrain(dividend - divided - remainder.variable);
+	positive_no_gadget(cs, quotient, FIXED_POINT_BITS)?;
+	positive_no_gadget(cs, remainder, FIXED_POINT_BITS)?;
+	lc_bits_gadget(
+		cs,
+		divisor - remainder.variable - Scalar::one(),
+		divisor_value.and_then(|divisor| {
+			division.map(|(_, r)| Scalar::from(divisor - 1 - r))
+		}),
+		FIXED_POINT_BITS,
+	)?;
+	Ok((quotient, remainder))
+}
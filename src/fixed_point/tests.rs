@@ -0,0 +1,144 @@
+use super::*;
+use crate::{
+	test_utils::{prove_and_verify, TestCircuit},
+	utils::AllocatedScalar,
+};
+use bulletproofs::{r1cs::Prover, PedersenGens};
+use merlin::Transcript;
+
+#[test]
+fn test_fixed_point() {
+	let amount = FixedPoint::from_integer(12_345, 2).unwrap();
+	assert_eq!(amount, FixedPoint::new(1_234_500, 2).unwrap());
+	assert_eq!(amount.scale(), 100);
+	assert_eq!(amount.floor(), 12_345);
+	assert_eq!(FixedPoint::from_integer(u64::MAX, 1), None);
+	assert_eq!(FixedPoint::new(1, MAX_DECIMALS + 1), None);
+	assert_eq!(FixedPoint::from_integer(1, MAX_DECIMALS + 1), None);
+
+	// 0.37% of 12345 is 45.6765, truncated to 45.67
+	let rate = FixedPoint::new(37, 4).unwrap();
+	assert_eq!(amount.mul(&rate), Some(FixedPoint::new(4_567, 2).unwrap()));
+	let small = FixedPoint::new(12_345, 2).unwrap();
+	assert_eq!(small.mul(&rate), Some(FixedPoint::new(45, 2).unwrap()));
+	assert_eq!(
+		FixedPoint::new(u64::MAX, 0)
+			.unwrap()
+			.mul(&FixedPoint::new(2, 0).unwrap()),
+		None
+	);
+
+	// 12345 / 0.37% is 3336486.486..., truncated to 3336486.48
+	assert_eq!(
+		amount.div(&rate),
+		Some(FixedPoint::new(333_648_648, 2).unwrap())
+	);
+	assert_eq!(amount.div(&FixedPoint::new(0, 4).unwrap()), None);
+	assert_eq!(
+		FixedPoint::new(u64::MAX, 0)
+			.unwrap()
+			.div(&FixedPoint::new(1, 1).unwrap()),
+		None
+	);
+}
+
+#[test]
+fn test_fixed_point_gadgets() {
+	let amount = FixedPoint::new(1_234_500, 2).unwrap();
+	let rate = FixedPoint::new(37, 4).unwrap();
+	let fee = FixedPoint::new(150, 4).unwrap();
+
+	assert!(prove_op(Op::Mul, amount, rate, 4_567).unwrap());
+	assert!(!prove_op(Op::Mul, amount, rate, 4_566).unwrap());
+	assert!(!prove_op(Op::Mul, amount, rate, 4_568).unwrap());
+	assert!(prove_op(Op::MulConstant, amount, rate, 4_567).unwrap());
+	assert!(!prove_op(Op::MulConstant, amount, rate, 4_568).unwrap());
+	// A relayer fee of 1.5%
+	assert!(prove_op(Op::MulConstant, amount, fee, 18_517).unwrap());
+	assert!(!prove_op(Op::MulConstant, amount, fee, 18_518).unwrap());
+
+	assert!(prove_op(Op::Div, amount, rate, 333_648_648).unwrap());
+	assert!(!prove_op(Op::Div, amount, rate, 333_648_649).unwrap());
+
+	assert!(prove_op(Op::DivRem, amount, rate, 1_234_500 / 37).unwrap());
+	assert!(!prove_op(Op::DivRem, amount, rate, 1_234_500 / 37 + 1).unwrap());
+
+	// The prover can't compute these
+	let zero = FixedPoint::new(0, 4).unwrap();
+	assert!(prove_op(Op::Div, amount, zero, 0).is_err());
+	assert!(prove_op(Op::DivRem, amount, zero, 0).is_err());
+	let max = FixedPoint::new(u64::MAX, 0).unwrap();
+	assert!(prove_op(Op::Mul, max, FixedPoint::new(2, 0).unwrap(), 0).is_err());
+
+	// Nor numbers allocated with too many decimals
+	let pc_gens = PedersenGens::default();
+	let mut transcript = Transcript::new(b"FixedPoint");
+	let mut prover = Prover::new(&pc_gens, &mut transcript);
+	let raw = allocate_quantity(&mut prover, Some(1)).unwrap();
+	let a = AllocatedFixedPoint { raw, decimals: 2 };
+	let b = AllocatedFixedPoint {
+		raw,
+		decimals: MAX_DECIMALS + 1,
+	};
+	assert_eq!(b.assignment(), None);
+	assert!(fixed_mul_gadget(&mut prover, a, b).is_err());
+	assert!(fixed_div_gadget(&mut prover, a, b).is_err());
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+	Mul,
+	MulConstant,
+	Div,
+	DivRem,
+}
+
+struct FixedPointOp {
+	op: Op,
+	a: FixedPoint,
+	b: FixedPoint,
+}
+
+impl TestCircuit for FixedPointOp {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		_: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let allocate =
+			|value: &FixedPoint, var: AllocatedScalar| AllocatedFixedPoint {
+				raw: AllocatedQuantity {
+					variable: var.variable,
+					assignment: if witness { Some(value.raw) } else { None },
+				},
+				decimals: value.decimals,
+			};
+		let a = allocate(&self.a, committed[0]);
+		let b = allocate(&self.b, committed[1]);
+		let result = match self.op {
+			Op::Mul => fixed_mul_gadget(cs, a, b)?.raw,
+			Op::MulConstant => fixed_mul_constant_gadget(cs, a, &self.b)?.raw,
+			Op::Div => fixed_div_gadget(cs, a, b)?.raw,
+			Op::DivRem => div_rem_gadget(cs, a.raw, b.raw)?.0,
+		};
+		cs.constrain(result.variable - committed[2].variable);
+		Ok(())
+	}
+}
+
+/// Proves that the raw value of `a op b` is the committed `expected`
+fn prove_op(
+	op: Op,
+	a: FixedPoint,
+	b: FixedPoint,
+	expected: u64,
+) -> Result<bool, R1CSError> {
+	let values = [
+		Scalar::from(a.raw),
+		Scalar::from(b.raw),
+		Scalar::from(expected),
+	];
+	prove_and_verify(&FixedPointOp { op, a, b }, &values, &[])
+}
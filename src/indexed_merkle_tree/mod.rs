@@ -0,0 +1,512 @@
+use crate::{
+	poseidon::{
+		builder::Poseidon, Poseidon_hash_2_constraints, Poseidon_hash_4,
+		Poseidon_hash_4_constraints,
+	},
+	smt::{
+		error::TreeError,
+		smt::{merkle_path_root_constraints, VanillaSparseMerkleTree},
+	},
+	utils::{
+		allocate_scalars, lc_bits_gadget, scalar_bits_gadget, AllocatedScalar,
+		ScalarBytes,
+	},
+};
+use alloc::{string::String, vec::Vec};
+use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
+use core::fmt;
+use curve25519_dalek::scalar::Scalar;
+use sp_std::collections::btree_map::BTreeMap;
+
+#[cfg(test)]
+pub mod tests;
+
+/// Errors returned by `IndexedMerkleTree`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IndexedTreeError {
+	/// The value is zero or does not fit in 252 bits
+	InvalidValue,
+	/// The value is already in the tree
+	DuplicateValue,
+	/// The underlying tree failed, e.g. it is full
+	Tree(TreeError),
+}
+
+impl fmt::Display for IndexedTreeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			IndexedTreeError::InvalidValue => write!(f, "Invalid value"),
+			IndexedTreeError::DuplicateValue => write!(f, "Duplicate value"),
+			IndexedTreeError::Tree(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for IndexedTreeError {}
+
+impl From<TreeError> for IndexedTreeError {
+	fn from(e: TreeError) -> IndexedTreeError { IndexedTreeError::Tree(e) }
+}
+
+/// Values are compared in circuit by decomposing differences in this many
+/// bits, so they have to be below 2^252
+const VALUE_BITS: usize = 252;
+
+/// A leaf of the linked list, sorted by value. A `next_value` of zero marks
+/// the end of the list.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IndexedLeaf {
+	pub value: Scalar,
+	pub next_value: Scalar,
+	pub next_index: u64,
+}
+
+impl IndexedLeaf {
+	/// `H(value, next_value, next_index, 0)`
+	pub fn hash(&self, hash_params: &Poseidon) -> Scalar {
+		Poseidon_hash_4(
+			[
+				self.value,
+				self.next_value,
+				Scalar::from(self.next_index),
+				Scalar::zero(),
+			],
+			hash_params,
+		)
+	}
+}
+
+/// The leaf with the largest value below some value, which proves that the
+/// value is not in the tree
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LowLeafProof {
+	pub index: u64,
+	pub leaf: IndexedLeaf,
+	pub path: Vec<Scalar>,
+}
+
+/// Witness of the insertion of `value`. `low.path` is the path of the low
+/// leaf under `old_root`, `new_path` is the path of `new_index` once the
+/// low leaf points to the new one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexedInsertion {
+	pub value: Scalar,
+	pub low: LowLeafProof,
+	pub new_index: u64,
+	pub new_path: Vec<Scalar>,
+	pub old_root: Scalar,
+	pub new_root: Scalar,
+}
+
+/// Merkle tree whose leaves form a linked list sorted by value, e.g. a set of
+/// spent nullifiers. Leaves are appended in insertion order and the leaf
+/// preceding a new value is updated to point to it, so that a value is
+/// proven not to be in the set by the leaf surrounding it. Leaf 0 holds the
+/// value zero, so values must be in `(0, 2^252)`.
+#[derive(Clone)]
+pub struct IndexedMerkleTree {
+	tree: VanillaSparseMerkleTree,
+	hash_params: Poseidon,
+	leaves: Vec<IndexedLeaf>,
+	/// Index of each value, keyed by its big endian bytes so they are sorted
+	sorted: BTreeMap<ScalarBytes, u64>,
+}
+
+impl IndexedMerkleTree {
+	pub fn new(hash_params: Poseidon, depth: usize) -> IndexedMerkleTree {
+		let mut tree = VanillaSparseMerkleTree::new(hash_params.clone(), depth);
+		let first = IndexedLeaf {
+			value: Scalar::zero(),
+			next_value: Scalar::zero(),
+			next_index: 0,
+		};
+		tree.update(Scalar::zero(), first.hash(&hash_params))
+			.expect("Index 0 is in any tree");
+		let mut sorted = BTreeMap::new();
+		sorted.insert(sort_key(&Scalar::zero()), 0);
+		IndexedMerkleTree {
+			tree,
+			hash_params,
+			leaves: vec![first],
+			sorted,
+		}
+	}
+
+	pub fn depth(&self) -> usize { self.tree.depth }
+
+	pub fn root(&self) -> Scalar { self.tree.root }
+
+	/// Number of leaves, including the zero leaf
+	pub fn len(&self) -> u64 { self.leaves.len() as u64 }
+
+	pub fn is_empty(&self) -> bool { self.leaves.len() == 1 }
+
+	pub fn leaf(&self, index: u64) -> Option<IndexedLeaf> {
+		self.leaves.get(index as usize).copied()
+	}
+
+	pub fn contains(&self, value: Scalar) -> bool {
+		value != Scalar::zero() && self.sorted.contains_key(&sort_key(&value))
+	}
+
+	/// The low leaf of `value` under the current root. Errors with
+	/// `DuplicateValue` if `value` is in the tree.
+	pub fn low_leaf(
+		&self,
+		value: Scalar,
+	) -> Result<LowLeafProof, IndexedTreeError> {
+		check_value(&value)?;
+		let key = sort_key(&value);
+		if self.sorted.contains_key(&key) {
+			return Err(IndexedTreeError::DuplicateValue);
+		}
+		let index = match self.sorted.range(..key).next_back() {
+			Some((_, index)) => *index,
+			None => 0,
+		};
+		Ok(LowLeafProof {
+			index,
+			leaf: self.leaves[index as usize],
+			path: self.tree.path_at(Scalar::from(index))?,
+		})
+	}
+
+	/// Verify that `value` is not in the tree with `root`
+	pub fn verify_non_membership(
+		&self,
+		value: Scalar,
+		proof: &LowLeafProof,
+		root: &Scalar,
+	) -> Result<bool, IndexedTreeError> {
+		check_value(&value)?;
+		let leaf = &proof.leaf;
+		let above_low = sort_key(&leaf.value) < sort_key(&value);
+		let below_next = leaf.next_value == Scalar::zero()
+			|| sort_key(&value) < sort_key(&leaf.next_value);
+		if !above_low || !below_next {
+			return Ok(false);
+		}
+		Ok(self.tree.verify_proof(
+			Scalar::from(proof.index),
+			leaf.hash(&self.hash_params),
+			&proof.path,
+			Some(root),
+		)?)
+	}
+
+	/// Insert `value` and return the witness of the insertion
+	pub fn insert(
+		&mut self,
+		value: Scalar,
+	) -> Result<IndexedInsertion, IndexedTreeError> {
+		let low = self.low_leaf(value)?;
+		let new_index = self.len();
+		if self.depth() < 64 && new_index >= 1 << self.depth() {
+			return Err(TreeError::IndexOutOfRange.into());
+		}
+		let old_root = self.root();
+
+		let new_leaf = IndexedLeaf {
+			value,
+			next_value: low.leaf.next_value,
+			next_index: low.leaf.next_index,
+		};
+		let updated_low = IndexedLeaf {
+			next_value: value,
+			next_index: new_index,
+			..low.leaf
+		};
+		self.tree.update(
+			Scalar::from(low.index),
+			updated_low.hash(&self.hash_params),
+		)?;
+		let new_path = self.tree.path_at(Scalar::from(new_index))?;
+		self.tree.update(
+			Scalar::from(new_index),
+			new_leaf.hash(&self.hash_params),
+		)?;
+		self.leaves[low.index as usize] = updated_low;
+		self.leaves.push(new_leaf);
+		self.sorted.insert(sort_key(&value), new_index);
+
+		Ok(IndexedInsertion {
+			value,
+			low,
+			new_index,
+			new_path,
+			old_root,
+			new_root: self.root(),
+		})
+	}
+}
+
+/// Big endian bytes, which sort like the values
+fn sort_key(value: &Scalar) -> ScalarBytes {
+	let mut key = value.to_bytes();
+	key.reverse();
+	key
+}
+
+fn check_value(value: &Scalar) -> Result<(), IndexedTreeError> {
+	if *value == Scalar::zero() || value.as_bytes()[31] >> 4 != 0 {
+		return Err(IndexedTreeError::InvalidValue);
+	}
+	Ok(())
+}
+
+/// The low leaf and its position allocated in a constraint system
+#[derive(Clone, Debug)]
+pub struct AllocatedLowLeaf {
+	pub value: AllocatedScalar,
+	pub next_value: AllocatedScalar,
+	pub next_index: AllocatedScalar,
+	pub index: AllocatedScalar,
+	pub path: Vec<AllocatedScalar>,
+}
+
+impl AllocatedLowLeaf {
+	/// Allocate the low leaf of a tree of `depth` with `allocate_scalars`,
+	/// the prover passes the proof and the verifier None
+	pub fn allocate<CS: ConstraintSystem>(
+		cs: &mut CS,
+		depth: usize,
+		proof: Option<&LowLeafProof>,
+	) -> Result<AllocatedLowLeaf, R1CSError> {
+		let assignments: Option<Vec<Scalar>> = proof.map(|p| {
+			let mut values = vec![
+				p.leaf.value,
+				p.leaf.next_value,
+				Scalar::from(p.leaf.next_index),
+				Scalar::from(p.index),
+			];
+			values.extend_from_slice(&p.path);
+			values
+		});
+		let mut vars = allocate_scalars(cs, 4 + depth, assignments.as_deref())?;
+		let path = vars.split_off(4);
+		Ok(AllocatedLowLeaf {
+			value: vars[0],
+			next_value: vars[1],
+			next_index: vars[2],
+			index: vars[3],
+			path,
+		})
+	}
+}
+
+/// Proves that the committed `value` is not in the indexed tree with `root`.
+/// 4 statics are needed for `statics_2` and 2 for `statics_4`.
+pub fn indexed_non_membership_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	root: LinearCombination,
+	value: AllocatedScalar,
+	low: AllocatedLowLeaf,
+	statics_2: Vec<AllocatedScalar>,
+	statics_4: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let statics_2: Vec<LinearCombination> =
+		statics_2.iter().map(|s| s.variable.into()).collect();
+	let statics_4: Vec<LinearCombination> =
+		statics_4.iter().map(|s| s.variable.into()).collect();
+	let low_hash =
+		low_leaf_constraints(cs, value, &low, &statics_4, poseidon_params)?;
+	let bits = scalar_bits_gadget(cs, low.index, depth)?;
+	let computed_root = merkle_path_root_constraints(
+		cs,
+		low_hash,
+		&bits,
+		&low.path,
+		&statics_2,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_root - root);
+	Ok(())
+}
+
+/// Proves that the committed `value` was not in the indexed tree with
+/// `old_root` and that inserting it at the public `new_index` gives
+/// `new_root`. The low leaf is updated to point to the new leaf, which takes
+/// over the old successor of the low leaf. Takes `4 * depth + 3` hashes.
+pub fn indexed_insert_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	old_root: LinearCombination,
+	new_root: LinearCombination,
+	new_index: u64,
+	value: AllocatedScalar,
+	low: AllocatedLowLeaf,
+	new_path: Vec<AllocatedScalar>,
+	statics_2: Vec<AllocatedScalar>,
+	statics_4: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	if depth < 64 && new_index >= 1 << depth {
+		return Err(R1CSError::GadgetError {
+			description: String::from("New index does not fit in the tree"),
+		});
+	}
+	let statics_2: Vec<LinearCombination> =
+		statics_2.iter().map(|s| s.variable.into()).collect();
+	let statics_4: Vec<LinearCombination> =
+		statics_4.iter().map(|s| s.variable.into()).collect();
+
+	let low_hash =
+		low_leaf_constraints(cs, value, &low, &statics_4, poseidon_params)?;
+	let bits = scalar_bits_gadget(cs, low.index, depth)?;
+	let computed_old_root = merkle_path_root_constraints(
+		cs,
+		low_hash,
+		&bits,
+		&low.path,
+		&statics_2,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_old_root - old_root);
+
+	// The low leaf now points to the new leaf
+	let updated_low = Poseidon_hash_4_constraints::<CS>(
+		cs,
+		[
+			low.value.variable.into(),
+			value.variable.into(),
+			Scalar::from(new_index).into(),
+			LinearCombination::default(),
+		],
+		statics_4.clone(),
+		poseidon_params,
+	)?;
+	let mid_root = merkle_path_root_constraints(
+		cs,
+		updated_low,
+		&bits,
+		&low.path,
+		&statics_2,
+		poseidon_params,
+	)?;
+
+	// The new leaf is appended to an empty slot
+	let empty_root = public_index_root_constraints(
+		cs,
+		LinearCombination::default(),
+		new_index,
+		&new_path,
+		&statics_2,
+		poseidon_params,
+	)?;
+	cs.constrain(empty_root - mid_root);
+	let new_leaf = Poseidon_hash_4_constraints::<CS>(
+		cs,
+		[
+			value.variable.into(),
+			low.next_value.variable.into(),
+			low.next_index.variable.into(),
+			LinearCombination::default(),
+		],
+		statics_4,
+		poseidon_params,
+	)?;
+	let computed_new_root = public_index_root_constraints(
+		cs,
+		new_leaf,
+		new_index,
+		&new_path,
+		&statics_2,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_new_root - new_root);
+
+	Ok(())
+}
+
+/// Checks that `low` surrounds `value`, which must be below 2^252, and
+/// returns the hash of `low`
+fn low_leaf_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	value: AllocatedScalar,
+	low: &AllocatedLowLeaf,
+	statics_4: &[LinearCombination],
+	poseidon_params: &Poseidon,
+) -> Result<LinearCombination, R1CSError> {
+	let value_lc: LinearCombination = value.variable.into();
+	let low_lc: LinearCombination = low.value.variable.into();
+	let next_lc: LinearCombination = low.next_value.variable.into();
+	lc_bits_gadget(cs, value_lc.clone(), value.assignment, VALUE_BITS)?;
+
+	// low < value
+	lc_bits_gadget(
+		cs,
+		value_lc.clone() - low_lc.clone() - Scalar::one(),
+		value
+			.assignment
+			.and_then(|v| low.value.assignment.map(|l| v - l - Scalar::one())),
+		VALUE_BITS,
+	)?;
+
+	// value < next, unless next is zero. `gap` is `next - value - 1`, or
+	// zero at the end of the list.
+	let gap_assignment = value.assignment.and_then(|v| {
+		low.next_value.assignment.map(|n| {
+			if n == Scalar::zero() {
+				Scalar::zero()
+			} else {
+				n - v - Scalar::one()
+			}
+		})
+	});
+	let gap = allocate_scalars(
+		cs,
+		1,
+		gap_assignment.as_ref().map(core::slice::from_ref),
+	)?[0]
+		.variable;
+	let (_, _, o) = cs.multiply(
+		next_lc.clone(),
+		next_lc.clone() - value_lc - Scalar::one() - gap,
+	);
+	cs.constrain(o.into());
+	lc_bits_gadget(cs, gap.into(), gap_assignment, VALUE_BITS)?;
+
+	Poseidon_hash_4_constraints::<CS>(
+		cs,
+		[
+			low_lc,
+			next_lc,
+			low.next_index.variable.into(),
+			LinearCombination::default(),
+		],
+		statics_4.to_vec(),
+		poseidon_params,
+	)
+}
+
+/// Hashes `leaf` up along `proof_nodes` to the root for an index known to
+/// the verifier, so the nodes are put in order without constraints
+fn public_index_root_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	leaf: LinearCombination,
+	index: u64,
+	proof_nodes: &[AllocatedScalar],
+	statics: &[LinearCombination],
+	poseidon_params: &Poseidon,
+) -> Result<LinearCombination, R1CSError> {
+	let mut prev_hash = leaf;
+	for (height, node) in proof_nodes.iter().enumerate() {
+		let node: LinearCombination = node.variable.into();
+		let (left, right) = if height < 64 && (index >> height) & 1 == 1 {
+			(node, prev_hash)
+		} else {
+			(prev_hash, node)
+		};
+		prev_hash = Poseidon_hash_2_constraints::<CS>(
+			cs,
+			left,
+			right,
+			statics.to_vec(),
+			poseidon_params,
+		)?;
+	}
+	Ok(prev_hash)
+}
@@ -0,0 +1,214 @@
+use super::*;
+use crate::{
+	poseidon::{sbox::PoseidonSbox, PoseidonBuilder},
+	smt::builder::SparseMerkleTreeBuilder,
+	test_utils::{prove_and_verify, TestCircuit},
+};
+
+#[test]
+fn test_indexed_merkle_tree() {
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let depth = 8;
+	let mut tree = IndexedMerkleTree::new(p_params.clone(), depth);
+	assert!(tree.is_empty());
+	let s = |v: u32| Scalar::from(v);
+
+	for v in [30, 10, 20, 50].iter() {
+		let insertion = tree.insert(s(*v)).unwrap();
+		assert_eq!(insertion.new_root, tree.root());
+	}
+	assert_eq!(tree.len(), 5);
+	assert!(tree.contains(s(20)));
+	assert!(!tree.contains(s(25)));
+	assert!(!tree.contains(Scalar::zero()));
+
+	// Following the links from the zero leaf gives the values in order
+	let mut sorted = vec![];
+	let mut leaf = tree.leaf(0).unwrap();
+	while leaf.next_value != Scalar::zero() {
+		leaf = tree.leaf(leaf.next_index).unwrap();
+		sorted.push(leaf.value);
+	}
+	assert_eq!(sorted, vec![s(10), s(20), s(30), s(50)]);
+
+	// The leaves are stored in insertion order in a plain sparse tree
+	let mut plain = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.depth(depth)
+		.build();
+	for i in 0..tree.len() {
+		let leaf = tree.leaf(i).unwrap();
+		plain.update(s(i as u32), leaf.hash(&p_params)).unwrap();
+	}
+	assert_eq!(plain.root, tree.root());
+
+	assert_eq!(tree.insert(s(20)), Err(IndexedTreeError::DuplicateValue));
+	assert_eq!(
+		tree.insert(Scalar::zero()),
+		Err(IndexedTreeError::InvalidValue)
+	);
+	let mut too_big = [0u8; 32];
+	too_big[31] = 0x10;
+	assert_eq!(
+		tree.insert(Scalar::from_bits(too_big)),
+		Err(IndexedTreeError::InvalidValue)
+	);
+
+	let root = tree.root();
+	let proof = tree.low_leaf(s(25)).unwrap();
+	assert_eq!(proof.leaf.value, s(20));
+	assert_eq!(proof.leaf.next_value, s(30));
+	assert!(tree.verify_non_membership(s(25), &proof, &root).unwrap());
+	assert!(!tree.verify_non_membership(s(20), &proof, &root).unwrap());
+	assert!(!tree.verify_non_membership(s(30), &proof, &root).unwrap());
+	let end = tree.low_leaf(s(60)).unwrap();
+	assert_eq!(end.leaf.value, s(50));
+	assert!(tree.verify_non_membership(s(60), &end, &root).unwrap());
+	tree.insert(s(25)).unwrap();
+	assert!(!tree
+		.verify_non_membership(s(25), &proof, &tree.root())
+		.unwrap());
+
+	// A full tree is left untouched
+	let mut small = IndexedMerkleTree::new(p_params, 2);
+	for v in 1..=3 {
+		small.insert(s(v)).unwrap();
+	}
+	let root = small.root();
+	assert_eq!(
+		small.insert(s(4)),
+		Err(IndexedTreeError::Tree(TreeError::IndexOutOfRange))
+	);
+	assert_eq!(small.root(), root);
+	assert!(!small.contains(s(4)));
+}
+
+#[test]
+fn test_indexed_merkle_tree_gadgets() {
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = IndexedMerkleTree::new(p_params.clone(), 8);
+	let s = |v: u32| Scalar::from(v);
+	for v in [30, 10, 20, 50].iter() {
+		tree.insert(s(*v)).unwrap();
+	}
+
+	let root = tree.root();
+	let proof = tree.low_leaf(s(25)).unwrap();
+	assert!(prove_non_membership(&tree, root, s(25), &proof, &p_params));
+	assert!(!prove_non_membership(&tree, root, s(20), &proof, &p_params));
+	assert!(!prove_non_membership(&tree, root, s(30), &proof, &p_params));
+	let end = tree.low_leaf(s(60)).unwrap();
+	assert!(prove_non_membership(&tree, root, s(60), &end, &p_params));
+
+	let insertion = tree.insert(s(40)).unwrap();
+	assert!(prove_insertion(&tree, &insertion, &p_params));
+	let insertion = tree.insert(s(70)).unwrap();
+	assert!(prove_insertion(&tree, &insertion, &p_params));
+
+	// The new leaf has to take over the successor of the low leaf
+	let mut forged = tree.clone().insert(s(5)).unwrap();
+	forged.low.leaf.next_value = s(30);
+	assert!(!prove_insertion(&tree, &forged, &p_params));
+	let mut forged = tree.clone().insert(s(5)).unwrap();
+	forged.new_root = root;
+	assert!(!prove_insertion(&tree, &forged, &p_params));
+}
+
+struct NonMembership<'a> {
+	depth: usize,
+	root: Scalar,
+	proof: &'a LowLeafProof,
+	p_params: &'a Poseidon,
+}
+
+impl<'a> TestCircuit for NonMembership<'a> {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		mut statics: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let proof = if witness { Some(self.proof) } else { None };
+		let low = AllocatedLowLeaf::allocate(cs, self.depth, proof)?;
+		let statics_4 = statics.remove(1);
+		indexed_non_membership_gadget(
+			cs,
+			self.depth,
+			self.root.into(),
+			committed[0],
+			low,
+			statics.remove(0),
+			statics_4,
+			self.p_params,
+		)
+	}
+}
+
+fn prove_non_membership(
+	tree: &IndexedMerkleTree,
+	root: Scalar,
+	value: Scalar,
+	proof: &LowLeafProof,
+	p_params: &Poseidon,
+) -> bool {
+	let circuit = NonMembership {
+		depth: tree.depth(),
+		root,
+		proof,
+		p_params,
+	};
+	prove_and_verify(&circuit, &[value], &[4, 2]).unwrap()
+}
+
+struct Insertion<'a> {
+	depth: usize,
+	insertion: &'a IndexedInsertion,
+	p_params: &'a Poseidon,
+}
+
+impl<'a> TestCircuit for Insertion<'a> {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		mut statics: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let insertion = self.insertion;
+		let (low, new_path) = if witness {
+			(Some(&insertion.low), Some(&insertion.new_path[..]))
+		} else {
+			(None, None)
+		};
+		let low = AllocatedLowLeaf::allocate(cs, self.depth, low)?;
+		let new_path = allocate_scalars(cs, self.depth, new_path)?;
+		let statics_4 = statics.remove(1);
+		indexed_insert_gadget(
+			cs,
+			self.depth,
+			insertion.old_root.into(),
+			insertion.new_root.into(),
+			insertion.new_index,
+			committed[0],
+			low,
+			new_path,
+			statics.remove(0),
+			statics_4,
+			self.p_params,
+		)
+	}
+}
+
+fn prove_insertion(
+	tree: &IndexedMerkleTree,
+	insertion: &IndexedInsertion,
+	p_params: &Poseidon,
+) -> bool {
+	let circuit = Insertion {
+		depth: tree.depth(),
+		insertion,
+		p_params,
+	};
+	prove_and_verify(&circuit, &[insertion.value], &[4, 2]).unwrap()
+}
@@ -5,8 +5,14 @@ extern crate alloc;
 
 pub mod crypto_constants;
 pub mod fixed_deposit_tree;
+pub mod fixed_point;
+pub mod indexed_merkle_tree;
+pub mod merkle_sum_tree;
+pub mod mmr;
 pub mod poseidon;
 pub mod smt;
+#[cfg(test)]
+mod test_utils;
 pub mod time_based_rewarding;
 pub mod transaction;
 pub mod utils;
@@ -0,0 +1,365 @@
+use crate::{
+	poseidon::{
+		builder::Poseidon, Poseidon_hash_2, Poseidon_hash_2_constraints,
+		Poseidon_hash_4, Poseidon_hash_4_constraints,
+	},
+	smt::smt::conditional_swap_constraints,
+	utils::{
+		allocate_scalars, constrain_lc_with_scalar, positive_no_gadget,
+		scalar_bits_gadget, AllocatedQuantity, AllocatedScalar,
+	},
+};
+use alloc::{string::String, vec::Vec};
+use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
+use core::fmt;
+use curve25519_dalek::scalar::Scalar;
+
+#[cfg(test)]
+pub mod tests;
+
+/// Balances and all the partial sums are range checked to this many bits
+pub const SUM_BITS: usize = 64;
+
+/// Errors returned by `MerkleSumTree`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SumTreeError {
+	/// The index is not an account, or the tree is full
+	IndexOutOfRange,
+	/// The sum of the balances does not fit in 64 bits
+	SumOverflow,
+}
+
+impl fmt::Display for SumTreeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SumTreeError::IndexOutOfRange => write!(f, "Index out of range"),
+			SumTreeError::SumOverflow => write!(f, "Sum overflow"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SumTreeError {}
+
+/// A node of a merkle sum tree, the hash of the subtree and the sum of the
+/// balances under it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SumNode {
+	pub hash: Scalar,
+	pub sum: u64,
+}
+
+impl SumNode {
+	/// The empty leaf
+	pub fn empty() -> SumNode {
+		SumNode {
+			hash: Scalar::zero(),
+			sum: 0,
+		}
+	}
+
+	/// Leaf of the account `id` holding `balance`, hashed as `H(id, balance)`
+	pub fn leaf(id: Scalar, balance: u64, hash_params: &Poseidon) -> SumNode {
+		SumNode {
+			hash: Poseidon_hash_2(id, Scalar::from(balance), hash_params),
+			sum: balance,
+		}
+	}
+
+	/// Parent of `left` and `right`, or None if the sum overflows. The hash
+	/// is `H(left.hash, left.sum, right.hash, right.sum)`. Hashing only the
+	/// total `left.sum + right.sum` would not bind how it is split between
+	/// the children, so the custodian could give two siblings inconsistent
+	/// sums for each other and shift liabilities out of the total.
+	pub fn parent(
+		left: &SumNode,
+		right: &SumNode,
+		hash_params: &Poseidon,
+	) -> Option<SumNode> {
+		let sum = left.sum.checked_add(right.sum)?;
+		let hash = Poseidon_hash_4(
+			[
+				left.hash,
+				Scalar::from(left.sum),
+				right.hash,
+				Scalar::from(right.sum),
+			],
+			hash_params,
+		);
+		Some(SumNode { hash, sum })
+	}
+}
+
+/// Inclusion proof of the leaf at `index`, with the sibling nodes from the
+/// leaf to the root
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SumProof {
+	pub index: u64,
+	pub path: Vec<SumNode>,
+}
+
+/// Merkle tree of account balances where every node also commits to the sum
+/// of the balances under it, so that the root sum is the total of the
+/// liabilities, e.g. for a proof of solvency. Leaves are appended from the
+/// left and the remaining ones are empty, with a zero hash and sum.
+#[derive(Clone)]
+pub struct MerkleSumTree {
+	hash_params: Poseidon,
+	depth: usize,
+	/// `levels[h][i]` is the `i`-th node at height `h` of the non-empty part
+	/// of the tree
+	levels: Vec<Vec<SumNode>>,
+	/// The root of an empty subtree of each height
+	empty: Vec<SumNode>,
+	/// The id of each account
+	ids: Vec<Scalar>,
+}
+
+impl MerkleSumTree {
+	pub fn new(hash_params: Poseidon, depth: usize) -> MerkleSumTree {
+		assert!(depth < 64, "Depth must be below 64");
+		let mut empty = vec![SumNode::empty()];
+		for h in 0..depth {
+			let node = SumNode::parent(&empty[h], &empty[h], &hash_params)
+				.expect("Empty sums are zero");
+			empty.push(node);
+		}
+		MerkleSumTree {
+			hash_params,
+			depth,
+			levels: vec![Vec::new(); depth + 1],
+			empty,
+			ids: Vec::new(),
+		}
+	}
+
+	pub fn depth(&self) -> usize { self.depth }
+
+	pub fn root(&self) -> SumNode { self.node(self.depth, 0) }
+
+	/// The sum of all the balances
+	pub fn total(&self) -> u64 { self.root().sum }
+
+	/// Number of accounts
+	pub fn len(&self) -> u64 { self.ids.len() as u64 }
+
+	pub fn is_empty(&self) -> bool { self.ids.is_empty() }
+
+	/// The id and balance of the account at `index`
+	pub fn account(&self, index: u64) -> Option<(Scalar, u64)> {
+		let id = self.ids.get(index as usize)?;
+		Some((*id, self.levels[0][index as usize].sum))
+	}
+
+	/// Append the account `id` holding `balance` and return its index.
+	/// Errors with `IndexOutOfRange` if the tree is full and `SumOverflow`
+	/// if the total does not fit in 64 bits, leaving the tree unchanged.
+	pub fn insert(
+		&mut self,
+		id: Scalar,
+		balance: u64,
+	) -> Result<u64, SumTreeError> {
+		let index = self.len();
+		if index >> self.depth != 0 {
+			return Err(SumTreeError::IndexOutOfRange);
+		}
+		self.total()
+			.checked_add(balance)
+			.ok_or(SumTreeError::SumOverflow)?;
+
+		self.levels[0].push(SumNode::leaf(id, balance, &self.hash_params));
+		self.ids.push(id);
+		for h in 0..self.depth {
+			let i = index >> (h + 1);
+			let parent = SumNode::parent(
+				&self.node(h, 2 * i),
+				&self.node(h, 2 * i + 1),
+				&self.hash_params,
+			)
+			.expect("Sums are bounded by the total");
+			let level = &mut self.levels[h + 1];
+			if level.len() as u64 == i {
+				level.push(parent);
+			} else {
+				level[i as usize] = parent;
+			}
+		}
+		Ok(index)
+	}
+
+	/// Inclusion proof of the account at `index` under the current root
+	pub fn prove(&self, index: u64) -> Result<SumProof, SumTreeError> {
+		if index >= self.len() {
+			return Err(SumTreeError::IndexOutOfRange);
+		}
+		let path = (0..self.depth)
+			.map(|h| self.node(h, (index >> h) ^ 1))
+			.collect();
+		Ok(SumProof { index, path })
+	}
+
+	fn node(&self, height: usize, index: u64) -> SumNode {
+		self.levels[height]
+			.get(index as usize)
+			.copied()
+			.unwrap_or(self.empty[height])
+	}
+}
+
+/// Verify that the account `id` holds `balance` in the sum tree with `root`
+pub fn verify_sum_proof(
+	root: &SumNode,
+	id: Scalar,
+	balance: u64,
+	proof: &SumProof,
+	hash_params: &Poseidon,
+) -> bool {
+	let mut node = SumNode::leaf(id, balance, hash_params);
+	for (height, sibling) in proof.path.iter().enumerate() {
+		let parent = if height < 64 && (proof.index >> height) & 1 == 1 {
+			SumNode::parent(sibling, &node, hash_params)
+		} else {
+			SumNode::parent(&node, sibling, hash_params)
+		};
+		node = match parent {
+			Some(parent) => parent,
+			None => return false,
+		};
+	}
+	node == *root
+}
+
+/// A node of a sum proof allocated in a constraint system
+#[derive(Clone, Copy, Debug)]
+pub struct AllocatedSumNode {
+	pub hash: AllocatedScalar,
+	pub sum: AllocatedQuantity,
+}
+
+impl AllocatedSumNode {
+	/// Allocate the `depth` nodes of a sum proof with `allocate_scalars`, the
+	/// prover passes the proof and the verifier None
+	pub fn allocate_path<CS: ConstraintSystem>(
+		cs: &mut CS,
+		depth: usize,
+		proof: Option<&SumProof>,
+	) -> Result<Vec<AllocatedSumNode>, R1CSError> {
+		let assignments: Option<Vec<Scalar>> = proof.map(|p| {
+			p.path
+				.iter()
+				.flat_map(|n| vec![n.hash, Scalar::from(n.sum)])
+				.collect()
+		});
+		let vars = allocate_scalars(cs, 2 * depth, assignments.as_deref())?;
+		Ok(vars
+			.chunks(2)
+			.enumerate()
+			.map(|(i, pair)| AllocatedSumNode {
+				hash: pair[0],
+				sum: AllocatedQuantity {
+					variable: pair[1].variable,
+					assignment: proof.map(|p| p.path[i].sum),
+				},
+			})
+			.collect())
+	}
+}
+
+/// Proves that the committed account `id` holds the committed `balance` at
+/// the committed `leaf_index` of the sum tree with the public `root`. The
+/// balance, the sums of the siblings and all the partial sums up to the
+/// root are range checked to `SUM_BITS`, so that no negative balance can
+/// lower the total. 4 statics are needed for `statics_2` and 2 for
+/// `statics_4`.
+pub fn merkle_sum_tree_inclusion_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	root: &SumNode,
+	id: AllocatedScalar,
+	balance: AllocatedQuantity,
+	leaf_index: AllocatedScalar,
+	path: Vec<AllocatedSumNode>,
+	statics_2: Vec<AllocatedScalar>,
+	statics_4: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	if path.len() != depth {
+		return Err(R1CSError::GadgetError {
+			description: String::from("Proof does not match the tree depth"),
+		});
+	}
+	let statics_2: Vec<LinearCombination> =
+		statics_2.iter().map(|s| s.variable.into()).collect();
+	let statics_4: Vec<LinearCombination> =
+		statics_4.iter().map(|s| s.variable.into()).collect();
+
+	positive_no_gadget(cs, balance, SUM_BITS)?;
+	for node in &path {
+		positive_no_gadget(cs, node.sum, SUM_BITS)?;
+	}
+
+	// The partial sums are allocated so that they can be range checked
+	let mut partial = balance.assignment;
+	let partial_sums: Option<Vec<u64>> = path
+		.iter()
+		.map(|node| {
+			partial = partial
+				.and_then(|p| node.sum.assignment.map(|s| p.wrapping_add(s)));
+			partial
+		})
+		.collect();
+	let sum_assignments: Option<Vec<Scalar>> = partial_sums
+		.as_ref()
+		.map(|sums| sums.iter().map(|s| Scalar::from(*s)).collect());
+	let sum_vars = allocate_scalars(cs, depth, sum_assignments.as_deref())?;
+
+	let bits = scalar_bits_gadget(cs, leaf_index, depth)?;
+	let mut hash = Poseidon_hash_2_constraints::<CS>(
+		cs,
+		id.variable.into(),
+		balance.variable.into(),
+		statics_2,
+		poseidon_params,
+	)?;
+	let mut sum: LinearCombination = balance.variable.into();
+	for (i, ((bit, node), sum_var)) in bits
+		.iter()
+		.zip(path.iter())
+		.zip(sum_vars.iter())
+		.enumerate()
+	{
+		let (left_hash, right_hash) = conditional_swap_constraints(
+			cs,
+			bit.variable.into(),
+			hash,
+			node.hash.variable.into(),
+		);
+		let (left_sum, right_sum) = conditional_swap_constraints(
+			cs,
+			bit.variable.into(),
+			sum.clone(),
+			node.sum.variable.into(),
+		);
+		hash = Poseidon_hash_4_constraints::<CS>(
+			cs,
+			[left_hash, left_sum, right_hash, right_sum],
+			statics_4.clone(),
+			poseidon_params,
+		)?;
+
+		cs.constrain(sum + node.sum.variable - sum_var.variable);
+		sum = sum_var.variable.into();
+		positive_no_gadget(
+			cs,
+			AllocatedQuantity {
+				variable: sum_var.variable,
+				assignment: partial_sums.as_ref().map(|sums| sums[i]),
+			},
+			SUM_BITS,
+		)?;
+	}
+
+	constrain_lc_with_scalar::<CS>(cs, hash, &root.hash);
+	constrain_lc_with_scalar::<CS>(cs, sum, &Scalar::from(root.sum));
+	Ok(())
+}
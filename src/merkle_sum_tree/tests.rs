@@ -0,0 +1,164 @@
+use super::*;
+use crate::{
+	poseidon::{sbox::PoseidonSbox, PoseidonBuilder},
+	test_utils::{prove_and_verify, TestCircuit},
+};
+use rand_core::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+#[test]
+fn test_merkle_sum_tree() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = MerkleSumTree::new(p_params.clone(), 3);
+	assert!(tree.is_empty());
+	assert_eq!(tree.total(), 0);
+
+	let accounts: Vec<(Scalar, u64)> = [10, 0, 250, 7, 33]
+		.iter()
+		.map(|b| (Scalar::random(&mut test_rng), *b))
+		.collect();
+	for (i, (id, balance)) in accounts.iter().enumerate() {
+		assert_eq!(tree.insert(*id, *balance), Ok(i as u64));
+	}
+	assert_eq!(tree.len(), 5);
+	assert_eq!(tree.total(), 300);
+	assert_eq!(tree.account(2), Some(accounts[2]));
+	assert_eq!(tree.account(5), None);
+
+	// The root sums both halves, the right one padded with empty leaves
+	let leaf =
+		|i: usize| SumNode::leaf(accounts[i].0, accounts[i].1, &p_params);
+	let parent =
+		|l: &SumNode, r: &SumNode| SumNode::parent(l, r, &p_params).unwrap();
+	let empty = SumNode::empty();
+	let left = parent(&parent(&leaf(0), &leaf(1)), &parent(&leaf(2), &leaf(3)));
+	let right = parent(&parent(&leaf(4), &empty), &parent(&empty, &empty));
+	assert_eq!(tree.root(), parent(&left, &right));
+
+	let root = tree.root();
+	for (i, (id, balance)) in accounts.iter().enumerate() {
+		let proof = tree.prove(i as u64).unwrap();
+		assert!(verify_sum_proof(&root, *id, *balance, &proof, &p_params));
+		assert!(!verify_sum_proof(
+			&root,
+			*id,
+			balance + 1,
+			&proof,
+			&p_params
+		));
+	}
+	assert_eq!(tree.prove(5), Err(SumTreeError::IndexOutOfRange));
+
+	// Lowering the sum of a sibling changes its hash, so the liabilities
+	// can't be shifted out of the total
+	let mut proof = tree.prove(0).unwrap();
+	proof.path[0].sum -= 1;
+	let lowered = SumNode {
+		hash: root.hash,
+		sum: root.sum - 1,
+	};
+	assert!(!verify_sum_proof(
+		&lowered,
+		accounts[0].0,
+		accounts[0].1,
+		&proof,
+		&p_params
+	));
+
+	assert_eq!(
+		tree.insert(Scalar::one(), u64::MAX),
+		Err(SumTreeError::SumOverflow)
+	);
+	for _ in 5..8 {
+		tree.insert(Scalar::one(), 1).unwrap();
+	}
+	assert_eq!(
+		tree.insert(Scalar::one(), 1),
+		Err(SumTreeError::IndexOutOfRange)
+	);
+	assert_eq!(tree.total(), 303);
+}
+
+#[test]
+fn test_merkle_sum_tree_gadget() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = MerkleSumTree::new(p_params, 3);
+	let ids: Vec<Scalar> =
+		(0..5).map(|_| Scalar::random(&mut test_rng)).collect();
+	for (id, balance) in ids.iter().zip([10, 0, 250, 7, 33].iter()) {
+		tree.insert(*id, *balance).unwrap();
+	}
+	let root = tree.root();
+
+	let proof = tree.prove(2).unwrap();
+	assert!(prove_inclusion(&tree, &root, ids[2], 250, &proof));
+	let proof = tree.prove(4).unwrap();
+	assert!(prove_inclusion(&tree, &root, ids[4], 33, &proof));
+	assert!(!prove_inclusion(&tree, &root, ids[4], 32, &proof));
+	assert!(!prove_inclusion(&tree, &root, ids[3], 33, &proof));
+
+	let lowered = SumNode {
+		hash: root.hash,
+		sum: root.sum - 1,
+	};
+	let mut forged = tree.prove(4).unwrap();
+	forged.path[2].sum -= 1;
+	assert!(!prove_inclusion(&tree, &lowered, ids[4], 33, &forged));
+}
+
+struct Inclusion<'a> {
+	tree: &'a MerkleSumTree,
+	root: &'a SumNode,
+	balance: u64,
+	proof: &'a SumProof,
+}
+
+impl<'a> TestCircuit for Inclusion<'a> {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		mut statics: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let depth = self.tree.depth();
+		let proof = if witness { Some(self.proof) } else { None };
+		let path = AllocatedSumNode::allocate_path(cs, depth, proof)?;
+		let balance = AllocatedQuantity {
+			variable: committed[1].variable,
+			assignment: if witness { Some(self.balance) } else { None },
+		};
+		let statics_4 = statics.remove(1);
+		merkle_sum_tree_inclusion_gadget(
+			cs,
+			depth,
+			self.root,
+			committed[0],
+			balance,
+			committed[2],
+			path,
+			statics.remove(0),
+			statics_4,
+			&self.tree.hash_params,
+		)
+	}
+}
+
+fn prove_inclusion(
+	tree: &MerkleSumTree,
+	root: &SumNode,
+	id: Scalar,
+	balance: u64,
+	proof: &SumProof,
+) -> bool {
+	let circuit = Inclusion {
+		tree,
+		root,
+		balance,
+		proof,
+	};
+	let values = [id, Scalar::from(balance), Scalar::from(proof.index)];
+	prove_and_verify(&circuit, &values, &[4, 2]).unwrap()
+}
@@ -0,0 +1,315 @@
+use crate::{
+	poseidon::{
+		builder::Poseidon, Poseidon_hash_2, Poseidon_hash_2_constraints,
+	},
+	smt::{error::TreeError, smt::conditional_swap_constraints},
+	utils::{
+		constrain_boolean, constrain_lc_with_scalar, scalar_bits_gadget,
+		AllocatedScalar,
+	},
+};
+use alloc::{string::String, vec::Vec};
+use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
+use curve25519_dalek::scalar::Scalar;
+
+#[cfg(test)]
+pub mod tests;
+
+/// Inclusion proof of a leaf in a merkle mountain range of `size` leaves
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MmrProof {
+	pub size: u64,
+	pub leaf_index: u64,
+	/// Siblings from the leaf up to the peak of its mountain
+	pub path: Vec<Scalar>,
+	/// The peaks of the other mountains, from left to right
+	pub peaks: Vec<Scalar>,
+}
+
+impl MmrProof {
+	/// Position of the mountain holding the leaf and the index of the leaf
+	/// in that mountain
+	pub fn mountain(&self) -> Option<(usize, u64)> {
+		locate(self.size, self.leaf_index)
+	}
+
+	/// One bit per mountain, set for the mountain holding the leaf, the
+	/// selector of `mmr_membership_gadget`
+	pub fn mountain_selector(&self) -> Option<Vec<Scalar>> {
+		let (mountain, _) = self.mountain()?;
+		Some(
+			(0..peak_heights(self.size).len())
+				.map(|i| Scalar::from((i == mountain) as u8))
+				.collect(),
+		)
+	}
+
+	/// The path padded with zeros up to the height of the highest mountain,
+	/// so that its length does not depend on the mountain of the leaf
+	pub fn padded_path(&self) -> Vec<Scalar> {
+		let height = peak_heights(self.size).first().copied().unwrap_or(0);
+		let mut path = self.path.clone();
+		path.resize(height.max(path.len()), Scalar::zero());
+		path
+	}
+
+	/// The peaks of all the mountains, from left to right, with the peak of
+	/// the leaf's mountain computed from `leaf` and the path. None if the
+	/// proof does not match the mountains of the range.
+	pub fn all_peaks(
+		&self,
+		leaf: Scalar,
+		hash_params: &Poseidon,
+	) -> Option<Vec<Scalar>> {
+		let heights = peak_heights(self.size);
+		let (mountain, mut local_index) = self.mountain()?;
+		if self.path.len() != heights[mountain]
+			|| self.peaks.len() + 1 != heights.len()
+		{
+			return None;
+		}
+		let mut peak = leaf;
+		for node in &self.path {
+			peak = if local_index & 1 == 1 {
+				Poseidon_hash_2(*node, peak, hash_params)
+			} else {
+				Poseidon_hash_2(peak, *node, hash_params)
+			};
+			local_index >>= 1;
+		}
+		let mut peaks = self.peaks.clone();
+		peaks.insert(mountain, peak);
+		Some(peaks)
+	}
+}
+
+/// Append only accumulator made of perfect binary trees (mountains) of
+/// decreasing height, one per bit set in the number of leaves. The root is
+/// `H(size, bag)` where the peaks are bagged from right to left, i.e.
+/// `bag = H(p_0, H(p_1, .. H(p_{k-1}, p_k)))`, or zero if there are no
+/// leaves. Unlike `VanillaSparseMerkleTree` the size is not bounded by a
+/// depth.
+#[derive(Clone)]
+pub struct MerkleMountainRange {
+	hash_params: Poseidon,
+	/// `levels[h][i]` is the root of the `i`-th perfect subtree of height
+	/// `h`, counting from the left
+	levels: Vec<Vec<Scalar>>,
+}
+
+impl MerkleMountainRange {
+	pub fn new(hash_params: Poseidon) -> MerkleMountainRange {
+		MerkleMountainRange {
+			hash_params,
+			levels: vec![Vec::new()],
+		}
+	}
+
+	/// Number of leaves
+	pub fn len(&self) -> u64 { self.levels[0].len() as u64 }
+
+	pub fn is_empty(&self) -> bool { self.levels[0].is_empty() }
+
+	pub fn leaf(&self, index: u64) -> Option<Scalar> {
+		self.levels[0].get(index as usize).copied()
+	}
+
+	/// Append `leaf` and return its index
+	pub fn append(&mut self, leaf: Scalar) -> u64 {
+		let index = self.len();
+		self.levels[0].push(leaf);
+		let mut height = 0;
+		while self.levels[height].len() % 2 == 0 {
+			let level = &self.levels[height];
+			let parent = Poseidon_hash_2(
+				level[level.len() - 2],
+				level[level.len() - 1],
+				&self.hash_params,
+			);
+			if self.levels.len() == height + 1 {
+				self.levels.push(Vec::new());
+			}
+			self.levels[height + 1].push(parent);
+			height += 1;
+		}
+		index
+	}
+
+	/// The roots of the mountains, from the highest to the lowest
+	pub fn peaks(&self) -> Vec<Scalar> {
+		let size = self.len();
+		peak_heights(size)
+			.into_iter()
+			.map(|h| self.levels[h][((size >> h) - 1) as usize])
+			.collect()
+	}
+
+	pub fn root(&self) -> Scalar {
+		bag_peaks(self.len(), &self.peaks(), &self.hash_params)
+	}
+
+	/// Inclusion proof of the leaf at `leaf_index` under the current root
+	pub fn prove(&self, leaf_index: u64) -> Result<MmrProof, TreeError> {
+		let size = self.len();
+		let (mountain, _) =
+			locate(size, leaf_index).ok_or(TreeError::IndexOutOfRange)?;
+		let height = peak_heights(size)[mountain];
+		let path = (0..height)
+			.map(|h| self.levels[h][((leaf_index >> h) ^ 1) as usize])
+			.collect();
+		let mut peaks = self.peaks();
+		peaks.remove(mountain);
+		Ok(MmrProof {
+			size,
+			leaf_index,
+			path,
+			peaks,
+		})
+	}
+}
+
+/// Verify that `leaf` is included in a merkle mountain range with `root`
+pub fn verify_mmr_proof(
+	root: &Scalar,
+	leaf: Scalar,
+	proof: &MmrProof,
+	hash_params: &Poseidon,
+) -> bool {
+	match proof.all_peaks(leaf, hash_params) {
+		Some(peaks) => bag_peaks(proof.size, &peaks, hash_params) == *root,
+		None => false,
+	}
+}
+
+/// Heights of the mountains of a range of `size` leaves, from left to right
+pub fn peak_heights(size: u64) -> Vec<usize> {
+	(0..64).rev().filter(|h| (size >> h) & 1 == 1).collect()
+}
+
+/// The mountain holding `leaf_index` and the index of the leaf in it
+fn locate(size: u64, leaf_index: u64) -> Option<(usize, u64)> {
+	if leaf_index >= size {
+		return None;
+	}
+	let mut start = 0u64;
+	for (mountain, height) in peak_heights(size).into_iter().enumerate() {
+		let end = start + (1 << height);
+		if leaf_index < end {
+			return Some((mountain, leaf_index - start));
+		}
+		start = end;
+	}
+	None
+}
+
+fn bag_peaks(size: u64, peaks: &[Scalar], hash_params: &Poseidon) -> Scalar {
+	let bag = match peaks.split_last() {
+		Some((last, rest)) => rest
+			.iter()
+			.rev()
+			.fold(*last, |bag, peak| Poseidon_hash_2(*peak, bag, hash_params)),
+		None => Scalar::zero(),
+	};
+	Poseidon_hash_2(Scalar::from(size), bag, hash_params)
+}
+
+/// Proves that the committed `leaf` is in the merkle mountain range of the
+/// public `size` and `root` without revealing the mountain holding it. The
+/// committed `mountain_selector` has one bit per mountain, only set for the
+/// mountain of the leaf, and `local_index` is the position of the leaf in
+/// that mountain. The path is hashed up to the highest mountain, so
+/// `path_nodes` is padded beyond the height of the leaf's mountain, and
+/// `peaks` holds the peaks of all the mountains, the selected one is checked
+/// against the node at its height. See `MmrProof::mountain_selector`,
+/// `MmrProof::padded_path` and `MmrProof::all_peaks` for the witnesses, 4
+/// statics are needed for the hashes.
+pub fn mmr_membership_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	size: u64,
+	root: &Scalar,
+	leaf: AllocatedScalar,
+	local_index: AllocatedScalar,
+	mountain_selector: Vec<AllocatedScalar>,
+	path_nodes: Vec<AllocatedScalar>,
+	peaks: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let heights = peak_heights(size);
+	if heights.is_empty()
+		|| mountain_selector.len() != heights.len()
+		|| path_nodes.len() != heights[0]
+		|| peaks.len() != heights.len()
+	{
+		return Err(R1CSError::GadgetError {
+			description: String::from(
+				"Proof does not match the mountains of the range",
+			),
+		});
+	}
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+
+	// The node at every height of the path, from the leaf up
+	let bits = scalar_bits_gadget(cs, local_index, heights[0])?;
+	let mut nodes: Vec<LinearCombination> = vec![leaf.variable.into()];
+	for (bit, node) in bits.iter().zip(path_nodes.iter()) {
+		let (left, right) = conditional_swap_constraints(
+			cs,
+			bit.variable.into(),
+			nodes[nodes.len() - 1].clone(),
+			node.variable.into(),
+		);
+		let parent = Poseidon_hash_2_constraints::<CS>(
+			cs,
+			left,
+			right,
+			statics.clone(),
+			poseidon_params,
+		)?;
+		nodes.push(parent);
+	}
+
+	// Exactly one mountain is selected and its peak is the node at its
+	// height, `Sum(s_i * (node_{h_i} - peak_i)) = 0`
+	let mut selected = LinearCombination::default();
+	let mut mismatch = LinearCombination::default();
+	for ((s, peak), height) in mountain_selector
+		.iter()
+		.zip(peaks.iter())
+		.zip(heights.iter())
+	{
+		constrain_boolean(cs, s.variable.into());
+		selected = selected + s.variable;
+		let (_, _, o) = cs.multiply(
+			s.variable.into(),
+			nodes[*height].clone() - peak.variable,
+		);
+		mismatch = mismatch + o;
+	}
+	cs.constrain(selected - Scalar::one());
+	cs.constrain(mismatch);
+
+	let mut all_peaks: Vec<LinearCombination> =
+		peaks.iter().map(|p| p.variable.into()).collect();
+	let mut bag = all_peaks.pop().unwrap_or_default();
+	while let Some(peak) = all_peaks.pop() {
+		bag = Poseidon_hash_2_constraints::<CS>(
+			cs,
+			peak,
+			bag,
+			statics.clone(),
+			poseidon_params,
+		)?;
+	}
+	let computed_root = Poseidon_hash_2_constraints::<CS>(
+		cs,
+		LinearCombination::from(Scalar::from(size)),
+		bag,
+		statics,
+		poseidon_params,
+	)?;
+	constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+
+	Ok(())
+}
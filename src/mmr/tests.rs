@@ -0,0 +1,204 @@
+use super::*;
+use crate::{
+	poseidon::{
+		allocate_statics_for_prover, sbox::PoseidonSbox, PoseidonBuilder,
+	},
+	smt::builder::SparseMerkleTreeBuilder,
+	test_utils::{prove_and_verify, TestCircuit},
+	utils::allocate_scalars,
+};
+use bulletproofs::{r1cs::Prover, PedersenGens};
+use merlin::Transcript;
+use rand_core::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+#[test]
+fn test_merkle_mountain_range() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut mmr = MerkleMountainRange::new(p_params.clone());
+	assert!(mmr.is_empty());
+	assert_eq!(
+		mmr.root(),
+		Poseidon_hash_2(Scalar::zero(), Scalar::zero(), &p_params)
+	);
+
+	let leaves: Vec<Scalar> =
+		(0..13).map(|_| Scalar::random(&mut test_rng)).collect();
+	let mut roots = vec![];
+	for (i, leaf) in leaves.iter().enumerate() {
+		assert_eq!(mmr.append(*leaf), i as u64);
+		roots.push(mmr.root());
+	}
+	assert_eq!(mmr.len(), 13);
+	assert_eq!(mmr.leaf(12), Some(leaves[12]));
+	assert_eq!(mmr.leaf(13), None);
+
+	// 13 leaves make mountains of 8, 4 and 1 leaves, each one a perfect
+	// merkle tree
+	assert_eq!(peak_heights(13), vec![3, 2, 0]);
+	let peaks = mmr.peaks();
+	assert_eq!(peaks.len(), 3);
+	for (i, (start, depth)) in [(0, 3), (8, 2)].iter().enumerate() {
+		let mut tree = SparseMerkleTreeBuilder::new()
+			.hash_params(p_params.clone())
+			.depth(*depth)
+			.build();
+		let mountain = &leaves[*start..*start + (1 << *depth)];
+		tree.add_leaves(mountain.iter().map(|l| l.to_bytes()).collect(), None)
+			.unwrap();
+		assert_eq!(tree.root, peaks[i]);
+	}
+	assert_eq!(peaks[2], leaves[12]);
+	let bag = Poseidon_hash_2(
+		peaks[0],
+		Poseidon_hash_2(peaks[1], peaks[2], &p_params),
+		&p_params,
+	);
+	assert_eq!(
+		mmr.root(),
+		Poseidon_hash_2(Scalar::from(13u32), bag, &p_params)
+	);
+
+	let root = mmr.root();
+	for (i, leaf) in leaves.iter().enumerate() {
+		let proof = mmr.prove(i as u64).unwrap();
+		assert!(verify_mmr_proof(&root, *leaf, &proof, &p_params));
+		assert!(!verify_mmr_proof(
+			&root,
+			leaves[0] + leaf,
+			&proof,
+			&p_params
+		));
+		assert!(!verify_mmr_proof(&roots[11], *leaf, &proof, &p_params));
+	}
+	assert_eq!(mmr.prove(13), Err(TreeError::IndexOutOfRange));
+	let mut proof = mmr.prove(9).unwrap();
+	assert_eq!(proof.mountain(), Some((1, 1)));
+	assert_eq!(
+		proof.mountain_selector(),
+		Some(vec![Scalar::zero(), Scalar::one(), Scalar::zero()])
+	);
+	assert_eq!(proof.padded_path().len(), 3);
+	assert_eq!(proof.padded_path()[..2], proof.path[..]);
+	assert_eq!(proof.all_peaks(leaves[9], &p_params), Some(peaks));
+	proof.size = 12;
+	assert!(!verify_mmr_proof(&root, leaves[9], &proof, &p_params));
+
+	assert!(prove_mmr_membership(&mmr, leaves[5], 5, 0, &p_params).unwrap());
+	assert!(prove_mmr_membership(&mmr, leaves[10], 10, 1, &p_params).unwrap());
+	assert!(prove_mmr_membership(&mmr, leaves[12], 12, 2, &p_params).unwrap());
+	assert!(!prove_mmr_membership(&mmr, leaves[4], 5, 0, &p_params).unwrap());
+	assert!(!prove_mmr_membership(&mmr, leaves[12], 11, 1, &p_params).unwrap());
+	// The peak of the leaf is checked against the selected mountain
+	assert!(!prove_mmr_membership(&mmr, leaves[10], 10, 0, &p_params).unwrap());
+	assert!(!prove_mmr_membership(&mmr, leaves[12], 12, 1, &p_params).unwrap());
+}
+
+struct MmrMembership {
+	size: u64,
+	root: Scalar,
+	path: Vec<Scalar>,
+	peaks: Vec<Scalar>,
+	p_params: Poseidon,
+}
+
+impl TestCircuit for MmrMembership {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		mut committed: Vec<AllocatedScalar>,
+		mut statics: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let selector = committed.split_off(2);
+		let (path, peaks) = if witness {
+			(Some(&self.path[..]), Some(&self.peaks[..]))
+		} else {
+			(None, None)
+		};
+		let path_vars = allocate_scalars(cs, self.path.len(), path)?;
+		let peak_vars = allocate_scalars(cs, self.peaks.len(), peaks)?;
+		mmr_membership_gadget(
+			cs,
+			self.size,
+			&self.root,
+			committed[0],
+			committed[1],
+			selector,
+			path_vars,
+			peak_vars,
+			statics.remove(0),
+			&self.p_params,
+		)
+	}
+}
+
+/// Proves that `leaf` is at `leaf_index` of `mmr`, in the selected
+/// `mountain`. The proof of `leaf_index` is used as the witness, so it fails
+/// to verify if `leaf` is not there.
+fn prove_mmr_membership(
+	mmr: &MerkleMountainRange,
+	leaf: Scalar,
+	leaf_index: u64,
+	mountain: usize,
+	p_params: &Poseidon,
+) -> Result<bool, R1CSError> {
+	let peaks = mmr.peaks();
+	let proof = mmr.prove(leaf_index).unwrap();
+	let (_, local_index) = proof.mountain().unwrap();
+	let mut values = vec![leaf, Scalar::from(local_index)];
+	values
+		.extend((0..peaks.len()).map(|i| Scalar::from((i == mountain) as u8)));
+	let circuit = MmrMembership {
+		size: proof.size,
+		root: mmr.root(),
+		path: proof.padded_path(),
+		peaks,
+		p_params: p_params.clone(),
+	};
+	prove_and_verify(&circuit, &values, &[4])
+}
+
+#[test]
+fn test_mmr_gadget_shape() {
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let pc_gens = PedersenGens::default();
+	let mut transcript = Transcript::new(b"MMR");
+	let mut prover = Prover::new(&pc_gens, &mut transcript);
+	let zero = |prover: &mut Prover<&mut Transcript>| {
+		let (_, variable) = prover.commit(Scalar::zero(), Scalar::zero());
+		AllocatedScalar {
+			variable,
+			assignment: Some(Scalar::zero()),
+		}
+	};
+	let (leaf, index) = (zero(&mut prover), zero(&mut prover));
+	let statics = allocate_statics_for_prover(&mut prover, 4);
+
+	// A range of 13 leaves has 3 mountains, the highest one of height 3
+	let nodes = |n: usize| vec![leaf; n];
+	for &(size, selector, path, peaks) in [
+		(13, 2, 3, 3),
+		(13, 3, 2, 3),
+		(13, 3, 3, 2),
+		(13, 3, 2, 2),
+		(0, 0, 0, 0),
+	]
+	.iter()
+	{
+		let res = mmr_membership_gadget(
+			&mut prover,
+			size,
+			&Scalar::zero(),
+			leaf,
+			index,
+			nodes(selector),
+			nodes(path),
+			nodes(peaks),
+			statics.clone(),
+			&p_params,
+		);
+		assert!(res.is_err());
+	}
+}
@@ -1,11 +1,18 @@
 use crate::{
 	poseidon::{builder::Poseidon, sbox::PoseidonSbox, PoseidonBuilder},
-	smt::smt::VanillaSparseMerkleTree,
+	smt::{
+		error::TreeError,
+		incremental::{Frontier, IncrementalMerkleTree},
+		smt::VanillaSparseMerkleTree,
+	},
 };
+use alloc::vec::Vec;
 use bulletproofs::BulletproofGens;
 use curve25519_dalek::scalar::Scalar;
 
 pub const DEFAULT_TREE_DEPTH: usize = 32;
+/// Number of roots a tree remembers, same as the Tornado contracts
+pub const DEFAULT_ROOT_HISTORY_SIZE: usize = 30;
 
 pub struct SparseMerkleTreeBuilder {
 	/// The depth of the tree
@@ -13,8 +20,16 @@ pub struct SparseMerkleTreeBuilder {
 	/// The hash params, defaults to Poseidon
 	/// TODO: Add abstract hasher
 	hash_params: Option<Poseidon>,
-	/// The merkle root of the tree
+	/// The merkle root of the tree, checked against the frontier when
+	/// building a light tree
 	pub root: Option<Scalar>,
+	/// How many recent roots are accepted as known roots, the current root is
+	/// always known so 0 is treated as 1
+	pub root_history_size: Option<usize>,
+	/// The frontier a light tree starts from
+	pub frontier: Option<Frontier>,
+	/// Leaf indices whose paths a light tree keeps up to date
+	pub tracked_leaves: Vec<u64>,
 }
 
 impl SparseMerkleTreeBuilder {
@@ -23,6 +38,9 @@ impl SparseMerkleTreeBuilder {
 			depth: None,
 			hash_params: None,
 			root: None,
+			root_history_size: None,
+			frontier: None,
+			tracked_leaves: Vec::new(),
 		}
 	}
 
@@ -41,14 +59,75 @@ impl SparseMerkleTreeBuilder {
 		self
 	}
 
+	pub fn root_history_size(mut self, root_history_size: usize) -> Self {
+		self.root_history_size = Some(root_history_size);
+		self
+	}
+
+	pub fn frontier(mut self, frontier: Frontier) -> Self {
+		self.frontier = Some(frontier);
+		self
+	}
+
+	pub fn tracked_leaves(mut self, tracked_leaves: Vec<u64>) -> Self {
+		self.tracked_leaves = tracked_leaves;
+		self
+	}
+
 	pub fn build(self) -> VanillaSparseMerkleTree {
 		let depth = self.depth.unwrap_or(DEFAULT_TREE_DEPTH);
-		let hash_params = self.hash_params.unwrap_or(
-			PoseidonBuilder::new(6)
-				.sbox(PoseidonSbox::Inverse)
-				.bulletproof_gens(BulletproofGens::new(4096, 1))
-				.build(),
-		);
-		VanillaSparseMerkleTree::new(hash_params, depth)
+		let hash_params = self.hash_params.unwrap_or_else(default_hash_params);
+		let root_history_size = self
+			.root_history_size
+			.unwrap_or(DEFAULT_ROOT_HISTORY_SIZE)
+			.max(1);
+		VanillaSparseMerkleTree::empty(hash_params, depth, root_history_size)
 	}
+
+	/// Build a tree holding `leaves`, see
+	/// `VanillaSparseMerkleTree::from_leaves`
+	pub fn build_from_leaves(
+		self,
+		leaves: Vec<Scalar>,
+	) -> Result<VanillaSparseMerkleTree, TreeError> {
+		let mut tree = self.build();
+		tree.fill_with_leaves(leaves)?;
+		Ok(tree)
+	}
+
+	/// Build an `IncrementalMerkleTree` starting at `frontier` (or empty) that
+	/// only tracks the paths of `tracked_leaves`. Fails if `root` is set and
+	/// does not match the frontier, and with `InvalidValue` if the depth is 64
+	/// or more as leaf indices are `u64`.
+	pub fn build_light(self) -> Result<IncrementalMerkleTree, TreeError> {
+		let depth = self.depth.unwrap_or(DEFAULT_TREE_DEPTH);
+		if depth >= 64 {
+			return Err(TreeError::InvalidValue);
+		}
+		let hash_params = self.hash_params.unwrap_or_else(default_hash_params);
+		let mut tree = match self.frontier {
+			Some(frontier) => IncrementalMerkleTree::from_frontier(
+				hash_params,
+				depth,
+				frontier,
+			)?,
+			None => IncrementalMerkleTree::new(hash_params, depth),
+		};
+		if let Some(root) = self.root {
+			if tree.root != root {
+				return Err(TreeError::InvalidFrontier);
+			}
+		}
+		for index in self.tracked_leaves {
+			tree.track(index)?;
+		}
+		Ok(tree)
+	}
+}
+
+fn default_hash_params() -> Poseidon {
+	PoseidonBuilder::new(6)
+		.sbox(PoseidonSbox::Inverse)
+		.bulletproof_gens(BulletproofGens::new(4096, 1))
+		.build()
 }
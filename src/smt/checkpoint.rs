@@ -0,0 +1,36 @@
+use crate::utils::ScalarBytes;
+use alloc::vec::Vec;
+use curve25519_dalek::scalar::Scalar;
+use sp_std::collections::btree_map::BTreeMap;
+
+/// State of a tree when a checkpoint was taken, along with an undo log of
+/// what changed until the next checkpoint. Taking a checkpoint copies
+/// nothing but the root and the next index, the rest is saved the first
+/// time it is written to.
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpoint {
+	pub id: u64,
+	pub root: Scalar,
+	pub curr_index: Scalar,
+	/// The root history ring buffer and its current position, saved before
+	/// the first root is pushed
+	pub root_history: Option<(Vec<Scalar>, usize)>,
+	/// Nodes that were not in the db at the checkpoint
+	pub new_nodes: Vec<ScalarBytes>,
+	/// Indices of the leaf values changed since the checkpoint, as they were
+	/// at the checkpoint. An empty list means the value was not indexed.
+	pub leaf_indices: BTreeMap<ScalarBytes, Vec<Scalar>>,
+}
+
+impl Checkpoint {
+	pub fn new(id: u64, root: Scalar, curr_index: Scalar) -> Checkpoint {
+		Checkpoint {
+			id,
+			root,
+			curr_index,
+			root_history: None,
+			new_nodes: Vec::new(),
+			leaf_indices: BTreeMap::new(),
+		}
+	}
+}
@@ -0,0 +1,103 @@
+use crate::{smt::error::TreeError, utils::ScalarBytes};
+use alloc::vec::Vec;
+use codec::{Decode, Encode};
+use curve25519_dalek::scalar::Scalar;
+
+/// Merkle path that leaves out the siblings which are roots of empty
+/// subtrees. In a sparse tree most siblings are defaults, so only a few
+/// hashes have to be sent along with the bitmap.
+#[derive(Clone, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct CompressedMerkleProof {
+	/// Number of siblings in the full path
+	#[codec(compact)]
+	pub depth: u32,
+	/// Bit `i % 8` of byte `i / 8` is set if the sibling at height `i` is
+	/// the root of an empty subtree
+	pub default_bitmap: Vec<u8>,
+	/// The other siblings, from the leaf to the root
+	pub nodes: Vec<ScalarBytes>,
+}
+
+impl CompressedMerkleProof {
+	/// Compress a path of siblings from the leaf to the root, as returned by
+	/// `VanillaSparseMerkleTree::get`. `zero_hashes[i]` is the root of an
+	/// empty subtree of height `i`.
+	pub fn compress(
+		path: &[Scalar],
+		zero_hashes: &[Scalar],
+	) -> Result<CompressedMerkleProof, TreeError> {
+		if zero_hashes.len() < path.len() {
+			return Err(TreeError::ProofLengthMismatch {
+				expected: zero_hashes.len(),
+				found: path.len(),
+			});
+		}
+		let mut default_bitmap = vec![0u8; (path.len() + 7) / 8];
+		let mut nodes = Vec::new();
+		for (i, node) in path.iter().enumerate() {
+			if *node == zero_hashes[i] {
+				default_bitmap[i / 8] |= 1 << (i % 8);
+			} else {
+				nodes.push(node.to_bytes());
+			}
+		}
+
+		Ok(CompressedMerkleProof {
+			depth: path.len() as u32,
+			default_bitmap,
+			nodes,
+		})
+	}
+
+	/// Returns true if the sibling at `height` is an empty subtree
+	pub fn is_default(&self, height: usize) -> bool {
+		self.default_bitmap
+			.get(height / 8)
+			.map_or(false, |byte| byte & (1 << (height % 8)) != 0)
+	}
+
+	/// Rebuild the full path of siblings from the leaf to the root, e.g. to
+	/// feed the path gadgets.
+	pub fn expand(
+		&self,
+		zero_hashes: &[Scalar],
+	) -> Result<Vec<Scalar>, TreeError> {
+		let depth = self.depth as usize;
+		if zero_hashes.len() < depth {
+			return Err(TreeError::ProofLengthMismatch {
+				expected: zero_hashes.len(),
+				found: depth,
+			});
+		}
+		// Only accept the canonical encoding, so that a proof has a single
+		// valid encoding
+		if self.default_bitmap.len() != (depth + 7) / 8 {
+			return Err(TreeError::InvalidProof);
+		}
+		if depth % 8 != 0 && self.default_bitmap[depth / 8] >> (depth % 8) != 0
+		{
+			return Err(TreeError::InvalidProof);
+		}
+		let num_defaults = (0..depth).filter(|i| self.is_default(*i)).count();
+		if self.nodes.len() + num_defaults != depth {
+			return Err(TreeError::InvalidProof);
+		}
+
+		let mut nodes = self.nodes.iter();
+		let mut path = Vec::with_capacity(depth);
+		for (i, zero) in zero_hashes.iter().take(depth).enumerate() {
+			if self.is_default(i) {
+				path.push(*zero);
+			} else {
+				let bytes = nodes.next().ok_or(TreeError::InvalidProof)?;
+				let node = Scalar::from_canonical_bytes(*bytes)
+					.ok_or(TreeError::InvalidProof)?;
+				if node == *zero {
+					return Err(TreeError::InvalidProof);
+				}
+				path.push(node);
+			}
+		}
+		Ok(path)
+	}
+}
@@ -0,0 +1,97 @@
+use bulletproofs::r1cs::R1CSError;
+use core::fmt;
+
+/// Errors returned by the merkle trees and the deposit trees built on them.
+/// The sum tree, the indexed tree, the map and the reward tree have their own
+/// errors, which wrap this one for the failures of the underlying tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TreeError {
+	// Lookups and proofs, returned by every tree
+	/// The root is neither the current root nor one of the retained roots
+	UnknownRoot,
+	/// The tree has no leaf (or no secrets) for the given value
+	UnknownLeaf,
+	/// The index does not fit the depth of the tree, or the tree is full
+	IndexOutOfRange,
+	/// A merkle path does not have exactly one node per level
+	ProofLengthMismatch { expected: usize, found: usize },
+	/// A compressed proof or a multiproof is malformed, or a path does not
+	/// hash to the expected root
+	InvalidProof,
+	/// An argument is outside of the accepted range, such as a zero
+	/// checkpoint interval or a depth too large for the tree
+	InvalidValue,
+
+	// Light trees, returned by `IncrementalMerkleTree` and
+	// `SparseMerkleTreeBuilder::build_light`
+	/// The frontier is inconsistent with the tree or the expected root
+	InvalidFrontier,
+	/// A witness missed some of the leaves appended to the tree
+	WitnessOutOfSync,
+
+	// Batches, returned by `VanillaSparseMerkleTree::batch_insert_path`
+	/// A batch is not a power of two number of leaves filling an empty,
+	/// aligned subtree
+	InvalidBatch,
+
+	// Syncing, returned by `TreeSync`
+	/// A leaf event could not be decoded or its leaf is not canonical
+	InvalidEvent,
+	/// Leaf events are missing between the synced leaves and the next event
+	MissingEvents { expected: u64, found: u64 },
+	/// The synced tree disagrees with the event log or the expected root
+	/// once `leaf_count` leaves were added
+	Diverged { leaf_count: u64 },
+	/// A snapshot does not fit the tree or does not hash to its root
+	InvalidSnapshot,
+
+	// Checkpoints, returned by `checkpoint` and `rollback_to`
+	/// Checkpoint ids must be increasing
+	InvalidCheckpoint,
+	/// No checkpoint was taken with the given id, or it was discarded
+	UnknownCheckpoint,
+
+	/// Building or proving the circuit failed, returned by the `prove_zk`
+	/// methods
+	R1CSError(R1CSError),
+}
+
+impl fmt::Display for TreeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TreeError::UnknownRoot => write!(f, "Unknown root"),
+			TreeError::UnknownLeaf => write!(f, "Unknown leaf"),
+			TreeError::IndexOutOfRange => write!(f, "Index out of range"),
+			TreeError::ProofLengthMismatch { expected, found } => write!(
+				f,
+				"Proof length mismatch: expected {} nodes, found {}",
+				expected, found
+			),
+			TreeError::InvalidProof => write!(f, "Invalid proof"),
+			TreeError::InvalidFrontier => write!(f, "Invalid frontier"),
+			TreeError::InvalidBatch => write!(f, "Invalid batch"),
+			TreeError::WitnessOutOfSync => write!(f, "Witness out of sync"),
+			TreeError::InvalidEvent => write!(f, "Invalid leaf event"),
+			TreeError::MissingEvents { expected, found } => write!(
+				f,
+				"Missing leaf events: expected index {}, found {}",
+				expected, found
+			),
+			TreeError::Diverged { leaf_count } => {
+				write!(f, "Tree diverged after {} leaves", leaf_count)
+			},
+			TreeError::InvalidSnapshot => write!(f, "Invalid snapshot"),
+			TreeError::InvalidCheckpoint => write!(f, "Invalid checkpoint"),
+			TreeError::UnknownCheckpoint => write!(f, "Unknown checkpoint"),
+			TreeError::InvalidValue => write!(f, "Invalid value"),
+			TreeError::R1CSError(e) => write!(f, "R1CS error: {:?}", e),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TreeError {}
+
+impl From<R1CSError> for TreeError {
+	fn from(e: R1CSError) -> TreeError { TreeError::R1CSError(e) }
+}
@@ -0,0 +1,334 @@
+use crate::{
+	poseidon::{builder::Poseidon, Poseidon_hash_2},
+	smt::{error::TreeError, smt::gen_zero_tree},
+};
+use alloc::vec::Vec;
+use curve25519_dalek::scalar::Scalar;
+use sp_std::collections::btree_map::BTreeMap;
+
+/// The right-most leaf of an append-only tree along with its merkle path.
+/// This is all that is needed to keep appending leaves to a tree without
+/// holding its nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frontier {
+	/// Number of leaves in the tree
+	pub next_index: u64,
+	/// The leaf at `next_index - 1`
+	pub leaf: Scalar,
+	/// Siblings of `leaf` from the leaf to the root
+	pub path: Vec<Scalar>,
+}
+
+/// Append-only merkle tree that only keeps the frontier of the tree, i.e. the
+/// last left node of every level, along with the roots of the empty subtrees.
+/// Leaves are inserted from index 0 onwards, so the roots are identical to
+/// the ones of a `VanillaSparseMerkleTree` filled with `add_leaves`.
+#[derive(Clone)]
+pub struct IncrementalMerkleTree {
+	pub depth: usize,
+	hash_params: Poseidon,
+	pub root: Scalar,
+	next_index: u64,
+	/// `zero_hashes[i]` is the root of an empty subtree of height `i`
+	zero_hashes: Vec<Scalar>,
+	/// `filled_subtrees[i]` is the last left node inserted at height `i`
+	filled_subtrees: Vec<Scalar>,
+	/// Nodes on the path of the last appended leaf, from the leaf up to the
+	/// root, with empty subtrees on the right
+	last_path: Vec<Scalar>,
+	/// Witnesses of tracked leaves, `None` until the leaf is appended
+	witnesses: BTreeMap<u64, Option<IncrementalWitness>>,
+}
+
+impl IncrementalMerkleTree {
+	pub fn new(hash_params: Poseidon, depth: usize) -> IncrementalMerkleTree {
+		assert!(depth < 64, "Depth must fit a u64 leaf index");
+		let zero_hashes: Vec<Scalar> =
+			gen_zero_tree(hash_params.width, &hash_params.sbox)
+				.iter()
+				.take(depth + 1)
+				.map(|z| Scalar::from_bytes_mod_order(*z))
+				.collect();
+		let root = zero_hashes[depth];
+		let filled_subtrees = zero_hashes[..depth].to_vec();
+		let last_path = zero_hashes.clone();
+
+		IncrementalMerkleTree {
+			depth,
+			hash_params,
+			root,
+			next_index: 0,
+			zero_hashes,
+			filled_subtrees,
+			last_path,
+			witnesses: BTreeMap::new(),
+		}
+	}
+
+	/// Restore a tree from the frontier of a tree with the same depth and
+	/// hash params, e.g. one returned by `VanillaSparseMerkleTree::frontier`.
+	pub fn from_frontier(
+		hash_params: Poseidon,
+		depth: usize,
+		frontier: Frontier,
+	) -> Result<IncrementalMerkleTree, TreeError> {
+		let mut tree = Self::new(hash_params, depth);
+		if frontier.next_index == 0 {
+			return Ok(tree);
+		}
+		if frontier.next_index > (1u64 << depth) {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		if frontier.path.len() != depth {
+			return Err(TreeError::ProofLengthMismatch {
+				expected: depth,
+				found: frontier.path.len(),
+			});
+		}
+
+		let index = frontier.next_index - 1;
+		let mut cur_val = frontier.leaf;
+		tree.last_path[0] = cur_val;
+		for i in 0..depth {
+			let (l, r) = if (index >> i) & 1 == 1 {
+				tree.filled_subtrees[i] = frontier.path[i];
+				(frontier.path[i], cur_val)
+			} else {
+				// Everything right of the frontier leaf must be empty
+				if frontier.path[i] != tree.zero_hashes[i] {
+					return Err(TreeError::InvalidFrontier);
+				}
+				tree.filled_subtrees[i] = cur_val;
+				(cur_val, tree.zero_hashes[i])
+			};
+			cur_val = Poseidon_hash_2(l, r, &tree.hash_params);
+			tree.last_path[i + 1] = cur_val;
+		}
+
+		tree.root = cur_val;
+		tree.next_index = frontier.next_index;
+		Ok(tree)
+	}
+
+	pub fn frontier(&self) -> Frontier {
+		let path = match IncrementalWitness::from_tree(self) {
+			Ok(witness) => witness.path,
+			Err(_) => self.zero_hashes[..self.depth].to_vec(),
+		};
+		Frontier {
+			next_index: self.next_index,
+			leaf: self.last_path[0],
+			path,
+		}
+	}
+
+	/// Number of leaves appended so far, this is also the index of the next
+	/// leaf.
+	pub fn next_index(&self) -> u64 { self.next_index }
+
+	pub fn zero_hashes(&self) -> &[Scalar] { &self.zero_hashes }
+
+	/// Append a leaf and return its index
+	pub fn append(&mut self, leaf: Scalar) -> Result<u64, TreeError> {
+		if self.next_index >= (1u64 << self.depth) {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		let index = self.next_index;
+		let mut cur_val = leaf;
+		self.last_path[0] = leaf;
+
+		for i in 0..self.depth {
+			let (l, r) = if (index >> i) & 1 == 1 {
+				(self.filled_subtrees[i], cur_val)
+			} else {
+				self.filled_subtrees[i] = cur_val;
+				(cur_val, self.zero_hashes[i])
+			};
+			cur_val = Poseidon_hash_2(l, r, &self.hash_params);
+			self.last_path[i + 1] = cur_val;
+		}
+
+		self.root = cur_val;
+		self.next_index += 1;
+		self.update_witnesses(index)?;
+		Ok(index)
+	}
+
+	pub fn add_leaves(&mut self, vals: Vec<[u8; 32]>) -> Result<(), TreeError> {
+		for val in vals {
+			self.append(Scalar::from_bytes_mod_order(val))?;
+		}
+		Ok(())
+	}
+
+	/// Keep the merkle path of the leaf at `index` up to date as leaves are
+	/// appended. Only the last appended leaf and leaves that are not in the
+	/// tree yet can be tracked, older leaves need their path, see
+	/// `track_with_path`.
+	pub fn track(&mut self, index: u64) -> Result<(), TreeError> {
+		if index >= (1u64 << self.depth) {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		if self.next_index > 0 && index == self.next_index - 1 {
+			let witness = IncrementalWitness::from_tree(self)?;
+			self.witnesses.insert(index, Some(witness));
+		} else if index >= self.next_index {
+			self.witnesses.insert(index, None);
+		} else {
+			// Older leaves are not part of the frontier
+			return Err(TreeError::UnknownLeaf);
+		}
+		Ok(())
+	}
+
+	/// Track a leaf that is already in the tree, such as a note a wallet
+	/// owned before restoring the tree from a frontier, from its merkle path
+	/// under the current root. See `IncrementalWitness::from_path`.
+	pub fn track_with_path(
+		&mut self,
+		index: u64,
+		leaf: Scalar,
+		path: Vec<Scalar>,
+	) -> Result<(), TreeError> {
+		let witness = IncrementalWitness::from_path(self, index, leaf, path)?;
+		self.witnesses.insert(index, Some(witness));
+		Ok(())
+	}
+
+	pub fn untrack(&mut self, index: u64) { self.witnesses.remove(&index); }
+
+	/// Witness of a tracked leaf, `None` if the leaf is not tracked or not
+	/// appended yet
+	pub fn witness(&self, index: u64) -> Option<&IncrementalWitness> {
+		self.witnesses.get(&index).and_then(|w| w.as_ref())
+	}
+
+	/// Merkle path of a tracked leaf against the current root
+	pub fn path(&self, index: u64) -> Option<Vec<Scalar>> {
+		self.witness(index).map(|w| w.path())
+	}
+
+	fn update_witnesses(&mut self, new_index: u64) -> Result<(), TreeError> {
+		let mut witnesses = core::mem::take(&mut self.witnesses);
+		let mut res = Ok(());
+		for (index, witness) in witnesses.iter_mut() {
+			res = match witness {
+				Some(w) => w.update(self),
+				None if *index == new_index => {
+					IncrementalWitness::from_tree(self).map(|w| {
+						*witness = Some(w);
+					})
+				},
+				None => Ok(()),
+			};
+			if res.is_err() {
+				break;
+			}
+		}
+		self.witnesses = witnesses;
+		res
+	}
+}
+
+/// Merkle path of a single leaf of an `IncrementalMerkleTree`. The witness
+/// has to be updated after every leaf appended to the tree, only the sibling
+/// that changed is replaced, so this is O(1) per leaf.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncrementalWitness {
+	pub index: u64,
+	pub leaf: Scalar,
+	/// Siblings from the leaf to the root, as returned by
+	/// `VanillaSparseMerkleTree::get`
+	path: Vec<Scalar>,
+	/// Root of the tree the path is valid for
+	pub root: Scalar,
+	tree_size: u64,
+}
+
+impl IncrementalWitness {
+	/// Create a witness for the last leaf appended to `tree`
+	pub fn from_tree(
+		tree: &IncrementalMerkleTree,
+	) -> Result<IncrementalWitness, TreeError> {
+		if tree.next_index == 0 {
+			return Err(TreeError::UnknownLeaf);
+		}
+		let index = tree.next_index - 1;
+		let path = (0..tree.depth)
+			.map(|i| {
+				if (index >> i) & 1 == 1 {
+					tree.filled_subtrees[i]
+				} else {
+					tree.zero_hashes[i]
+				}
+			})
+			.collect();
+
+		Ok(IncrementalWitness {
+			index,
+			leaf: tree.last_path[0],
+			path,
+			root: tree.root,
+			tree_size: tree.next_index,
+		})
+	}
+
+	/// Create a witness for the leaf at `index` of `tree` from its merkle
+	/// path, which has to hash to the current root of the tree
+	pub fn from_path(
+		tree: &IncrementalMerkleTree,
+		index: u64,
+		leaf: Scalar,
+		path: Vec<Scalar>,
+	) -> Result<IncrementalWitness, TreeError> {
+		if index >= tree.next_index {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		if path.len() != tree.depth {
+			return Err(TreeError::ProofLengthMismatch {
+				expected: tree.depth,
+				found: path.len(),
+			});
+		}
+		let mut cur_val = leaf;
+		for (i, node) in path.iter().enumerate() {
+			cur_val = if (index >> i) & 1 == 1 {
+				Poseidon_hash_2(*node, cur_val, &tree.hash_params)
+			} else {
+				Poseidon_hash_2(cur_val, *node, &tree.hash_params)
+			};
+		}
+		if cur_val != tree.root {
+			return Err(TreeError::InvalidProof);
+		}
+
+		Ok(IncrementalWitness {
+			index,
+			leaf,
+			path,
+			root: tree.root,
+			tree_size: tree.next_index,
+		})
+	}
+
+	/// Catch up with the last leaf appended to `tree`. The witness must be
+	/// updated once for every appended leaf.
+	pub fn update(
+		&mut self,
+		tree: &IncrementalMerkleTree,
+	) -> Result<(), TreeError> {
+		if tree.next_index != self.tree_size + 1 {
+			return Err(TreeError::WitnessOutOfSync);
+		}
+		let new_index = tree.next_index - 1;
+		// The new leaf ends up in the subtree right of ours at the height
+		// where both paths join.
+		let height = (63 - (self.index ^ new_index).leading_zeros()) as usize;
+		self.path[height] = tree.last_path[height];
+		self.root = tree.root;
+		self.tree_size = tree.next_index;
+		Ok(())
+	}
+
+	pub fn path(&self) -> Vec<Scalar> { self.path.clone() }
+}
@@ -0,0 +1,270 @@
+use crate::{
+	poseidon::{
+		builder::Poseidon, Poseidon_hash_2, Poseidon_hash_2_constraints,
+	},
+	smt::{
+		error::TreeError,
+		smt::{merkle_path_root_constraints, VanillaSparseMerkleTree},
+	},
+	utils::{
+		constrain_lc_with_scalar, lc_bits_gadget, AllocatedScalar, ScalarBytes,
+	},
+};
+use alloc::vec::Vec;
+use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
+use core::fmt;
+use curve25519_dalek::scalar::Scalar;
+use sp_std::collections::btree_map::BTreeMap;
+
+/// Number of bits of the path hash decomposed in circuit. Hashes of at least
+/// 2^252 are rejected so that the decomposition is unique.
+const PATH_HASH_BITS: usize = 252;
+
+/// Errors returned by `SparseMerkleMap`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MapError {
+	/// The path hash of the key does not fit in 252 bits
+	InvalidKey,
+	/// Another key of the map is stored at the same index
+	KeyCollision,
+	/// The underlying tree failed, e.g. the root is unknown
+	Tree(TreeError),
+}
+
+impl fmt::Display for MapError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MapError::InvalidKey => write!(f, "Invalid key"),
+			MapError::KeyCollision => write!(f, "Key collision"),
+			MapError::Tree(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MapError {}
+
+impl From<TreeError> for MapError {
+	fn from(e: TreeError) -> MapError { MapError::Tree(e) }
+}
+
+/// Key-value map stored in a `VanillaSparseMerkleTree`. A key lives at the
+/// index given by the low `depth` bits of `H(key, key)`, its leaf is
+/// `H(key, value)` and missing keys have an empty leaf. Two keys landing on
+/// the same index can't both be stored, so the depth should be large enough
+/// for collisions to be unlikely.
+#[derive(Clone)]
+pub struct SparseMerkleMap {
+	tree: VanillaSparseMerkleTree,
+	hash_params: Poseidon,
+	/// The key and value stored at each index
+	entries: BTreeMap<ScalarBytes, (Scalar, Scalar)>,
+}
+
+impl SparseMerkleMap {
+	pub fn new(hash_params: Poseidon, depth: usize) -> SparseMerkleMap {
+		assert!(depth <= PATH_HASH_BITS, "Depth must be at most 252 bits");
+		SparseMerkleMap {
+			tree: VanillaSparseMerkleTree::new(hash_params.clone(), depth),
+			hash_params,
+			entries: BTreeMap::new(),
+		}
+	}
+
+	pub fn depth(&self) -> usize { self.tree.depth }
+
+	pub fn root(&self) -> Scalar { self.tree.root }
+
+	pub fn tree(&self) -> &VanillaSparseMerkleTree { &self.tree }
+
+	pub fn len(&self) -> usize { self.entries.len() }
+
+	pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+	/// The index of `key` in the tree, errors with `InvalidKey` if its path
+	/// hash can't be decomposed in 252 bits
+	pub fn index_of(&self, key: Scalar) -> Result<Scalar, MapError> {
+		map_index(key, self.tree.depth, &self.hash_params)
+	}
+
+	/// The leaf storing `value` under `key`
+	pub fn leaf(&self, key: Scalar, value: Scalar) -> Scalar {
+		Poseidon_hash_2(key, value, &self.hash_params)
+	}
+
+	pub fn get(&self, key: Scalar) -> Option<Scalar> {
+		let index = self.index_of(key).ok()?;
+		match self.entries.get(&index.to_bytes()) {
+			Some((k, v)) if *k == key => Some(*v),
+			_ => None,
+		}
+	}
+
+	/// Set `key` to `value` and return the previous value. Errors with
+	/// `KeyCollision` if another key is stored at the same index.
+	pub fn insert(
+		&mut self,
+		key: Scalar,
+		value: Scalar,
+	) -> Result<Option<Scalar>, MapError> {
+		let index = self.index_of(key)?;
+		let old = match self.entries.get(&index.to_bytes()) {
+			Some((k, _)) if *k != key => return Err(MapError::KeyCollision),
+			Some((_, v)) => Some(*v),
+			None => None,
+		};
+		self.tree.update(index, self.leaf(key, value))?;
+		self.entries.insert(index.to_bytes(), (key, value));
+		Ok(old)
+	}
+
+	/// Remove `key` and return its value, if it was set
+	pub fn remove(&mut self, key: Scalar) -> Result<Option<Scalar>, MapError> {
+		if self.get(key).is_none() {
+			return Ok(None);
+		}
+		let index = self.index_of(key)?;
+		self.tree.update(index, Scalar::zero())?;
+		Ok(self.entries.remove(&index.to_bytes()).map(|(_, v)| v))
+	}
+
+	/// The merkle path of the index of `key` under the current root, which
+	/// proves either the value of `key` or that it is not set
+	pub fn prove(&self, key: Scalar) -> Result<Vec<Scalar>, MapError> {
+		Ok(self.tree.path_at(self.index_of(key)?)?)
+	}
+
+	/// Verify that `key` is set to `value` under `root`
+	pub fn verify_membership(
+		&self,
+		key: Scalar,
+		value: Scalar,
+		proof: &[Scalar],
+		root: &Scalar,
+	) -> Result<bool, MapError> {
+		let index = self.index_of(key)?;
+		Ok(self.tree.verify_proof(
+			index,
+			self.leaf(key, value),
+			proof,
+			Some(root),
+		)?)
+	}
+
+	/// Verify that `key` is not set under `root`
+	pub fn verify_non_membership(
+		&self,
+		key: Scalar,
+		proof: &[Scalar],
+		root: &Scalar,
+	) -> Result<bool, MapError> {
+		let index = self.index_of(key)?;
+		Ok(self
+			.tree
+			.verify_proof(index, Scalar::zero(), proof, Some(root))?)
+	}
+}
+
+/// The low `depth` bits of `H(key, key)`
+fn map_index(
+	key: Scalar,
+	depth: usize,
+	hash_params: &Poseidon,
+) -> Result<Scalar, MapError> {
+	let path_hash = Poseidon_hash_2(key, key, hash_params);
+	let mut bytes = path_hash.to_bytes();
+	if bytes[31] >> 4 != 0 {
+		return Err(MapError::InvalidKey);
+	}
+	for (i, byte) in bytes.iter_mut().enumerate() {
+		let low_bits = depth.saturating_sub(8 * i).min(8);
+		*byte &= ((1u16 << low_bits) - 1) as u8;
+	}
+	Ok(Scalar::from_bits(bytes))
+}
+
+/// Hash `key` to its path in circuit and return the `depth` index bits
+fn map_index_bits_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	key: AllocatedScalar,
+	statics: &[LinearCombination],
+	poseidon_params: &Poseidon,
+) -> Result<Vec<AllocatedScalar>, R1CSError> {
+	let path_hash = Poseidon_hash_2_constraints::<CS>(
+		cs,
+		key.variable.into(),
+		key.variable.into(),
+		statics.to_vec(),
+		poseidon_params,
+	)?;
+	let assignment = key
+		.assignment
+		.map(|k| Poseidon_hash_2(k, k, poseidon_params));
+	let mut bits = lc_bits_gadget(cs, path_hash, assignment, PATH_HASH_BITS)?;
+	bits.truncate(depth);
+	Ok(bits)
+}
+
+/// Proves that the committed `key` is set to the committed `value` in the
+/// map with the public `root`. `proof_nodes` is the path returned by
+/// `SparseMerkleMap::prove`, 4 statics are needed for the hashes.
+pub fn sparse_merkle_map_membership_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	root: &Scalar,
+	key: AllocatedScalar,
+	value: AllocatedScalar,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+	let bits =
+		map_index_bits_constraints(cs, depth, key, &statics, poseidon_params)?;
+	let leaf = Poseidon_hash_2_constraints::<CS>(
+		cs,
+		key.variable.into(),
+		value.variable.into(),
+		statics.clone(),
+		poseidon_params,
+	)?;
+	let computed_root = merkle_path_root_constraints(
+		cs,
+		leaf,
+		&bits,
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
+	constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+	Ok(())
+}
+
+/// Proves that the committed `key` is not set in the map with the public
+/// `root`, i.e. its leaf is empty. See `sparse_merkle_map_membership_gadget`.
+pub fn sparse_merkle_map_non_membership_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	root: &Scalar,
+	key: AllocatedScalar,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+	let bits =
+		map_index_bits_constraints(cs, depth, key, &statics, poseidon_params)?;
+	let computed_root = merkle_path_root_constraints(
+		cs,
+		LinearCombination::default(),
+		&bits,
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
+	constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+	Ok(())
+}
@@ -1,7 +1,22 @@
 pub mod builder;
+mod checkpoint;
+pub mod compressed;
+pub mod error;
+pub mod incremental;
+pub mod map;
+pub mod multiproof;
+pub mod quaternary;
 
 pub mod smt;
+pub mod sync;
+pub use compressed::CompressedMerkleProof;
+pub use error::TreeError;
+pub use incremental::*;
+pub use map::{MapError, SparseMerkleMap};
+pub use multiproof::MerkleMultiProof;
+pub use quaternary::QuaternarySparseMerkleTree;
 pub use smt::*;
+pub use sync::{LeafEvent, TreeSnapshot, TreeSync};
 
 #[cfg(test)]
 pub mod tests;
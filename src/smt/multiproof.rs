@@ -0,0 +1,163 @@
+use crate::{
+	poseidon::{builder::Poseidon, Poseidon_hash_2},
+	smt::error::TreeError,
+	utils::ScalarBytes,
+};
+use alloc::vec::Vec;
+use codec::{Decode, Encode};
+use curve25519_dalek::scalar::Scalar;
+use sp_std::collections::btree_map::BTreeMap;
+
+/// Position of a node within its level, big-endian so that positions are
+/// ordered like the indices they represent
+type Position = [u8; 32];
+
+/// Merkle proof for several leaves at once. Siblings shared by the paths,
+/// and siblings that can be computed from the other leaves, are only
+/// included once.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Encode, Decode)]
+pub struct MerkleMultiProof {
+	/// Siblings that cannot be computed from the leaves, ordered by height
+	/// and then by position, from the leaves to the root
+	pub nodes: Vec<ScalarBytes>,
+}
+
+impl MerkleMultiProof {
+	/// Build a multi proof from the merkle paths of the leaves at the given
+	/// indices, as returned by `VanillaSparseMerkleTree::get`. Every path
+	/// must have `depth` nodes.
+	pub fn from_paths(
+		depth: usize,
+		paths: &[(Scalar, Vec<Scalar>)],
+	) -> Result<MerkleMultiProof, TreeError> {
+		// Value of every sibling found on the paths, per height
+		let mut siblings = vec![BTreeMap::<Position, Scalar>::new(); depth];
+		let mut known: Vec<Position> = Vec::new();
+		for (index, path) in paths {
+			if path.len() != depth {
+				return Err(TreeError::ProofLengthMismatch {
+					expected: depth,
+					found: path.len(),
+				});
+			}
+			let mut pos = position(index);
+			known.push(pos);
+			for (height, node) in path.iter().enumerate() {
+				siblings[height].insert(sibling(&pos), *node);
+				shr(&mut pos);
+			}
+		}
+		known.sort_unstable();
+		known.dedup();
+
+		let mut nodes = Vec::new();
+		for level in siblings.iter() {
+			for pos in known.iter() {
+				let sib = sibling(pos);
+				if known.binary_search(&sib).is_err() {
+					nodes.push(level[&sib].to_bytes());
+				}
+			}
+			known = parents(&known);
+		}
+
+		Ok(MerkleMultiProof { nodes })
+	}
+
+	/// Compute the root of a tree of depth `depth` holding the given
+	/// `(index, leaf)` pairs.
+	pub fn root(
+		&self,
+		depth: usize,
+		leaves: &[(Scalar, Scalar)],
+		hash_params: &Poseidon,
+	) -> Result<Scalar, TreeError> {
+		let mut level = BTreeMap::<Position, Scalar>::new();
+		for (index, leaf) in leaves {
+			if let Some(prev) = level.insert(position(index), *leaf) {
+				// The same index can't hold two different leaves
+				if prev != *leaf {
+					return Err(TreeError::InvalidProof);
+				}
+			}
+		}
+		if level.is_empty() {
+			return Err(TreeError::UnknownLeaf);
+		}
+
+		let mut nodes = self.nodes.iter();
+		for _ in 0..depth {
+			let mut next = BTreeMap::<Position, Scalar>::new();
+			for (pos, val) in level.iter() {
+				let sib = sibling(pos);
+				let sib_val = match level.get(&sib) {
+					Some(v) => *v,
+					None => {
+						let bytes =
+							nodes.next().ok_or(TreeError::InvalidProof)?;
+						Scalar::from_canonical_bytes(*bytes)
+							.ok_or(TreeError::InvalidProof)?
+					},
+				};
+				let mut parent = *pos;
+				shr(&mut parent);
+				if next.contains_key(&parent) {
+					// Already hashed from the left child
+					continue;
+				}
+				let h = if is_right(pos) {
+					Poseidon_hash_2(sib_val, *val, hash_params)
+				} else {
+					Poseidon_hash_2(*val, sib_val, hash_params)
+				};
+				next.insert(parent, h);
+			}
+			level = next;
+		}
+
+		if nodes.next().is_some() {
+			return Err(TreeError::InvalidProof);
+		}
+		// All leaves end up in the root, unless some index was out of range
+		match (level.len(), level.get(&[0u8; 32])) {
+			(1, Some(root)) => Ok(*root),
+			_ => Err(TreeError::IndexOutOfRange),
+		}
+	}
+}
+
+fn position(index: &Scalar) -> Position {
+	let mut pos = index.reduce().to_bytes();
+	pos.reverse();
+	pos
+}
+
+fn sibling(pos: &Position) -> Position {
+	let mut sib = *pos;
+	sib[31] ^= 1;
+	sib
+}
+
+fn is_right(pos: &Position) -> bool { pos[31] & 1 == 1 }
+
+fn shr(pos: &mut Position) {
+	let mut carry = 0u8;
+	for byte in pos.iter_mut() {
+		let next_carry = *byte & 1;
+		*byte = (*byte >> 1) | (carry << 7);
+		carry = next_carry;
+	}
+}
+
+fn parents(positions: &[Position]) -> Vec<Position> {
+	let mut parents: Vec<Position> = positions
+		.iter()
+		.map(|pos| {
+			let mut parent = *pos;
+			shr(&mut parent);
+			parent
+		})
+		.collect();
+	parents.dedup();
+	parents
+}
@@ -0,0 +1,365 @@
+#[cfg(feature = "std")]
+use crate::{poseidon::allocate_statics_for_prover, utils::allocate_scalars};
+use crate::{
+	poseidon::{
+		builder::Poseidon, Poseidon_hash_4, Poseidon_hash_4_constraints,
+	},
+	smt::{error::TreeError, smt::MembershipProofComms},
+	utils::{
+		constrain_lc_with_scalar, get_bits, scalar_bits_gadget,
+		AllocatedScalar, ScalarBytes,
+	},
+};
+use alloc::vec::Vec;
+use bulletproofs::r1cs::{
+	ConstraintSystem, LinearCombination, R1CSError, Variable,
+};
+#[cfg(feature = "std")]
+use bulletproofs::{
+	r1cs::{Prover, R1CSProof},
+	BulletproofGens,
+};
+use curve25519_dalek::scalar::Scalar;
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use sp_std::collections::btree_map::BTreeMap;
+
+/// Children of a node, from left to right
+pub type QuaternaryDBVal = [Scalar; 4];
+/// The 3 other children of the parent of a node, from left to right
+pub type QuaternarySiblings = [Scalar; 3];
+
+/// Sparse merkle tree with 4 children per node, hashed with
+/// `Poseidon_hash_4`. A tree of depth `d` holds `4^d` leaves, so it needs
+/// half the levels of a binary tree of the same size. Every level consumes
+/// two bits of the leaf index, LSB first.
+#[derive(Clone)]
+pub struct QuaternarySparseMerkleTree {
+	pub depth: usize,
+	db: BTreeMap<ScalarBytes, QuaternaryDBVal>,
+	hash_params: Poseidon,
+	pub root: Scalar,
+	curr_index: Scalar,
+	/// `zero_hashes[i]` is the root of an empty subtree of height `i`
+	zero_hashes: Vec<Scalar>,
+}
+
+impl QuaternarySparseMerkleTree {
+	pub fn new(
+		hash_params: Poseidon,
+		depth: usize,
+	) -> QuaternarySparseMerkleTree {
+		let mut db = BTreeMap::new();
+		let zero_hashes = gen_quaternary_zero_tree(depth, &hash_params);
+		for i in 1..=depth {
+			let prev = zero_hashes[i - 1];
+			db.insert(zero_hashes[i].to_bytes(), [prev; 4]);
+		}
+		let root = zero_hashes[depth];
+
+		QuaternarySparseMerkleTree {
+			depth,
+			db,
+			hash_params,
+			root,
+			curr_index: Scalar::zero(),
+			zero_hashes,
+		}
+	}
+
+	pub fn zero_hashes(&self) -> &[Scalar] { &self.zero_hashes }
+
+	/// Insert leaves from the current index onwards, see
+	/// `VanillaSparseMerkleTree::add_leaves`
+	pub fn add_leaves(
+		&mut self,
+		vals: Vec<[u8; 32]>,
+		target_root: Option<[u8; 32]>,
+	) -> Result<(), TreeError> {
+		for val in vals {
+			if let Some(root) = target_root {
+				if self.root.to_bytes() == root {
+					break;
+				}
+			}
+			self.update(self.curr_index, Scalar::from_bytes_mod_order(val))?;
+			self.curr_index += Scalar::one();
+		}
+		Ok(())
+	}
+
+	pub fn update(
+		&mut self,
+		idx: Scalar,
+		val: Scalar,
+	) -> Result<Scalar, TreeError> {
+		let digits = self.index_digits(&idx)?;
+		let mut proof = Some(Vec::new());
+		self.get(idx, self.root, &mut proof)?;
+		let path = proof.unwrap_or_default();
+
+		let mut cur_val = val;
+		for (digit, siblings) in digits.iter().zip(path.iter()) {
+			let children = children(*digit, cur_val, siblings);
+			cur_val = Poseidon_hash_4(children, &self.hash_params);
+			self.db.insert(cur_val.to_bytes(), children);
+		}
+
+		self.root = cur_val;
+		Ok(cur_val)
+	}
+
+	/// Get a value from tree, if `proof` is not None, populate `proof` with the
+	/// siblings of every node on the path from the leaf to the root.
+	pub fn get(
+		&self,
+		idx: Scalar,
+		root: Scalar,
+		proof: &mut Option<Vec<QuaternarySiblings>>,
+	) -> Result<Scalar, TreeError> {
+		let digits = self.index_digits(&idx)?;
+		let mut cur_node = root;
+		let mut proof_vec = Vec::<QuaternarySiblings>::new();
+
+		for digit in digits.iter().rev() {
+			let children = self
+				.db
+				.get(&cur_node.to_bytes())
+				.ok_or(TreeError::UnknownRoot)?;
+			cur_node = children[*digit];
+			if proof.is_some() {
+				proof_vec.push(siblings(*digit, children));
+			}
+		}
+		proof_vec.reverse();
+		if let Some(v) = proof {
+			v.extend_from_slice(&proof_vec);
+		}
+
+		Ok(cur_node)
+	}
+
+	/// Verify a merkle proof, if `root` is None, use the current root else use
+	/// given root
+	pub fn verify_proof(
+		&self,
+		idx: Scalar,
+		val: Scalar,
+		proof: &[QuaternarySiblings],
+		root: Option<&Scalar>,
+	) -> Result<bool, TreeError> {
+		if proof.len() != self.depth {
+			return Err(TreeError::ProofLengthMismatch {
+				expected: self.depth,
+				found: proof.len(),
+			});
+		}
+		let digits = self.index_digits(&idx)?;
+		let mut cur_val = val;
+		for (digit, siblings) in digits.iter().zip(proof.iter()) {
+			let children = children(*digit, cur_val, siblings);
+			cur_val = Poseidon_hash_4(children, &self.hash_params);
+		}
+
+		Ok(match root {
+			Some(r) => cur_val == *r,
+			None => cur_val == self.root,
+		})
+	}
+
+	/// Prove membership of the leaf at `index` under `root`, see
+	/// `quaternary_merkle_tree_verif_gadget`.
+	#[cfg(feature = "std")]
+	pub fn prove_zk_at(
+		&self,
+		root: Scalar,
+		index: Scalar,
+		bp_gens: &BulletproofGens,
+		mut prover: Prover,
+	) -> Result<(R1CSProof, MembershipProofComms), TreeError> {
+		let mut test_rng = OsRng::default();
+		let mut merkle_proof = Some(Vec::new());
+		let leaf = self.get(index, root, &mut merkle_proof)?;
+		let merkle_proof_vec = merkle_proof.unwrap_or_default();
+
+		let (com_leaf, var_leaf) =
+			prover.commit(leaf, Scalar::random(&mut test_rng));
+		let leaf_alloc_scalar = AllocatedScalar {
+			variable: var_leaf,
+			assignment: Some(leaf),
+		};
+
+		let (com_leaf_index, var_leaf_index) =
+			prover.commit(index, Scalar::random(&mut test_rng));
+		let leaf_index_alloc_scalar = AllocatedScalar {
+			variable: var_leaf_index,
+			assignment: Some(index),
+		};
+
+		let proof_nodes: Vec<Scalar> = merkle_proof_vec
+			.iter()
+			.flat_map(|s| s.iter())
+			.cloned()
+			.collect();
+		let proof_alloc_scalars =
+			allocate_scalars(&mut prover, 3 * self.depth, Some(&proof_nodes))?;
+
+		let num_statics = 2;
+		let statics = allocate_statics_for_prover(&mut prover, num_statics);
+
+		quaternary_merkle_tree_verif_gadget(
+			&mut prover,
+			self.depth,
+			&root,
+			leaf_alloc_scalar,
+			leaf_index_alloc_scalar,
+			proof_alloc_scalars,
+			statics,
+			&self.hash_params,
+		)?;
+
+		let proof = prover.prove_with_rng(bp_gens, &mut test_rng)?;
+		Ok((proof, (com_leaf, com_leaf_index)))
+	}
+
+	/// Base 4 digits of `idx`, from the leaf level up, errors if `idx` does
+	/// not fit in the tree
+	fn index_digits(&self, idx: &Scalar) -> Result<Vec<usize>, TreeError> {
+		let bits = get_bits(&idx.reduce(), 256);
+		let num_bits = 2 * self.depth;
+		if num_bits < 256 && bits[num_bits..].iter().any(|b| *b != 0) {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		Ok((0..self.depth)
+			.map(|i| (bits[2 * i] + 2 * bits[2 * i + 1]) as usize)
+			.collect())
+	}
+}
+
+/// Put `node` in slot `digit` and the siblings in the other slots
+fn children(
+	digit: usize,
+	node: Scalar,
+	siblings: &QuaternarySiblings,
+) -> QuaternaryDBVal {
+	let mut children = [Scalar::zero(); 4];
+	let mut others = siblings.iter();
+	for (slot, child) in children.iter_mut().enumerate() {
+		*child = if slot == digit {
+			node
+		} else {
+			*others.next().unwrap()
+		};
+	}
+	children
+}
+
+/// The children other than the one in slot `digit`
+fn siblings(digit: usize, children: &QuaternaryDBVal) -> QuaternarySiblings {
+	let mut siblings = [Scalar::zero(); 3];
+	let others = children
+		.iter()
+		.enumerate()
+		.filter(|(slot, _)| *slot != digit)
+		.map(|(_, child)| child);
+	for (sibling, child) in siblings.iter_mut().zip(others) {
+		*sibling = *child;
+	}
+	siblings
+}
+
+/// Roots of empty subtrees of height 0 to `depth`, starting with the zero leaf
+pub fn gen_quaternary_zero_tree(
+	depth: usize,
+	hash_params: &Poseidon,
+) -> Vec<Scalar> {
+	let mut zero_hashes = vec![Scalar::zero()];
+	for i in 0..depth {
+		let prev = zero_hashes[i];
+		zero_hashes.push(Poseidon_hash_4([prev; 4], hash_params));
+	}
+	zero_hashes
+}
+
+/// Membership of `leaf_val` at the committed `leaf_index` of a quaternary
+/// tree. The index is decomposed into 2 bits per level, LSB first, and
+/// `proof_nodes` holds the 3 siblings of every level, as returned by
+/// `QuaternarySparseMerkleTree::get`. The proof nodes can be allocated with
+/// `allocate_scalars`.
+pub fn quaternary_merkle_tree_verif_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	root: &Scalar,
+	leaf_val: AllocatedScalar,
+	leaf_index: AllocatedScalar,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+
+	let leaf_index_bits = scalar_bits_gadget(cs, leaf_index, 2 * depth)?;
+	let mut prev_hash = LinearCombination::from(leaf_val.variable);
+	for i in 0..depth {
+		let siblings = [
+			proof_nodes[3 * i].variable.into(),
+			proof_nodes[3 * i + 1].variable.into(),
+			proof_nodes[3 * i + 2].variable.into(),
+		];
+		let children = quaternary_select_constraints(
+			cs,
+			prev_hash,
+			siblings,
+			leaf_index_bits[2 * i].variable.into(),
+			leaf_index_bits[2 * i + 1].variable.into(),
+		);
+		prev_hash = Poseidon_hash_4_constraints::<CS>(
+			cs,
+			children,
+			statics.clone(),
+			poseidon_params,
+		)?;
+	}
+
+	constrain_lc_with_scalar::<CS>(cs, prev_hash, root);
+
+	Ok(())
+}
+
+/// Puts `node` in slot `b0 + 2 * b1` and `siblings` in the other slots, in
+/// order. The bits are assumed to be boolean. With the slot indicators
+/// e0 = (1-b0)(1-b1), e1 = b0(1-b1), e2 = (1-b0)b1, e3 = b0b1
+/// slot0 = s0 + e0 * (node - s0)
+/// slot1 = s1 + e0 * (s0 - s1) + e1 * (node - s1)
+/// slot3 = s2 + e3 * (node - s2)
+/// and slot2 follows from the sum of the slots being node + s0 + s1 + s2,
+/// so the selection takes 5 multipliers.
+pub fn quaternary_select_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	node: LinearCombination,
+	siblings: [LinearCombination; 3],
+	b0: LinearCombination,
+	b1: LinearCombination,
+) -> [LinearCombination; 4] {
+	let [s0, s1, s2] = siblings;
+	let (_, _, b0_b1) = cs.multiply(b0.clone(), b1.clone());
+	let e0: LinearCombination = Variable::One() - b0.clone() - b1 + b0_b1;
+	let e1: LinearCombination = b0 - b0_b1;
+	let e3: LinearCombination = b0_b1.into();
+
+	let (_, _, d0) = cs.multiply(e0.clone(), node.clone() - s0.clone());
+	let slot0 = s0.clone() + d0;
+
+	let (_, _, d1_0) = cs.multiply(e0, s0.clone() - s1.clone());
+	let (_, _, d1_1) = cs.multiply(e1, node.clone() - s1.clone());
+	let slot1 = s1.clone() + d1_0 + d1_1;
+
+	let (_, _, d3) = cs.multiply(e3, node.clone() - s2.clone());
+	let slot3 = s2.clone() + d3;
+
+	let slot2 =
+		node + s0 + s1 + s2 - slot0.clone() - slot1.clone() - slot3.clone();
+
+	[slot0, slot1, slot2, slot3]
+}
@@ -4,25 +4,32 @@ use crate::{
 		allocate_statics_for_prover, builder::Poseidon, PoseidonSbox,
 		Poseidon_hash_2, Poseidon_hash_2_constraints,
 	},
+	smt::{
+		builder::DEFAULT_ROOT_HISTORY_SIZE, checkpoint::Checkpoint,
+		compressed::CompressedMerkleProof, error::TreeError,
+		incremental::Frontier, multiproof::MerkleMultiProof,
+	},
 	utils::{
-		constrain_lc_with_scalar, get_bits, AllocatedScalar, ScalarBits,
-		ScalarBytes,
+		allocate_scalars, constrain_boolean, constrain_lc_with_scalar,
+		get_bits, leaf_index_recomposition_gadget, scalar_bits_gadget,
+		scalar_to_u64, AllocatedScalar, ScalarBits, ScalarBytes,
 	},
 };
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use bulletproofs::{
-	r1cs::{
-		ConstraintSystem, LinearCombination, Prover, R1CSError, R1CSProof,
-		Variable,
-	},
+	r1cs::{ConstraintSystem, LinearCombination, Prover, R1CSError, R1CSProof},
 	BulletproofGens,
 };
 use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
 #[cfg(feature = "std")]
 use rand_core::OsRng;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use sp_std::collections::btree_map::BTreeMap;
 
 pub type DBVal = (Scalar, Scalar);
+/// Commitments to the leaf and the leaf index
+pub type MembershipProofComms = (CompressedRistretto, CompressedRistretto);
 
 // TODO: ABSTRACT HASH FUNCTION BETTER
 #[derive(Clone)]
@@ -31,12 +38,44 @@ pub struct VanillaSparseMerkleTree {
 	db: BTreeMap<ScalarBytes, DBVal>,
 	hash_params: Poseidon,
 	pub root: Scalar,
-	curr_index: Scalar,
-	pub(crate) leaf_indices: BTreeMap<ScalarBytes, Scalar>,
+	pub(crate) curr_index: Scalar,
+	/// Indices holding each leaf value, empty leaves are not indexed
+	pub(crate) leaf_indices: BTreeMap<ScalarBytes, Vec<Scalar>>,
+	/// Ring buffer of the most recent roots, oldest entries are overwritten
+	root_history: Vec<Scalar>,
+	root_history_size: usize,
+	current_root_index: usize,
+	/// `zero_hashes[i]` is the root of an empty subtree of height `i`
+	zero_hashes: Vec<Scalar>,
+	/// Checkpoints that can be rolled back to, oldest first
+	checkpoints: Vec<Checkpoint>,
 }
 
 impl VanillaSparseMerkleTree {
 	pub fn new(hash_params: Poseidon, depth: usize) -> VanillaSparseMerkleTree {
+		Self::empty(hash_params, depth, DEFAULT_ROOT_HISTORY_SIZE)
+	}
+
+	/// Create a tree that remembers the last `root_history_size` roots,
+	/// including the current one. Fails with `InvalidValue` if
+	/// `root_history_size` is 0 as the current root is always known.
+	pub fn with_root_history(
+		hash_params: Poseidon,
+		depth: usize,
+		root_history_size: usize,
+	) -> Result<VanillaSparseMerkleTree, TreeError> {
+		if root_history_size == 0 {
+			return Err(TreeError::InvalidValue);
+		}
+		Ok(Self::empty(hash_params, depth, root_history_size))
+	}
+
+	/// See `with_root_history`, `root_history_size` must not be 0
+	pub(crate) fn empty(
+		hash_params: Poseidon,
+		depth: usize,
+		root_history_size: usize,
+	) -> VanillaSparseMerkleTree {
 		let mut db = BTreeMap::new();
 		let zero_tree = gen_zero_tree(hash_params.width, &hash_params.sbox);
 		for i in 1..=depth {
@@ -46,7 +85,12 @@ impl VanillaSparseMerkleTree {
 			db.insert(curr, (prev, prev));
 		}
 
-		let root = Scalar::from_bytes_mod_order(zero_tree[depth]);
+		let zero_hashes: Vec<Scalar> = zero_tree
+			.iter()
+			.take(depth + 1)
+			.map(|z| Scalar::from_bytes_mod_order(*z))
+			.collect();
+		let root = zero_hashes[depth];
 
 		VanillaSparseMerkleTree {
 			depth,
@@ -55,6 +99,47 @@ impl VanillaSparseMerkleTree {
 			root,
 			curr_index: Scalar::zero(),
 			leaf_indices: BTreeMap::new(),
+			root_history: vec![root],
+			root_history_size,
+			current_root_index: 0,
+			zero_hashes,
+			checkpoints: Vec::new(),
+		}
+	}
+
+	/// Returns true if `root` is the current root or one of the roots
+	/// retained in the history window. Mirrors `isKnownRoot` of the Tornado
+	/// contracts, walking backwards from the most recent root.
+	pub fn is_known_root(&self, root: &Scalar) -> bool {
+		let len = self.root_history.len();
+		(0..len).any(|i| {
+			let pos = (self.current_root_index + len - i) % len;
+			self.root_history[pos] == *root
+		})
+	}
+
+	/// The retained roots, from oldest to most recent.
+	pub fn known_roots(&self) -> Vec<Scalar> {
+		let len = self.root_history.len();
+		(1..=len)
+			.map(|i| self.root_history[(self.current_root_index + i) % len])
+			.collect()
+	}
+
+	fn push_root(&mut self, root: Scalar) {
+		if let Some(checkpoint) = self.checkpoints.last_mut() {
+			if checkpoint.root_history.is_none() {
+				checkpoint.root_history =
+					Some((self.root_history.clone(), self.current_root_index));
+			}
+		}
+		if self.root_history.len() < self.root_history_size {
+			self.root_history.push(root);
+			self.current_root_index = self.root_history.len() - 1;
+		} else {
+			self.current_root_index =
+				(self.current_root_index + 1) % self.root_history_size;
+			self.root_history[self.current_root_index] = root;
 		}
 	}
 
@@ -66,7 +151,7 @@ impl VanillaSparseMerkleTree {
 		&mut self,
 		vals: Vec<[u8; 32]>,
 		target_root: Option<[u8; 32]>,
-	) {
+	) -> Result<(), TreeError> {
 		for val in vals {
 			// check if current root equals target root before inserting
 			// more leaves. This is necessary to prevent inconsistencies
@@ -76,18 +161,101 @@ impl VanillaSparseMerkleTree {
 					break;
 				}
 			}
-			self.update(self.curr_index, Scalar::from_bytes_mod_order(val));
+			self.check_index(&self.curr_index)?;
+			self.update(self.curr_index, Scalar::from_bytes_mod_order(val))?;
 			self.curr_index += Scalar::one();
 		}
+		Ok(())
+	}
+
+	/// Build a tree holding `leaves` at indices `0..leaves.len()`. The root,
+	/// the db, the leaf indices and the root history are the same as with
+	/// `add_leaves`, so every intermediate node is still hashed, `depth`
+	/// hashes per leaf. The nodes are hashed level by level rather than one
+	/// path per leaf, so that with the `parallel` feature each level is
+	/// hashed in parallel. The depth must be less than 64.
+	pub fn from_leaves(
+		hash_params: Poseidon,
+		depth: usize,
+		leaves: Vec<Scalar>,
+	) -> Result<VanillaSparseMerkleTree, TreeError> {
+		let mut tree = Self::new(hash_params, depth);
+		tree.fill_with_leaves(leaves)?;
+		Ok(tree)
+	}
+
+	/// See `from_leaves`, the tree must be empty
+	pub(crate) fn fill_with_leaves(
+		&mut self,
+		leaves: Vec<Scalar>,
+	) -> Result<(), TreeError> {
+		if self.depth >= 64 {
+			return Err(TreeError::InvalidValue);
+		}
+		if leaves.len() as u64 > 1 << self.depth {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		for (i, leaf) in leaves.iter().enumerate() {
+			self.index_leaf(*leaf, Scalar::from(i as u64));
+		}
+		self.curr_index = Scalar::from(leaves.len() as u64);
+
+		// `nodes[i]` is the node above leaf `i` at the current height once
+		// the leaves up to `i` are added, i.e. the nodes `update` stores
+		let mut nodes = leaves;
+		for height in 0..self.depth {
+			let parents = prefix_parents(
+				&nodes,
+				height,
+				self.zero_hashes[height],
+				&self.hash_params,
+			);
+			nodes.clear();
+			for (node, children) in parents {
+				self.update_db_with_key_val(node, children);
+				nodes.push(node);
+			}
+		}
+		for root in nodes {
+			self.root = root;
+			self.push_root(root);
+		}
+		Ok(())
 	}
 
-	pub fn update(&mut self, idx: Scalar, val: Scalar) -> Scalar {
+	/// The last leaf added with `add_leaves` and its path, used to start an
+	/// `IncrementalMerkleTree` that follows this tree.
+	pub fn frontier(&self) -> Result<Frontier, TreeError> {
+		let next_index = scalar_to_u64(&self.curr_index)
+			.ok_or(TreeError::IndexOutOfRange)?;
+		let last_index = if next_index == 0 {
+			self.curr_index
+		} else {
+			self.curr_index - Scalar::one()
+		};
+		let mut path = Some(Vec::new());
+		let leaf = self.get(last_index, self.root, &mut path)?;
+		Ok(Frontier {
+			next_index,
+			leaf,
+			path: path.unwrap_or_default(),
+		})
+	}
+
+	/// Set the leaf at `idx`, keys are truncated to their low `depth` bits so
+	/// any scalar can be used as a key. Returns the new root.
+	pub fn update(
+		&mut self,
+		idx: Scalar,
+		val: Scalar,
+	) -> Result<Scalar, TreeError> {
 		// Find path to insert the new key
-		let mut cur_idx = ScalarBits::from_scalar(&idx, self.depth);
+		let idx = self.truncate_index(&idx);
+		let mut cur_idx = self.index_bits(&idx);
 		let mut cur_val = val;
 		let mut sidenodes_wrap = Some(Vec::<Scalar>::new());
-		self.get(idx, self.root, &mut sidenodes_wrap);
-		let path = sidenodes_wrap.unwrap();
+		let old_val = self.get(idx, self.root, &mut sidenodes_wrap)?;
+		let path = sidenodes_wrap.unwrap_or_default();
 
 		for i in 0..self.depth {
 			let side_elem = path[i];
@@ -105,27 +273,68 @@ impl VanillaSparseMerkleTree {
 		}
 
 		self.root = cur_val;
-		self.leaf_indices.insert(val.to_bytes(), idx);
-		cur_val
+		self.push_root(cur_val);
+		self.unindex_leaf(old_val, idx);
+		self.index_leaf(val, idx);
+		Ok(cur_val)
+	}
+
+	/// The leaf at `index` under the current root
+	pub fn leaf_at(&self, index: Scalar) -> Result<Scalar, TreeError> {
+		self.check_index(&index)?;
+		self.get(index, self.root, &mut None)
+	}
+
+	/// The merkle path of the leaf at `index` under the current root
+	pub fn path_at(&self, index: Scalar) -> Result<Vec<Scalar>, TreeError> {
+		self.check_index(&index)?;
+		let mut proof = Some(Vec::new());
+		self.get(index, self.root, &mut proof)?;
+		Ok(proof.unwrap_or_default())
+	}
+
+	/// All indices currently holding `leaf`, in insertion order
+	pub fn indices_of(&self, leaf: Scalar) -> Vec<Scalar> {
+		self.leaf_indices
+			.get(&leaf.to_bytes())
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	/// The first index holding `leaf` under `root`
+	pub fn index_of(
+		&self,
+		leaf: Scalar,
+		root: Scalar,
+	) -> Result<Scalar, TreeError> {
+		for idx in self.indices_of(leaf) {
+			if self.get(idx, root, &mut None)? == leaf {
+				return Ok(idx);
+			}
+		}
+		Err(TreeError::UnknownLeaf)
 	}
 
 	/// Get a value from tree, if `proof` is not None, populate `proof` with the
-	/// merkle proof
+	/// merkle proof. Nodes are only removed from the db by `rollback_to`, so
+	/// `root` can be any root the tree had, not only the ones reported by
+	/// `is_known_root`. Fails with `UnknownRoot` otherwise. Like `update`, only
+	/// the low `depth` bits of `idx` are used.
 	pub fn get(
 		&self,
 		idx: Scalar,
 		root: Scalar,
 		proof: &mut Option<Vec<Scalar>>,
-	) -> Scalar {
-		let mut cur_idx = ScalarBits::from_scalar(&idx, self.depth);
-		let mut cur_node = root.clone();
+	) -> Result<Scalar, TreeError> {
+		let mut cur_idx = self.index_bits(&idx);
+		let mut cur_node = root;
 
 		let need_proof = proof.is_some();
 		let mut proof_vec = Vec::<Scalar>::new();
 
 		for _i in 0..self.depth {
 			let k = cur_node.to_bytes();
-			let v = self.db.get(&k).unwrap();
+			let v = self.db.get(&k).ok_or(TreeError::UnknownRoot)?;
 			if cur_idx.is_msb_set() {
 				// MSB is set, traverse to right subtree
 				cur_node = v.1;
@@ -149,7 +358,7 @@ impl VanillaSparseMerkleTree {
 			None => (),
 		}
 
-		cur_node
+		Ok(cur_node)
 	}
 
 	/// Verify a merkle proof, if `root` is None, use the current root else use
@@ -160,24 +369,22 @@ impl VanillaSparseMerkleTree {
 		val: Scalar,
 		proof: &[Scalar],
 		root: Option<&Scalar>,
-	) -> bool {
-		let mut cur_idx = ScalarBits::from_scalar(&idx, self.depth);
-		let mut cur_val = val.clone();
+	) -> Result<bool, TreeError> {
+		if proof.len() != self.depth {
+			return Err(TreeError::ProofLengthMismatch {
+				expected: self.depth,
+				found: proof.len(),
+			});
+		}
+		let mut cur_idx = self.index_bits(&idx);
+		let mut cur_val = val;
 
 		for i in 0..self.depth {
 			cur_val = {
 				if cur_idx.is_lsb_set() {
-					Poseidon_hash_2(
-						proof[i].clone(),
-						cur_val.clone(),
-						&self.hash_params,
-					)
+					Poseidon_hash_2(proof[i], cur_val, &self.hash_params)
 				} else {
-					Poseidon_hash_2(
-						cur_val.clone(),
-						proof[i].clone(),
-						&self.hash_params,
-					)
+					Poseidon_hash_2(cur_val, proof[i], &self.hash_params)
 				}
 			};
 
@@ -185,91 +392,334 @@ impl VanillaSparseMerkleTree {
 		}
 
 		// Check if root is equal to cur_val
-		match root {
+		Ok(match root {
 			Some(r) => cur_val == *r,
 			None => cur_val == self.root,
+		})
+	}
+
+	pub fn zero_hashes(&self) -> &[Scalar] { &self.zero_hashes }
+
+	/// Get a value from the tree along with its merkle proof, leaving out
+	/// the siblings that are roots of empty subtrees
+	pub fn get_compressed(
+		&self,
+		idx: Scalar,
+		root: Scalar,
+	) -> Result<(Scalar, CompressedMerkleProof), TreeError> {
+		self.check_index(&idx)?;
+		let mut proof = Some(Vec::new());
+		let val = self.get(idx, root, &mut proof)?;
+		let path = proof.unwrap_or_default();
+		let compressed =
+			CompressedMerkleProof::compress(&path, &self.zero_hashes)?;
+		Ok((val, compressed))
+	}
+
+	/// Rebuild the full merkle proof from a compressed one
+	pub fn expand_proof(
+		&self,
+		proof: &CompressedMerkleProof,
+	) -> Result<Vec<Scalar>, TreeError> {
+		proof.expand(&self.zero_hashes)
+	}
+
+	/// Verify a compressed merkle proof, see `verify_proof`
+	pub fn verify_compressed_proof(
+		&self,
+		idx: Scalar,
+		val: Scalar,
+		proof: &CompressedMerkleProof,
+		root: Option<&Scalar>,
+	) -> Result<bool, TreeError> {
+		self.check_index(&idx)?;
+		let path = self.expand_proof(proof)?;
+		self.verify_proof(idx, val, &path, root)
+	}
+
+	/// Get the leaves at `indices` under the current root along with a
+	/// single proof for all of them
+	pub fn multi_proof(
+		&self,
+		indices: &[Scalar],
+	) -> Result<(Vec<Scalar>, MerkleMultiProof), TreeError> {
+		let mut leaves = Vec::with_capacity(indices.len());
+		let mut paths = Vec::with_capacity(indices.len());
+		for idx in indices {
+			self.check_index(idx)?;
+			let mut proof = Some(Vec::new());
+			leaves.push(self.get(*idx, self.root, &mut proof)?);
+			paths.push((*idx, proof.unwrap_or_default()));
 		}
+		let proof = MerkleMultiProof::from_paths(self.depth, &paths)?;
+		Ok((leaves, proof))
 	}
 
+	/// Verify a multi proof of `(index, leaf)` pairs, if `root` is None, use
+	/// the current root else use given root
+	pub fn verify_multi_proof(
+		&self,
+		leaves: &[(Scalar, Scalar)],
+		proof: &MerkleMultiProof,
+		root: Option<&Scalar>,
+	) -> Result<bool, TreeError> {
+		for (idx, _) in leaves {
+			self.check_index(idx)?;
+		}
+		let cur_val = proof.root(self.depth, leaves, &self.hash_params)?;
+		Ok(match root {
+			Some(r) => cur_val == *r,
+			None => cur_val == self.root,
+		})
+	}
+
+	/// Index and merkle path of the empty subtree that the next `num_leaves`
+	/// leaves added with `add_leaves` will fill, for
+	/// `merkle_batch_insert_gadget`. `num_leaves` must be a power of two and
+	/// the next index a multiple of it.
+	pub fn batch_insert_path(
+		&self,
+		num_leaves: usize,
+	) -> Result<(Scalar, Vec<Scalar>), TreeError> {
+		if !num_leaves.is_power_of_two() {
+			return Err(TreeError::InvalidBatch);
+		}
+		let height = num_leaves.trailing_zeros() as usize;
+		if height > self.depth {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		let start = scalar_to_u64(&self.curr_index)
+			.ok_or(TreeError::IndexOutOfRange)?;
+		if start % num_leaves as u64 != 0 {
+			return Err(TreeError::InvalidBatch);
+		}
+		let path = self.path_at(self.curr_index)?.split_off(height);
+		let subtree_index = Scalar::from(start >> height);
+
+		// Leaves set with `update` could have filled part of the subtree
+		let mut cur_idx = ScalarBits::from_scalar(&subtree_index, path.len());
+		let mut cur_val = self.zero_hashes[height];
+		for node in path.iter() {
+			cur_val = if cur_idx.is_lsb_set() {
+				Poseidon_hash_2(*node, cur_val, &self.hash_params)
+			} else {
+				Poseidon_hash_2(cur_val, *node, &self.hash_params)
+			};
+			cur_idx.shr();
+		}
+		if cur_val != self.root {
+			return Err(TreeError::InvalidBatch);
+		}
+
+		Ok((subtree_index, path))
+	}
+
+	/// Prove membership of `leaf` under `root`. If the leaf is stored more
+	/// than once, the first index is used, see `prove_zk_at`.
 	#[cfg(feature = "std")]
 	pub fn prove_zk(
 		&self,
 		root: Scalar,
 		leaf: Scalar,
 		bp_gens: &BulletproofGens,
+		prover: Prover,
+	) -> Result<(R1CSProof, MembershipProofComms), TreeError> {
+		let k = self.index_of(leaf, root)?;
+		self.prove_zk_at(root, k, bp_gens, prover)
+	}
+
+	/// Prove membership of the leaf at `index` under `root`
+	#[cfg(feature = "std")]
+	pub fn prove_zk_at(
+		&self,
+		root: Scalar,
+		index: Scalar,
+		bp_gens: &BulletproofGens,
 		mut prover: Prover,
-	) -> (
-		R1CSProof,
-		(
-			CompressedRistretto,
-			Vec<CompressedRistretto>,
-			Vec<CompressedRistretto>,
-		),
-	) {
+	) -> Result<(R1CSProof, MembershipProofComms), TreeError> {
 		let mut test_rng = OsRng::default();
 		let mut merkle_proof_vec = Vec::<Scalar>::new();
 		let mut merkle_proof = Some(merkle_proof_vec);
-		let k = self.leaf_indices.get(&leaf.to_bytes()).unwrap();
-		let leaf = self.get(*k, root, &mut merkle_proof);
-		merkle_proof_vec = merkle_proof.unwrap();
+		self.check_index(&index)?;
+		let leaf = self.get(index, root, &mut merkle_proof)?;
+		merkle_proof_vec = merkle_proof.unwrap_or_default();
 
 		let (com_leaf, var_leaf) =
 			prover.commit(leaf, Scalar::random(&mut test_rng));
 		let leaf_alloc_scalar = AllocatedScalar {
 			variable: var_leaf,
-			assignment: Some(*k),
+			assignment: Some(leaf),
 		};
 
-		let mut leaf_index_comms = vec![];
-		let mut leaf_index_alloc_scalars = vec![];
-		for b in get_bits(&k, self.depth).iter().take(self.depth) {
-			let val: Scalar = Scalar::from(*b as u8);
-			let (c, v) =
-				prover.commit(val.clone(), Scalar::random(&mut test_rng));
-			leaf_index_comms.push(c);
-			leaf_index_alloc_scalars.push(AllocatedScalar {
-				variable: v,
-				assignment: Some(val),
-			});
-		}
+		let (com_leaf_index, var_leaf_index) =
+			prover.commit(index, Scalar::random(&mut test_rng));
+		let leaf_index_alloc_scalar = AllocatedScalar {
+			variable: var_leaf_index,
+			assignment: Some(index),
+		};
 
-		let mut proof_comms = vec![];
-		let mut proof_alloc_scalars = vec![];
-		for p in merkle_proof_vec.iter() {
-			let (c, v) = prover.commit(*p, Scalar::random(&mut test_rng));
-			proof_comms.push(c);
-			proof_alloc_scalars.push(AllocatedScalar {
-				variable: v,
-				assignment: Some(*p),
-			});
-		}
+		let proof_alloc_scalars =
+			allocate_scalars(&mut prover, self.depth, Some(&merkle_proof_vec))?;
 
 		let num_statics = 4;
 		let statics = allocate_statics_for_prover(&mut prover, num_statics);
 
-		assert!(vanilla_merkle_merkle_tree_verif_gadget(
+		vanilla_merkle_merkle_tree_committed_index_verif_gadget(
 			&mut prover,
 			self.depth,
-			&self.root,
+			&root,
 			leaf_alloc_scalar,
-			leaf_index_alloc_scalars,
+			leaf_index_alloc_scalar,
 			proof_alloc_scalars,
 			statics,
-			&self.hash_params
-		)
-		.is_ok());
+			&self.hash_params,
+		)?;
+
+		let proof = prover.prove_with_rng(bp_gens, &mut test_rng)?;
+		Ok((proof, (com_leaf, com_leaf_index)))
+	}
+
+	/// Bits of `idx`, LSB first, errors if `idx` does not fit in the tree
+	/// The low `depth` bits of `idx`, keys are truncated to a leaf index
+	fn index_bits(&self, idx: &Scalar) -> ScalarBits {
+		ScalarBits::from_scalar(idx, self.depth)
+	}
+
+	/// The leaf index addressed by the key `idx`
+	fn truncate_index(&self, idx: &Scalar) -> Scalar {
+		let mut bytes = idx.reduce().to_bytes();
+		for i in self.depth.min(256)..256 {
+			bytes[i / 8] &= !(1 << (i % 8));
+		}
+		Scalar::from_bits(bytes)
+	}
+
+	/// Reject indices that do not fit in the tree, used by the APIs that take
+	/// a leaf index rather than a key
+	fn check_index(&self, idx: &Scalar) -> Result<(), TreeError> {
+		let bits = get_bits(&idx.reduce(), 256);
+		if self.depth < 256 && bits[self.depth..].iter().any(|b| *b != 0) {
+			return Err(TreeError::IndexOutOfRange);
+		}
+		Ok(())
+	}
+
+	fn index_leaf(&mut self, val: Scalar, idx: Scalar) {
+		if val == Scalar::zero() {
+			return;
+		}
+		self.journal_leaf_indices(val);
+		let indices = self.leaf_indices.entry(val.to_bytes()).or_default();
+		if !indices.contains(&idx) {
+			indices.push(idx);
+		}
+	}
+
+	fn unindex_leaf(&mut self, val: Scalar, idx: Scalar) {
+		let key = val.to_bytes();
+		if self.leaf_indices.contains_key(&key) {
+			self.journal_leaf_indices(val);
+		}
+		if let Some(indices) = self.leaf_indices.get_mut(&key) {
+			indices.retain(|i| *i != idx);
+			if indices.is_empty() {
+				self.leaf_indices.remove(&key);
+			}
+		}
+	}
 
-		let proof = prover.prove_with_rng(bp_gens, &mut test_rng).unwrap();
-		(proof, (com_leaf, leaf_index_comms, proof_comms))
+	/// Save the indices of `val` in the latest checkpoint before they are
+	/// first changed
+	fn journal_leaf_indices(&mut self, val: Scalar) {
+		if let Some(checkpoint) = self.checkpoints.last_mut() {
+			let leaf_indices = &self.leaf_indices;
+			checkpoint
+				.leaf_indices
+				.entry(val.to_bytes())
+				.or_insert_with_key(|key| {
+					leaf_indices.get(key).cloned().unwrap_or_default()
+				});
+		}
 	}
 
 	fn update_db_with_key_val(&mut self, key: Scalar, val: DBVal) {
-		self.db.insert(key.to_bytes(), val);
+		let key = key.to_bytes();
+		if self.db.insert(key, val).is_none() {
+			if let Some(checkpoint) = self.checkpoints.last_mut() {
+				checkpoint.new_nodes.push(key);
+			}
+		}
+	}
+
+	/// Remember the current state under `id` so the tree can be rolled back
+	/// to it, e.g. on a chain reorg. Ids must be increasing, such as block
+	/// numbers. Only what changes after the checkpoint is recorded, so it is
+	/// cheap to take one per block and `discard_checkpoints_before` the
+	/// finalized ones.
+	pub fn checkpoint(&mut self, id: u64) -> Result<(), TreeError> {
+		if let Some(last) = self.checkpoints.last() {
+			if last.id >= id {
+				return Err(TreeError::InvalidCheckpoint);
+			}
+		}
+		self.checkpoints
+			.push(Checkpoint::new(id, self.root, self.curr_index));
+		Ok(())
+	}
+
+	/// Restore the leaves, next index, leaf indices, root and root history
+	/// to what they were at checkpoint `id`. The checkpoint is kept, the
+	/// ones taken after it are dropped.
+	pub fn rollback_to(&mut self, id: u64) -> Result<(), TreeError> {
+		let pos = self
+			.checkpoints
+			.iter()
+			.position(|c| c.id == id)
+			.ok_or(TreeError::UnknownCheckpoint)?;
+		let undone: Vec<Checkpoint> =
+			self.checkpoints.drain(pos..).rev().collect();
+		for checkpoint in undone {
+			for key in checkpoint.new_nodes {
+				self.db.remove(&key);
+			}
+			for (key, indices) in checkpoint.leaf_indices {
+				if indices.is_empty() {
+					self.leaf_indices.remove(&key);
+				} else {
+					self.leaf_indices.insert(key, indices);
+				}
+			}
+			if let Some((root_history, current_root_index)) =
+				checkpoint.root_history
+			{
+				self.root_history = root_history;
+				self.current_root_index = current_root_index;
+			}
+			self.root = checkpoint.root;
+			self.curr_index = checkpoint.curr_index;
+		}
+		self.checkpoints
+			.push(Checkpoint::new(id, self.root, self.curr_index));
+		Ok(())
+	}
+
+	/// Forget the checkpoints older than `id`, which can no longer be rolled
+	/// back to
+	pub fn discard_checkpoints_before(&mut self, id: u64) {
+		let keep_from = self
+			.checkpoints
+			.iter()
+			.position(|c| c.id >= id)
+			.unwrap_or_else(|| self.checkpoints.len());
+		self.checkpoints.drain(..keep_from);
+	}
+
+	/// Ids of the checkpoints that can be rolled back to, oldest first
+	pub fn checkpoint_ids(&self) -> Vec<u64> {
+		self.checkpoints.iter().map(|c| c.id).collect()
 	}
 }
 
-/// left = (1-leaf_side) * leaf + (leaf_side * proof_node)
-/// right = leaf_side * leaf + ((1-leaf_side) * proof_node))
 pub fn vanilla_merkle_merkle_tree_verif_gadget<CS: ConstraintSystem>(
 	cs: &mut CS,
 	depth: usize,
@@ -280,46 +730,303 @@ pub fn vanilla_merkle_merkle_tree_verif_gadget<CS: ConstraintSystem>(
 	statics: Vec<AllocatedScalar>,
 	poseidon_params: &Poseidon,
 ) -> Result<(), R1CSError> {
-	let mut prev_hash = LinearCombination::default();
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+
+	for bit in &leaf_index_bits[..depth] {
+		constrain_boolean(cs, bit.variable.into());
+	}
+	let computed_root = merkle_path_root_constraints(
+		cs,
+		leaf_val.variable.into(),
+		&leaf_index_bits[..depth],
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
+
+	constrain_lc_with_scalar::<CS>(cs, computed_root, root);
+
+	Ok(())
+}
 
+/// Membership of `leaf_val` at the committed `leaf_index`, which is
+/// decomposed into `depth` bits in circuit. The proof nodes don't need to be
+/// committed, they can be allocated with `allocate_scalars`, so the verifier
+/// only needs the commitments to the leaf and the index.
+pub fn vanilla_merkle_merkle_tree_committed_index_verif_gadget<
+	CS: ConstraintSystem,
+>(
+	cs: &mut CS,
+	depth: usize,
+	root: &Scalar,
+	leaf_val: AllocatedScalar,
+	leaf_index: AllocatedScalar,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
 	let statics: Vec<LinearCombination> =
 		statics.iter().map(|s| s.variable.into()).collect();
 
-	for i in 0..depth {
-		let leaf_val_lc = if i == 0 {
-			LinearCombination::from(leaf_val.variable)
-		} else {
-			prev_hash.clone()
-		};
-		let one_minus_leaf_side: LinearCombination =
-			Variable::One() - leaf_index_bits[i].variable;
-
-		let (_, _, left_1) =
-			cs.multiply(one_minus_leaf_side.clone(), leaf_val_lc.clone());
-		let (_, _, left_2) = cs.multiply(
-			leaf_index_bits[i].variable.into(),
-			proof_nodes[i].variable.into(),
-		);
-		let left = left_1 + left_2;
+	let leaf_index_bits = scalar_bits_gadget(cs, leaf_index, depth)?;
+	let computed_root = merkle_path_root_constraints(
+		cs,
+		leaf_val.variable.into(),
+		&leaf_index_bits,
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
+
+	constrain_lc_with_scalar::<CS>(cs, computed_root, root);
 
-		let (_, _, right_1) =
-			cs.multiply(leaf_index_bits[i].variable.into(), leaf_val_lc);
-		let (_, _, right_2) =
-			cs.multiply(one_minus_leaf_side, proof_nodes[i].variable.into());
-		let right = right_1 + right_2;
+	Ok(())
+}
+
+/// Same as `vanilla_merkle_merkle_tree_verif_gadget`, and binds the index
+/// bits to `leaf_index`, which can be a committed variable or a public
+/// index, e.g. `LinearCombination::from(index)`.
+pub fn vanilla_merkle_merkle_tree_bound_index_verif_gadget<
+	CS: ConstraintSystem,
+>(
+	cs: &mut CS,
+	depth: usize,
+	root: &Scalar,
+	leaf_val: AllocatedScalar,
+	leaf_index: LinearCombination,
+	leaf_index_bits: Vec<AllocatedScalar>,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	leaf_index_recomposition_gadget(cs, &leaf_index_bits[..depth], leaf_index)?;
+	vanilla_merkle_merkle_tree_verif_gadget(
+		cs,
+		depth,
+		root,
+		leaf_val,
+		leaf_index_bits,
+		proof_nodes,
+		statics,
+		poseidon_params,
+	)
+}
+
+/// Proves that replacing `old_leaf` by `new_leaf` at the leaf index given by
+/// `leaf_index_bits` moves the tree from `old_root` to `new_root`. Both roots
+/// are computed from the same siblings, so no other leaf changes. Roots can
+/// be public, e.g. `LinearCombination::from(root)`, or committed variables.
+pub fn merkle_root_transition_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	old_root: LinearCombination,
+	new_root: LinearCombination,
+	old_leaf: AllocatedScalar,
+	new_leaf: AllocatedScalar,
+	leaf_index_bits: Vec<AllocatedScalar>,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+
+	for bit in &leaf_index_bits[..depth] {
+		constrain_boolean(cs, bit.variable.into());
+	}
+	let computed_old_root = merkle_path_root_constraints(
+		cs,
+		old_leaf.variable.into(),
+		&leaf_index_bits[..depth],
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_old_root - old_root);
+
+	let computed_new_root = merkle_path_root_constraints(
+		cs,
+		new_leaf.variable.into(),
+		&leaf_index_bits[..depth],
+		&proof_nodes[..depth],
+		&statics,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_new_root - new_root);
+
+	Ok(())
+}
+
+/// Proves that appending `leaves` at `curr_index`, the index of the first
+/// new leaf, moves the tree from `old_root` to `new_root`. The index can be
+/// public, e.g. `LinearCombination::from(index)`, or a committed variable.
+///
+/// Only aligned batches are supported: the number of leaves must be a power
+/// of two, `2^s`, and `curr_index` a multiple of it, so that the batch fills
+/// a whole empty subtree of height `s`. The subtree is hashed in circuit and
+/// a single path of `depth - s` siblings is used for both roots. The bits of
+/// `subtree_index_bits` are bound to `curr_index >> s`, which also rejects
+/// unaligned indices. See `VanillaSparseMerkleTree::batch_insert_path`.
+pub fn merkle_batch_insert_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	old_root: LinearCombination,
+	new_root: LinearCombination,
+	curr_index: LinearCombination,
+	leaves: Vec<AllocatedScalar>,
+	subtree_index_bits: Vec<AllocatedScalar>,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	if !leaves.len().is_power_of_two() {
+		return Err(R1CSError::GadgetError {
+			description: String::from(
+				"Batch size must be a power of two, unaligned batches are not \
+				 supported",
+			),
+		});
+	}
+	let height = leaves.len().trailing_zeros() as usize;
+	if height > depth {
+		return Err(R1CSError::GadgetError {
+			description: String::from("Batch does not fit in the tree"),
+		});
+	}
+	let path_len = depth - height;
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+
+	// Root of the subtree holding the new leaves
+	let mut level: Vec<LinearCombination> =
+		leaves.iter().map(|l| l.variable.into()).collect();
+	while level.len() > 1 {
+		let mut next = Vec::with_capacity(level.len() / 2);
+		for pair in level.chunks(2) {
+			next.push(Poseidon_hash_2_constraints::<CS>(
+				cs,
+				pair[0].clone(),
+				pair[1].clone(),
+				statics.clone(),
+				poseidon_params,
+			)?);
+		}
+		level = next;
+	}
+	let subtree_root = level.remove(0);
+
+	let zero_tree = gen_zero_tree(poseidon_params.width, &poseidon_params.sbox);
+	let empty_subtree = Scalar::from_bytes_mod_order(zero_tree[height]);
+	for bit in &subtree_index_bits[..path_len] {
+		constrain_boolean(cs, bit.variable.into());
+	}
+	// `curr_index / 2^s` is only an integer below `2^path_len` if the index
+	// is aligned to the subtree
+	let subtree_size = Scalar::from(leaves.len() as u64);
+	leaf_index_recomposition_gadget(
+		cs,
+		&subtree_index_bits[..path_len],
+		curr_index * subtree_size.invert(),
+	)?;
+	let computed_old_root = merkle_path_root_constraints(
+		cs,
+		empty_subtree.into(),
+		&subtree_index_bits[..path_len],
+		&proof_nodes[..path_len],
+		&statics,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_old_root - old_root);
+
+	let computed_new_root = merkle_path_root_constraints(
+		cs,
+		subtree_root,
+		&subtree_index_bits[..path_len],
+		&proof_nodes[..path_len],
+		&statics,
+		poseidon_params,
+	)?;
+	cs.constrain(computed_new_root - new_root);
+
+	Ok(())
+}
+
+/// Hashes `leaf` up along the path given by `proof_nodes` and returns the
+/// root. At every level the current node and the sibling are put in order
+/// by the index bit, LSB first, see `conditional_swap_constraints`. The bits
+/// are not constrained to be boolean here, callers have to do it once for
+/// all the paths using them.
+pub fn merkle_path_root_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	leaf: LinearCombination,
+	leaf_index_bits: &[AllocatedScalar],
+	proof_nodes: &[AllocatedScalar],
+	statics: &[LinearCombination],
+	poseidon_params: &Poseidon,
+) -> Result<LinearCombination, R1CSError> {
+	let mut prev_hash = leaf;
+
+	for (bit, node) in leaf_index_bits.iter().zip(proof_nodes.iter()) {
+		let (left, right) = conditional_swap_constraints(
+			cs,
+			bit.variable.into(),
+			prev_hash,
+			node.variable.into(),
+		);
 
 		prev_hash = Poseidon_hash_2_constraints::<CS>(
 			cs,
 			left,
 			right,
-			statics.clone(),
+			statics.to_vec(),
 			poseidon_params,
 		)?;
 	}
 
-	constrain_lc_with_scalar::<CS>(cs, prev_hash, root);
+	Ok(prev_hash)
+}
 
-	Ok(())
+/// The parents of `nodes` at `height`, as defined in `fill_with_leaves`,
+/// along with their children. The left child of a right node is complete,
+/// it is the node above the last leaf of the left subtree, and the right
+/// child of a left node is still empty. `height` must be less than 64.
+fn prefix_parents(
+	nodes: &[Scalar],
+	height: usize,
+	zero_hash: Scalar,
+	hash_params: &Poseidon,
+) -> Vec<(Scalar, DBVal)> {
+	let parent = |i: usize| {
+		let pos = i as u64 >> height;
+		let (l, r) = if pos % 2 == 0 {
+			(nodes[i], zero_hash)
+		} else {
+			(nodes[(pos << height) as usize - 1], nodes[i])
+		};
+		(Poseidon_hash_2(l, r, hash_params), (l, r))
+	};
+	#[cfg(feature = "parallel")]
+	let parents = (0..nodes.len()).into_par_iter().map(parent).collect();
+	#[cfg(not(feature = "parallel"))]
+	let parents = (0..nodes.len()).map(parent).collect();
+	parents
+}
+
+/// Returns `(cur, sibling)` if `bit` is 0 and `(sibling, cur)` if it is 1,
+/// using a single multiplier:
+/// d = bit * (sibling - cur)
+/// left = cur + d
+/// right = sibling - d
+/// `bit` is assumed to be boolean.
+pub fn conditional_swap_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	bit: LinearCombination,
+	cur: LinearCombination,
+	sibling: LinearCombination,
+) -> (LinearCombination, LinearCombination) {
+	let (_, _, d) = cs.multiply(bit, sibling.clone() - cur.clone());
+	(cur + d, sibling - d)
 }
 
 pub fn gen_zero_tree(width: usize, sbox: &PoseidonSbox) -> Vec<[u8; 32]> {
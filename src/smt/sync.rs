@@ -0,0 +1,207 @@
+use crate::{
+	smt::{error::TreeError, smt::VanillaSparseMerkleTree},
+	utils::ScalarBytes,
+};
+use alloc::vec::Vec;
+use codec::{Decode, Encode};
+use curve25519_dalek::scalar::Scalar;
+use sp_std::collections::btree_map::BTreeMap;
+
+/// A leaf appended to the tree, e.g. decoded from a chain event
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct LeafEvent {
+	#[codec(compact)]
+	pub index: u64,
+	pub leaf: ScalarBytes,
+}
+
+/// The leaves of a tree in insertion order along with the root they hash to
+#[derive(Clone, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct TreeSnapshot {
+	#[codec(compact)]
+	pub depth: u32,
+	pub leaves: Vec<ScalarBytes>,
+	pub root: ScalarBytes,
+}
+
+/// Keeps a `VanillaSparseMerkleTree` in sync with an ordered log of leaf
+/// insertions, as `add_leaves` would build it. The root is checkpointed every
+/// `checkpoint_interval` leaves, so a root reported by the chain for some
+/// number of leaves can be checked against the local tree.
+#[derive(Clone)]
+pub struct TreeSync {
+	tree: VanillaSparseMerkleTree,
+	leaves: Vec<Scalar>,
+	/// Root of the tree once the first `n` leaves were added
+	checkpoints: BTreeMap<u64, Scalar>,
+	checkpoint_interval: u64,
+}
+
+impl TreeSync {
+	/// Start syncing `tree`, errors with `InvalidSnapshot` if it is not empty
+	/// and with `InvalidValue` if `checkpoint_interval` is zero
+	pub fn new(
+		tree: VanillaSparseMerkleTree,
+		checkpoint_interval: u64,
+	) -> Result<TreeSync, TreeError> {
+		if checkpoint_interval == 0 {
+			return Err(TreeError::InvalidValue);
+		}
+		if tree.curr_index != Scalar::zero()
+			|| tree.root != tree.zero_hashes()[tree.depth]
+		{
+			return Err(TreeError::InvalidSnapshot);
+		}
+		let mut checkpoints = BTreeMap::new();
+		checkpoints.insert(0, tree.root);
+		Ok(TreeSync {
+			tree,
+			leaves: Vec::new(),
+			checkpoints,
+			checkpoint_interval,
+		})
+	}
+
+	/// Rebuild the leaves of `snapshot` into `tree`, which must be empty and
+	/// of the same depth. Fails if they don't hash to the snapshot root.
+	pub fn from_snapshot(
+		tree: VanillaSparseMerkleTree,
+		snapshot: &TreeSnapshot,
+		checkpoint_interval: u64,
+	) -> Result<TreeSync, TreeError> {
+		if snapshot.depth as usize != tree.depth {
+			return Err(TreeError::InvalidSnapshot);
+		}
+		let mut sync = TreeSync::new(tree, checkpoint_interval)?;
+		for (index, leaf) in snapshot.leaves.iter().enumerate() {
+			sync.apply(&LeafEvent {
+				index: index as u64,
+				leaf: *leaf,
+			})
+			.map_err(|e| match e {
+				TreeError::InvalidEvent => TreeError::InvalidSnapshot,
+				e => e,
+			})?;
+		}
+		if sync.tree.root.to_bytes() != snapshot.root {
+			return Err(TreeError::InvalidSnapshot);
+		}
+		Ok(sync)
+	}
+
+	/// The leaves synced so far and the current root
+	pub fn snapshot(&self) -> TreeSnapshot {
+		TreeSnapshot {
+			depth: self.tree.depth as u32,
+			leaves: self.leaves.iter().map(|l| l.to_bytes()).collect(),
+			root: self.tree.root.to_bytes(),
+		}
+	}
+
+	pub fn tree(&self) -> &VanillaSparseMerkleTree { &self.tree }
+
+	pub fn into_tree(self) -> VanillaSparseMerkleTree { self.tree }
+
+	pub fn root(&self) -> Scalar { self.tree.root }
+
+	/// Number of leaves synced so far, i.e. the index of the next event
+	pub fn leaf_count(&self) -> u64 { self.leaves.len() as u64 }
+
+	/// The checkpointed roots, keyed by the number of leaves they commit to
+	pub fn checkpoints(&self) -> &BTreeMap<u64, Scalar> { &self.checkpoints }
+
+	/// The root once the first `leaf_count` leaves were added, if it was
+	/// checkpointed
+	pub fn root_at(&self, leaf_count: u64) -> Option<Scalar> {
+		if leaf_count == self.leaf_count() {
+			return Some(self.tree.root);
+		}
+		self.checkpoints.get(&leaf_count).copied()
+	}
+
+	/// Append the leaf of `event` and return the new root. Events already
+	/// synced are skipped if they carry the same leaf, so a log can be
+	/// replayed from an earlier position.
+	pub fn apply(&mut self, event: &LeafEvent) -> Result<Scalar, TreeError> {
+		let leaf = Scalar::from_canonical_bytes(event.leaf)
+			.ok_or(TreeError::InvalidEvent)?;
+		let next = self.leaf_count();
+		if event.index < next {
+			if self.leaves[event.index as usize] != leaf {
+				return Err(TreeError::Diverged {
+					leaf_count: event.index,
+				});
+			}
+			return Ok(self.tree.root);
+		}
+		if event.index > next {
+			return Err(TreeError::MissingEvents {
+				expected: next,
+				found: event.index,
+			});
+		}
+		self.tree.add_leaves(vec![event.leaf], None)?;
+		self.leaves.push(leaf);
+		let count = self.leaf_count();
+		if count % self.checkpoint_interval == 0 {
+			self.checkpoints.insert(count, self.tree.root);
+		}
+		Ok(self.tree.root)
+	}
+
+	/// Apply `events` in order, stopping at the first error
+	pub fn apply_all(
+		&mut self,
+		events: &[LeafEvent],
+	) -> Result<Scalar, TreeError> {
+		for event in events {
+			self.apply(event)?;
+		}
+		Ok(self.tree.root)
+	}
+
+	/// Apply events until the root equals `target_root`. Returns true once
+	/// it does, false if the events ran out before, in which case more
+	/// events are needed.
+	pub fn sync_to(
+		&mut self,
+		events: &[LeafEvent],
+		target_root: &Scalar,
+	) -> Result<bool, TreeError> {
+		for event in events {
+			if self.tree.root == *target_root {
+				return Ok(true);
+			}
+			self.apply(event)?;
+		}
+		Ok(self.tree.root == *target_root)
+	}
+
+	/// Check the root the tree had with `leaf_count` leaves against
+	/// `expected_root`, e.g. a root reported on chain. Errors with
+	/// `Diverged` if they differ and `UnknownRoot` if that root was not
+	/// checkpointed.
+	pub fn verify_root(
+		&self,
+		leaf_count: u64,
+		expected_root: &Scalar,
+	) -> Result<(), TreeError> {
+		match self.root_at(leaf_count) {
+			Some(root) if root == *expected_root => Ok(()),
+			Some(_) => Err(TreeError::Diverged { leaf_count }),
+			None => Err(TreeError::UnknownRoot),
+		}
+	}
+
+	/// Decode a SCALE encoded list of events
+	pub fn decode_events(
+		mut bytes: &[u8],
+	) -> Result<Vec<LeafEvent>, TreeError> {
+		let events = Vec::<LeafEvent>::decode(&mut bytes)
+			.map_err(|_| TreeError::InvalidEvent)?;
+		if !bytes.is_empty() {
+			return Err(TreeError::InvalidEvent);
+		}
+		Ok(events)
+	}
+}
@@ -1,16 +1,25 @@
-use super::smt::*;
+use super::{
+	compressed::*, error::TreeError, incremental::*, map::*, multiproof::*,
+	quaternary::*, smt::*, sync::*,
+};
 use crate::{
 	poseidon::{
 		allocate_statics_for_prover, allocate_statics_for_verifier,
-		sbox::PoseidonSbox, PoseidonBuilder,
+		builder::Poseidon, sbox::PoseidonSbox, PoseidonBuilder,
+		Poseidon_hash_2_constraints,
 	},
 	smt::builder::{SparseMerkleTreeBuilder, DEFAULT_TREE_DEPTH},
-	utils::{get_bits, AllocatedScalar},
+	test_utils::{prove_and_verify, TestCircuit},
+	utils::{allocate_scalars, get_bits, AllocatedScalar},
 };
 use bulletproofs::{
-	r1cs::{Prover, Verifier},
+	r1cs::{
+		ConstraintSystem, LinearCombination, Prover, R1CSError, R1CSProof,
+		Variable, Verifier,
+	},
 	BulletproofGens, PedersenGens,
 };
+use codec::{Decode, Encode};
 use curve25519_dalek::scalar::Scalar;
 use merlin::Transcript;
 use rand_core::SeedableRng;
@@ -32,30 +41,75 @@ fn test_vanilla_sparse_merkle_tree() {
 
 	for i in 1..10 {
 		let s = Scalar::from(i as u32);
-		tree.update(s, s);
+		tree.update(s, s).unwrap();
 	}
 
 	for i in 1..10 {
 		let s = Scalar::from(i as u32);
-		assert_eq!(s, tree.get(s, tree.root, &mut None));
+		assert_eq!(s, tree.get(s, tree.root, &mut None).unwrap());
 		let mut proof_vec = Vec::<Scalar>::new();
 		let mut proof = Some(proof_vec);
-		assert_eq!(s, tree.get(s, tree.root, &mut proof));
+		assert_eq!(s, tree.get(s, tree.root, &mut proof).unwrap());
 		proof_vec = proof.unwrap();
-		assert!(tree.verify_proof(s, s, &proof_vec, None));
-		assert!(tree.verify_proof(s, s, &proof_vec, Some(&tree.root)));
+		assert!(tree.verify_proof(s, s, &proof_vec, None).unwrap());
+		assert!(tree
+			.verify_proof(s, s, &proof_vec, Some(&tree.root))
+			.unwrap());
 	}
 
 	let kvs: Vec<(Scalar, Scalar)> = (0..100)
 		.map(|_| (Scalar::random(&mut test_rng), Scalar::random(&mut test_rng)))
 		.collect();
 	for i in 0..kvs.len() {
-		tree.update(kvs[i].0, kvs[i].1);
+		tree.update(kvs[i].0, kvs[i].1).unwrap();
 	}
 
 	for i in 0..kvs.len() {
-		assert_eq!(kvs[i].1, tree.get(kvs[i].0, tree.root, &mut None));
+		assert_eq!(kvs[i].1, tree.get(kvs[i].0, tree.root, &mut None).unwrap());
 	}
+
+	assert_eq!(
+		tree.get(Scalar::one(), Scalar::random(&mut test_rng), &mut None),
+		Err(TreeError::UnknownRoot)
+	);
+	assert_eq!(
+		tree.verify_proof(Scalar::one(), Scalar::one(), &[], None),
+		Err(TreeError::ProofLengthMismatch {
+			expected: DEFAULT_TREE_DEPTH,
+			found: 0
+		})
+	);
+}
+
+#[test]
+fn test_vsmt_index_out_of_range() {
+	let mut tree = SparseMerkleTreeBuilder::new().build();
+	let too_big = Scalar::from(1u64 << DEFAULT_TREE_DEPTH);
+	let seven = Scalar::from(7u32);
+	tree.update(seven, seven).unwrap();
+
+	// Keys are truncated to the low bits, so `too_big` addresses index 0
+	tree.update(too_big, too_big).unwrap();
+	assert_eq!(tree.get(too_big, tree.root, &mut None), Ok(too_big));
+	assert_eq!(tree.leaf_at(Scalar::zero()), Ok(too_big));
+	assert_eq!(tree.indices_of(too_big), vec![Scalar::zero()]);
+
+	// Leaf indices have to fit in the tree
+	assert_eq!(tree.leaf_at(too_big), Err(TreeError::IndexOutOfRange));
+	assert_eq!(tree.path_at(too_big), Err(TreeError::IndexOutOfRange));
+	assert_eq!(
+		tree.get_compressed(too_big, tree.root).err(),
+		Some(TreeError::IndexOutOfRange)
+	);
+	assert_eq!(
+		tree.multi_proof(&[seven, too_big]).err(),
+		Some(TreeError::IndexOutOfRange)
+	);
+	let (leaves, proof) = tree.multi_proof(&[seven]).unwrap();
+	assert_eq!(
+		tree.verify_multi_proof(&[(too_big, leaves[0])], &proof, None),
+		Err(TreeError::IndexOutOfRange)
+	);
 }
 
 #[test]
@@ -73,16 +127,18 @@ fn test_vsmt_verif() {
 
 	for i in 1..=10 {
 		let s = Scalar::from(i as u32);
-		tree.update(s, s);
+		tree.update(s, s).unwrap();
 	}
 
 	let mut merkle_proof_vec = Vec::<Scalar>::new();
 	let mut merkle_proof = Some(merkle_proof_vec);
 	let k = Scalar::from(7u32);
-	assert_eq!(k, tree.get(k, tree.root, &mut merkle_proof));
+	assert_eq!(k, tree.get(k, tree.root, &mut merkle_proof).unwrap());
 	merkle_proof_vec = merkle_proof.unwrap();
-	assert!(tree.verify_proof(k, k, &merkle_proof_vec, None));
-	assert!(tree.verify_proof(k, k, &merkle_proof_vec, Some(&tree.root)));
+	assert!(tree.verify_proof(k, k, &merkle_proof_vec, None).unwrap());
+	assert!(tree
+		.verify_proof(k, k, &merkle_proof_vec, Some(&tree.root))
+		.unwrap());
 
 	let pc_gens = PedersenGens::default();
 	let bp_gens = BulletproofGens::new(16500, 1);
@@ -226,23 +282,26 @@ fn test_vsmt_prove_verif() {
 
 	for i in 1..=10 {
 		let s = Scalar::from(i as u32);
-		tree.update(s, s);
+		tree.update(s, s).unwrap();
 	}
 
 	let mut merkle_proof_vec = Vec::<Scalar>::new();
 	let mut merkle_proof = Some(merkle_proof_vec);
 	let k = Scalar::from(7u32);
-	assert_eq!(k, tree.get(k, tree.root, &mut merkle_proof));
+	assert_eq!(k, tree.get(k, tree.root, &mut merkle_proof).unwrap());
 	merkle_proof_vec = merkle_proof.unwrap();
-	assert!(tree.verify_proof(k, k, &merkle_proof_vec, None));
-	assert!(tree.verify_proof(k, k, &merkle_proof_vec, Some(&tree.root)));
+	assert!(tree.verify_proof(k, k, &merkle_proof_vec, None).unwrap());
+	assert!(tree
+		.verify_proof(k, k, &merkle_proof_vec, Some(&tree.root))
+		.unwrap());
 
 	let pc_gens = PedersenGens::default();
 	let bp_gens = BulletproofGens::new(16500, 1);
 	let mut prover_transcript = Transcript::new(b"VSMT");
 	let prover = Prover::new(&pc_gens, &mut prover_transcript);
 
-	let (proof, commitments) = tree.prove_zk(tree.root, k, &bp_gens, prover);
+	let (proof, commitments) =
+		tree.prove_zk(tree.root, k, &bp_gens, prover).unwrap();
 
 	// Verify part
 	let mut verifier_transcript = Transcript::new(b"VSMT");
@@ -253,35 +312,26 @@ fn test_vsmt_prove_verif() {
 		assignment: None,
 	};
 
-	let mut leaf_index_alloc_scalars = vec![];
-	for l in commitments.1 {
-		let v = verifier.commit(l);
-		leaf_index_alloc_scalars.push(AllocatedScalar {
-			variable: v,
-			assignment: None,
-		});
-	}
+	let var_leaf_index = verifier.commit(commitments.1);
+	let leaf_index_alloc_scalar = AllocatedScalar {
+		variable: var_leaf_index,
+		assignment: None,
+	};
 
-	let mut proof_alloc_scalars = vec![];
-	for p in commitments.2 {
-		let v = verifier.commit(p);
-		proof_alloc_scalars.push(AllocatedScalar {
-			variable: v,
-			assignment: None,
-		});
-	}
+	let proof_alloc_scalars =
+		allocate_scalars(&mut verifier, tree.depth, None).unwrap();
 
 	let num_statics = 4;
 	let statics =
 		allocate_statics_for_verifier(&mut verifier, num_statics, &pc_gens);
 
 	let start = Instant::now();
-	assert!(vanilla_merkle_merkle_tree_verif_gadget(
+	assert!(vanilla_merkle_merkle_tree_committed_index_verif_gadget(
 		&mut verifier,
 		tree.depth,
 		&tree.root,
 		leaf_alloc_scalar,
-		leaf_index_alloc_scalars,
+		leaf_index_alloc_scalar,
 		proof_alloc_scalars,
 		statics,
 		&p_params
@@ -295,3 +345,1511 @@ fn test_vsmt_prove_verif() {
 
 	println!("Verification time is {:?}, sbox: {:?}", end, p_params.sbox);
 }
+
+#[test]
+fn test_vsmt_root_history() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let width = 6;
+	let p_params = PoseidonBuilder::new(width)
+		.sbox(PoseidonSbox::Inverse)
+		.build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.root_history_size(3)
+		.build();
+
+	let empty_root = tree.root;
+	assert!(tree.is_known_root(&empty_root));
+
+	let mut roots = vec![];
+	for i in 1..=5 {
+		let s = Scalar::from(i as u32);
+		tree.update(s, s).unwrap();
+		roots.push(tree.root);
+	}
+
+	// Only the last 3 roots are retained
+	assert!(!tree.is_known_root(&empty_root));
+	assert!(!tree.is_known_root(&roots[0]));
+	assert!(!tree.is_known_root(&roots[1]));
+	for root in &roots[2..] {
+		assert!(tree.is_known_root(root));
+	}
+	assert_eq!(tree.known_roots(), roots[2..].to_vec());
+
+	// Older roots are forgotten by the history but the tree can still be read
+	let two = Scalar::from(2u32);
+	assert_eq!(two, tree.get(two, roots[1], &mut None).unwrap());
+	assert_eq!(
+		Scalar::zero(),
+		tree.get(two, empty_root, &mut None).unwrap()
+	);
+	assert_eq!(
+		VanillaSparseMerkleTree::with_root_history(p_params.clone(), 4, 0)
+			.err(),
+		Some(TreeError::InvalidValue)
+	);
+
+	// Leaf 4 was inserted when `roots[3]` was the current root, leaf 5 was
+	// not yet in the tree
+	let old_root = roots[3];
+	let k = Scalar::from(4u32);
+	let mut merkle_proof = Some(Vec::<Scalar>::new());
+	assert_eq!(k, tree.get(k, old_root, &mut merkle_proof).unwrap());
+	let merkle_proof_vec = merkle_proof.unwrap();
+	assert!(tree
+		.verify_proof(k, k, &merkle_proof_vec, Some(&old_root))
+		.unwrap());
+	assert!(!tree.verify_proof(k, k, &merkle_proof_vec, None).unwrap());
+	let five = Scalar::from(5u32);
+	assert_eq!(Scalar::zero(), tree.get(five, old_root, &mut None).unwrap());
+
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	let mut prover_transcript = Transcript::new(b"VSMT");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+	let (proof, commitments) =
+		tree.prove_zk(old_root, k, &bp_gens, prover).unwrap();
+
+	let mut verifier_transcript = Transcript::new(b"VSMT");
+	let mut verifier = Verifier::new(&mut verifier_transcript);
+	let var_leaf = verifier.commit(commitments.0);
+	let leaf_alloc_scalar = AllocatedScalar {
+		variable: var_leaf,
+		assignment: None,
+	};
+
+	let var_leaf_index = verifier.commit(commitments.1);
+	let leaf_index_alloc_scalar = AllocatedScalar {
+		variable: var_leaf_index,
+		assignment: None,
+	};
+
+	let proof_alloc_scalars =
+		allocate_scalars(&mut verifier, tree.depth, None).unwrap();
+
+	let num_statics = 4;
+	let statics =
+		allocate_statics_for_verifier(&mut verifier, num_statics, &pc_gens);
+
+	assert!(vanilla_merkle_merkle_tree_committed_index_verif_gadget(
+		&mut verifier,
+		tree.depth,
+		&old_root,
+		leaf_alloc_scalar,
+		leaf_index_alloc_scalar,
+		proof_alloc_scalars,
+		statics,
+		&p_params
+	)
+	.is_ok());
+
+	assert!(verifier
+		.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+		.is_ok());
+}
+
+#[test]
+fn test_incremental_merkle_tree() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let width = 6;
+	let p_params = PoseidonBuilder::new(width)
+		.sbox(PoseidonSbox::Inverse)
+		.build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.build();
+	let mut inc_tree = IncrementalMerkleTree::new(p_params, DEFAULT_TREE_DEPTH);
+	assert_eq!(tree.root, inc_tree.root);
+
+	let mut witnesses: Vec<IncrementalWitness> = vec![];
+	for i in 0..20 {
+		let leaf = Scalar::random(&mut test_rng);
+		tree.add_leaves(vec![leaf.to_bytes()], None).unwrap();
+		assert_eq!(inc_tree.append(leaf).unwrap(), i);
+		assert_eq!(tree.root, inc_tree.root);
+
+		for w in witnesses.iter_mut() {
+			w.update(&inc_tree).unwrap();
+		}
+		if i == 0 || i == 3 || i == 10 {
+			witnesses.push(IncrementalWitness::from_tree(&inc_tree).unwrap());
+		}
+	}
+	assert_eq!(inc_tree.next_index(), 20);
+
+	for w in witnesses {
+		let idx = Scalar::from(w.index);
+		let mut merkle_proof = Some(Vec::<Scalar>::new());
+		assert_eq!(
+			w.leaf,
+			tree.get(idx, tree.root, &mut merkle_proof).unwrap()
+		);
+		assert_eq!(w.root, tree.root);
+		assert_eq!(w.path(), merkle_proof.unwrap());
+		assert!(tree
+			.verify_proof(idx, w.leaf, &w.path(), Some(&w.root))
+			.unwrap());
+	}
+}
+
+#[test]
+fn test_light_tree_tracked_leaves() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let width = 6;
+	let p_params = PoseidonBuilder::new(width)
+		.sbox(PoseidonSbox::Inverse)
+		.build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.build();
+
+	let leaves: Vec<[u8; 32]> = (0..13)
+		.map(|_| Scalar::random(&mut test_rng).to_bytes())
+		.collect();
+	tree.add_leaves(leaves, None).unwrap();
+
+	// The wallet only knows the current root and the frontier, and wants
+	// to follow its last deposit and two upcoming ones
+	let mut light_tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params)
+		.root(tree.root)
+		.frontier(tree.frontier().unwrap())
+		.tracked_leaves(vec![12, 15, 20])
+		.build_light()
+		.unwrap();
+	assert_eq!(light_tree.next_index(), 13);
+	assert!(light_tree.path(15).is_none());
+
+	for _ in 0..10 {
+		let leaf = Scalar::random(&mut test_rng);
+		tree.add_leaves(vec![leaf.to_bytes()], None).unwrap();
+		light_tree.append(leaf).unwrap();
+		assert_eq!(tree.root, light_tree.root);
+	}
+
+	for index in &[12u64, 15, 20] {
+		let index = *index;
+		let idx = Scalar::from(index);
+		let witness = light_tree.witness(index).unwrap();
+		let mut merkle_proof = Some(Vec::<Scalar>::new());
+		assert_eq!(
+			witness.leaf,
+			tree.get(idx, tree.root, &mut merkle_proof).unwrap()
+		);
+		let path = light_tree.path(index).unwrap();
+		assert_eq!(path, merkle_proof.unwrap());
+		assert!(tree.verify_proof(idx, witness.leaf, &path, None).unwrap());
+	}
+	assert!(light_tree.path(3).is_none());
+	assert_eq!(light_tree.frontier(), tree.frontier().unwrap());
+
+	// Leaf indices of a light tree are `u64`
+	assert_eq!(
+		SparseMerkleTreeBuilder::new().depth(64).build_light().err(),
+		Some(TreeError::InvalidValue)
+	);
+}
+
+#[test]
+fn test_light_tree_track_with_path() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let width = 6;
+	let p_params = PoseidonBuilder::new(width)
+		.sbox(PoseidonSbox::Inverse)
+		.build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.build();
+
+	let leaves: Vec<[u8; 32]> = (0..13)
+		.map(|_| Scalar::random(&mut test_rng).to_bytes())
+		.collect();
+	tree.add_leaves(leaves, None).unwrap();
+
+	// The wallet restores the tree from the frontier and seeds the
+	// witnesses of the notes it already owns from their paths
+	let mut light_tree = IncrementalMerkleTree::from_frontier(
+		p_params,
+		DEFAULT_TREE_DEPTH,
+		tree.frontier().unwrap(),
+	)
+	.unwrap();
+	let owned = [2u64, 7];
+	for index in owned.iter() {
+		let idx = Scalar::from(*index);
+		light_tree
+			.track_with_path(
+				*index,
+				tree.leaf_at(idx).unwrap(),
+				tree.path_at(idx).unwrap(),
+			)
+			.unwrap();
+	}
+
+	let idx = Scalar::from(3u32);
+	let leaf = tree.leaf_at(idx).unwrap();
+	let path = tree.path_at(idx).unwrap();
+	assert_eq!(
+		light_tree.track_with_path(3, leaf + Scalar::one(), path.clone()),
+		Err(TreeError::InvalidProof)
+	);
+	assert_eq!(
+		light_tree.track_with_path(13, leaf, path.clone()),
+		Err(TreeError::IndexOutOfRange)
+	);
+	assert_eq!(
+		light_tree.track_with_path(3, leaf, path[1..].to_vec()),
+		Err(TreeError::ProofLengthMismatch {
+			expected: DEFAULT_TREE_DEPTH,
+			found: DEFAULT_TREE_DEPTH - 1,
+		})
+	);
+	assert!(light_tree.path(3).is_none());
+
+	for _ in 0..10 {
+		let leaf = Scalar::random(&mut test_rng);
+		tree.add_leaves(vec![leaf.to_bytes()], None).unwrap();
+		light_tree.append(leaf).unwrap();
+	}
+	for index in owned.iter() {
+		let idx = Scalar::from(*index);
+		let path = light_tree.path(*index).unwrap();
+		assert_eq!(path, tree.path_at(idx).unwrap());
+		assert_eq!(light_tree.witness(*index).unwrap().root, tree.root);
+	}
+}
+
+/// Verifies a proof produced by `VanillaSparseMerkleTree::prove_zk`
+fn verify_prove_zk(
+	tree: &VanillaSparseMerkleTree,
+	root: &Scalar,
+	proof: R1CSProof,
+	commitments: MembershipProofComms,
+	p_params: &Poseidon,
+	test_rng: &mut ChaChaRng,
+) -> bool {
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	let mut verifier_transcript = Transcript::new(b"VSMT");
+	let mut verifier = Verifier::new(&mut verifier_transcript);
+	let var_leaf = verifier.commit(commitments.0);
+	let leaf_alloc_scalar = AllocatedScalar {
+		variable: var_leaf,
+		assignment: None,
+	};
+
+	let var_leaf_index = verifier.commit(commitments.1);
+	let leaf_index_alloc_scalar = AllocatedScalar {
+		variable: var_leaf_index,
+		assignment: None,
+	};
+
+	let proof_alloc_scalars =
+		allocate_scalars(&mut verifier, tree.depth, None).unwrap();
+
+	let num_statics = 4;
+	let statics =
+		allocate_statics_for_verifier(&mut verifier, num_statics, &pc_gens);
+
+	vanilla_merkle_merkle_tree_committed_index_verif_gadget(
+		&mut verifier,
+		tree.depth,
+		root,
+		leaf_alloc_scalar,
+		leaf_index_alloc_scalar,
+		proof_alloc_scalars,
+		statics,
+		p_params,
+	)
+	.is_ok() && verifier
+		.verify_with_rng(&proof, &pc_gens, &bp_gens, test_rng)
+		.is_ok()
+}
+
+#[test]
+fn test_vsmt_duplicate_leaves() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let width = 6;
+	let p_params = PoseidonBuilder::new(width)
+		.sbox(PoseidonSbox::Inverse)
+		.build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.build();
+
+	// The same commitment is deposited twice
+	let leaf = Scalar::random(&mut test_rng);
+	let other = Scalar::random(&mut test_rng);
+	let leaves = vec![other, leaf, other, leaf, other];
+	tree.add_leaves(leaves.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+
+	let (one, three) = (Scalar::from(1u32), Scalar::from(3u32));
+	assert_eq!(tree.indices_of(leaf), vec![one, three]);
+	assert_eq!(tree.index_of(leaf, tree.root).unwrap(), one);
+	assert_eq!(tree.leaf_at(three).unwrap(), leaf);
+	let path = tree.path_at(three).unwrap();
+	assert!(tree.verify_proof(three, leaf, &path, None).unwrap());
+
+	// Overwriting one of them keeps the other one provable
+	tree.update(one, other).unwrap();
+	assert_eq!(tree.indices_of(leaf), vec![three]);
+	assert_eq!(tree.indices_of(other).len(), 4);
+	assert_eq!(tree.leaf_at(one).unwrap(), other);
+	assert_eq!(
+		tree.index_of(Scalar::random(&mut test_rng), tree.root),
+		Err(TreeError::UnknownLeaf)
+	);
+
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	let mut prover_transcript = Transcript::new(b"VSMT");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let (proof, commitments) = tree
+		.prove_zk_at(tree.root, three, &bp_gens, prover)
+		.unwrap();
+	assert!(verify_prove_zk(
+		&tree,
+		&tree.root,
+		proof,
+		commitments,
+		&p_params,
+		&mut test_rng
+	));
+}
+
+#[test]
+fn test_vsmt_compressed_proof() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = SparseMerkleTreeBuilder::new().hash_params(p_params).build();
+	let leaves: Vec<Scalar> =
+		(0..5).map(|_| Scalar::random(&mut test_rng)).collect();
+	tree.add_leaves(leaves.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+
+	let idx = Scalar::from(2u32);
+	let (leaf, proof) = tree.get_compressed(idx, tree.root).unwrap();
+	assert_eq!(leaf, leaves[2]);
+	// Only the siblings inside the filled subtree of 8 leaves are not empty
+	assert_eq!(proof.depth as usize, DEFAULT_TREE_DEPTH);
+	assert_eq!(proof.nodes.len(), 3);
+	assert_eq!(proof.default_bitmap, vec![0xf8, 0xff, 0xff, 0xff]);
+
+	let decoded =
+		CompressedMerkleProof::decode(&mut &proof.encode()[..]).unwrap();
+	assert_eq!(decoded, proof);
+	assert_eq!(
+		tree.expand_proof(&decoded).unwrap(),
+		tree.path_at(idx).unwrap()
+	);
+	assert!(tree
+		.verify_compressed_proof(idx, leaf, &decoded, None)
+		.unwrap());
+	assert!(!tree
+		.verify_compressed_proof(idx, leaves[3], &decoded, None)
+		.unwrap());
+
+	// Malformed proofs are rejected instead of being expanded
+	let mut missing_node = proof.clone();
+	missing_node.nodes.pop();
+	assert_eq!(
+		tree.expand_proof(&missing_node),
+		Err(TreeError::InvalidProof)
+	);
+	let mut padded = proof.clone();
+	padded.default_bitmap.push(0);
+	assert_eq!(tree.expand_proof(&padded), Err(TreeError::InvalidProof));
+	let mut explicit_default = proof.clone();
+	explicit_default.default_bitmap[0] = 0xf0;
+	explicit_default
+		.nodes
+		.push(tree.zero_hashes()[3].to_bytes());
+	assert_eq!(
+		tree.expand_proof(&explicit_default),
+		Err(TreeError::InvalidProof)
+	);
+	let mut too_deep = proof;
+	too_deep.depth += 1;
+	assert_eq!(
+		tree.verify_compressed_proof(idx, leaf, &too_deep, None),
+		Err(TreeError::InvalidProof)
+	);
+}
+
+#[test]
+fn test_vsmt_multi_proof() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = SparseMerkleTreeBuilder::new().hash_params(p_params).build();
+	let leaves: Vec<Scalar> =
+		(0..12).map(|_| Scalar::random(&mut test_rng)).collect();
+	tree.add_leaves(leaves.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+
+	let indices: Vec<Scalar> =
+		[9u32, 2, 3, 11].iter().map(|i| Scalar::from(*i)).collect();
+	let (proof_leaves, proof) = tree.multi_proof(&indices).unwrap();
+	assert_eq!(
+		proof_leaves,
+		vec![leaves[9], leaves[2], leaves[3], leaves[11]]
+	);
+	// Leaves 2 and 3 are siblings and so are the parents of 9 and 11, so
+	// heights 0 and 1 need 3 nodes, height 2 needs one for each of the two
+	// subtrees, height 3 none and every height above needs one
+	assert_eq!(proof.nodes.len(), 3 + 2 + DEFAULT_TREE_DEPTH - 4);
+
+	let pairs: Vec<(Scalar, Scalar)> =
+		indices.iter().cloned().zip(proof_leaves).collect();
+	let decoded = MerkleMultiProof::decode(&mut &proof.encode()[..]).unwrap();
+	assert_eq!(decoded, proof);
+	assert!(tree.verify_multi_proof(&pairs, &decoded, None).unwrap());
+	// The order of the leaves does not matter
+	let mut reversed = pairs.clone();
+	reversed.reverse();
+	assert!(tree.verify_multi_proof(&reversed, &proof, None).unwrap());
+
+	let mut wrong_leaf = pairs.clone();
+	wrong_leaf[1].1 = leaves[4];
+	assert!(!tree.verify_multi_proof(&wrong_leaf, &proof, None).unwrap());
+	assert_eq!(
+		tree.verify_multi_proof(&[pairs[0], pairs[3]], &proof, None),
+		Err(TreeError::InvalidProof)
+	);
+	let mut short = proof.clone();
+	short.nodes.pop();
+	assert_eq!(
+		tree.verify_multi_proof(&pairs, &short, None),
+		Err(TreeError::InvalidProof)
+	);
+	let mut conflicting = pairs.clone();
+	conflicting.push((indices[0], leaves[0]));
+	assert_eq!(
+		tree.verify_multi_proof(&conflicting, &proof, None),
+		Err(TreeError::InvalidProof)
+	);
+
+	// An older root can still be checked against a proof made for it
+	let old_root = tree.root;
+	tree.update(Scalar::from(2u32), leaves[0]).unwrap();
+	assert!(!tree.verify_multi_proof(&pairs, &proof, None).unwrap());
+	assert!(tree
+		.verify_multi_proof(&pairs, &proof, Some(&old_root))
+		.unwrap());
+}
+
+#[test]
+fn test_vsmt_root_transition() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.depth(16)
+		.hash_params(p_params.clone())
+		.build();
+	for i in 1..=10 {
+		let s = Scalar::from(i as u32);
+		tree.update(s, s).unwrap();
+	}
+
+	let k = Scalar::from(7u32);
+	let old_root = tree.root;
+	let old_leaf = tree.leaf_at(k).unwrap();
+	let path = tree.path_at(k).unwrap();
+	let new_leaf = Scalar::random(&mut test_rng);
+	let new_root = tree.update(k, new_leaf).unwrap();
+
+	let index_bits: Vec<Scalar> = get_bits(&k, tree.depth)
+		.iter()
+		.take(tree.depth)
+		.map(|b| Scalar::from(*b as u8))
+		.collect();
+	let witness = (old_leaf, new_leaf, index_bits, path);
+
+	assert!(prove_root_transition(
+		&tree, old_root, new_root, true, &witness, &p_params
+	));
+	// The new root can be kept private as well
+	assert!(prove_root_transition(
+		&tree, old_root, new_root, false, &witness, &p_params
+	));
+	// Any other new root is rejected
+	let wrong_root = tree.update(k, Scalar::one()).unwrap();
+	assert!(!prove_root_transition(
+		&tree, old_root, wrong_root, true, &witness, &p_params
+	));
+}
+
+/// Prove and verify the transition of `witness = (old_leaf, new_leaf,
+/// index_bits, path)` from the public `old_root` to `new_root`, which is
+/// committed to unless `public_new_root` is set
+fn prove_root_transition(
+	tree: &VanillaSparseMerkleTree,
+	old_root: Scalar,
+	new_root: Scalar,
+	public_new_root: bool,
+	witness: &(Scalar, Scalar, Vec<Scalar>, Vec<Scalar>),
+	p_params: &Poseidon,
+) -> bool {
+	let mut test_rng = ChaChaRng::from_seed([2u8; 32]);
+	let (old_leaf, new_leaf, index_bits, path) = witness;
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+
+	let mut prover_transcript = Transcript::new(b"VSMTTransition");
+	let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let mut comms = vec![];
+	let mut allocs = vec![];
+	let values = [*old_leaf, *new_leaf]
+		.iter()
+		.chain(index_bits.iter())
+		.chain(path.iter())
+		.chain([new_root].iter())
+		.cloned()
+		.collect::<Vec<Scalar>>();
+	for v in values.iter() {
+		let (c, var) = prover.commit(*v, Scalar::random(&mut test_rng));
+		comms.push(c);
+		allocs.push(AllocatedScalar {
+			variable: var,
+			assignment: Some(*v),
+		});
+	}
+	let statics = allocate_statics_for_prover(&mut prover, 4);
+	let depth = tree.depth;
+	let new_root_lc = if public_new_root {
+		new_root.into()
+	} else {
+		allocs[2 + 2 * depth].variable.into()
+	};
+	merkle_root_transition_gadget(
+		&mut prover,
+		depth,
+		old_root.into(),
+		new_root_lc,
+		allocs[0],
+		allocs[1],
+		allocs[2..2 + depth].to_vec(),
+		allocs[2 + depth..2 + 2 * depth].to_vec(),
+		statics,
+		p_params,
+	)
+	.unwrap();
+	let proof = prover.prove_with_rng(&bp_gens, &mut test_rng).unwrap();
+
+	let mut verifier_transcript = Transcript::new(b"VSMTTransition");
+	let mut verifier = Verifier::new(&mut verifier_transcript);
+	let vars: Vec<AllocatedScalar> = comms
+		.iter()
+		.map(|c| AllocatedScalar {
+			variable: verifier.commit(*c),
+			assignment: None,
+		})
+		.collect();
+	let statics = allocate_statics_for_verifier(&mut verifier, 4, &pc_gens);
+	let new_root_lc = if public_new_root {
+		new_root.into()
+	} else {
+		vars[2 + 2 * depth].variable.into()
+	};
+	merkle_root_transition_gadget(
+		&mut verifier,
+		depth,
+		old_root.into(),
+		new_root_lc,
+		vars[0],
+		vars[1],
+		vars[2..2 + depth].to_vec(),
+		vars[2 + depth..2 + 2 * depth].to_vec(),
+		statics,
+		p_params,
+	)
+	.unwrap();
+	verifier
+		.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+		.is_ok()
+}
+
+#[test]
+fn test_vsmt_batch_insert() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.depth(16)
+		.hash_params(p_params.clone())
+		.build();
+	let first: Vec<Scalar> =
+		(0..4).map(|_| Scalar::random(&mut test_rng)).collect();
+	tree.add_leaves(first.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+
+	assert_eq!(tree.batch_insert_path(3), Err(TreeError::InvalidBatch));
+	assert_eq!(tree.batch_insert_path(8), Err(TreeError::InvalidBatch));
+	let (subtree_index, path) = tree.batch_insert_path(4).unwrap();
+	assert_eq!(subtree_index, Scalar::one());
+	assert_eq!(path.len(), tree.depth - 2);
+
+	let old_root = tree.root;
+	let batch: Vec<Scalar> =
+		(0..4).map(|_| Scalar::random(&mut test_rng)).collect();
+	tree.add_leaves(batch.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+	let new_root = tree.root;
+
+	let mut swapped = batch.clone();
+	swapped.swap(0, 1);
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	// The batch starts at leaf 4, a wrong or unaligned start is rejected
+	for (leaves, start, valid) in vec![
+		(batch.clone(), 4u64, true),
+		(swapped, 4, false),
+		(batch.clone(), 8, false),
+		(batch, 5, false),
+	] {
+		let bits: Vec<Scalar> = get_bits(&subtree_index, path.len())
+			.iter()
+			.take(path.len())
+			.map(|b| Scalar::from(*b as u8))
+			.collect();
+		let values: Vec<Scalar> = leaves
+			.iter()
+			.chain(bits.iter())
+			.chain(path.iter())
+			.cloned()
+			.collect();
+
+		let mut prover_transcript = Transcript::new(b"VSMTBatch");
+		let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+		let (comms, allocs): (Vec<_>, Vec<_>) = values
+			.iter()
+			.map(|v| {
+				let (c, var) = prover.commit(*v, Scalar::random(&mut test_rng));
+				(
+					c,
+					AllocatedScalar {
+						variable: var,
+						assignment: Some(*v),
+					},
+				)
+			})
+			.unzip();
+		let statics = allocate_statics_for_prover(&mut prover, 4);
+		merkle_batch_insert_gadget(
+			&mut prover,
+			tree.depth,
+			old_root.into(),
+			new_root.into(),
+			Scalar::from(start).into(),
+			allocs[..4].to_vec(),
+			allocs[4..4 + path.len()].to_vec(),
+			allocs[4 + path.len()..].to_vec(),
+			statics,
+			&p_params,
+		)
+		.unwrap();
+		let proof = prover.prove_with_rng(&bp_gens, &mut test_rng).unwrap();
+
+		let mut verifier_transcript = Transcript::new(b"VSMTBatch");
+		let mut verifier = Verifier::new(&mut verifier_transcript);
+		let vars: Vec<AllocatedScalar> = comms
+			.iter()
+			.map(|c| AllocatedScalar {
+				variable: verifier.commit(*c),
+				assignment: None,
+			})
+			.collect();
+		let statics = allocate_statics_for_verifier(&mut verifier, 4, &pc_gens);
+		merkle_batch_insert_gadget(
+			&mut verifier,
+			tree.depth,
+			old_root.into(),
+			new_root.into(),
+			Scalar::from(start).into(),
+			vars[..4].to_vec(),
+			vars[4..4 + path.len()].to_vec(),
+			vars[4 + path.len()..].to_vec(),
+			statics,
+			&p_params,
+		)
+		.unwrap();
+		let res =
+			verifier.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng);
+		assert_eq!(res.is_ok(), valid);
+	}
+
+	// The subtree was filled, the next batch of 8 starts at index 8
+	assert!(tree.batch_insert_path(8).is_ok());
+	tree.update(Scalar::from(9u32), Scalar::one()).unwrap();
+	assert_eq!(tree.batch_insert_path(8), Err(TreeError::InvalidBatch));
+}
+
+#[test]
+fn test_vsmt_from_leaves() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let builder = || {
+		SparseMerkleTreeBuilder::new()
+			.depth(5)
+			.hash_params(p_params.clone())
+			.root_history_size(16)
+	};
+	let mut leaves: Vec<Scalar> =
+		(0..13).map(|_| Scalar::random(&mut test_rng)).collect();
+	leaves[4] = leaves[1];
+	leaves[7] = Scalar::zero();
+
+	let mut expected = builder().build();
+	expected
+		.add_leaves(leaves.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+	let tree = builder().build_from_leaves(leaves.clone()).unwrap();
+	assert_eq!(tree.root, expected.root);
+	assert_eq!(tree.curr_index, expected.curr_index);
+	assert_eq!(tree.known_roots(), expected.known_roots());
+	assert_eq!(tree.indices_of(leaves[1]), expected.indices_of(leaves[1]));
+	assert!(tree.indices_of(Scalar::zero()).is_empty());
+
+	// Every intermediate root is known and has the same nodes
+	for root in expected.known_roots() {
+		for i in 0..32u32 {
+			let mut path = Some(Vec::new());
+			let mut expected_path = Some(Vec::new());
+			assert_eq!(
+				tree.get(Scalar::from(i), root, &mut path),
+				expected.get(Scalar::from(i), root, &mut expected_path)
+			);
+			assert_eq!(path, expected_path);
+		}
+	}
+
+	let tree =
+		VanillaSparseMerkleTree::from_leaves(p_params.clone(), 5, vec![])
+			.unwrap();
+	assert_eq!(tree.root, builder().build().root);
+	assert_eq!(tree.curr_index, Scalar::zero());
+	let full = vec![Scalar::one(); 32];
+	assert!(builder().build_from_leaves(full.clone()).is_ok());
+	assert_eq!(
+		VanillaSparseMerkleTree::from_leaves(p_params.clone(), 4, full).err(),
+		Some(TreeError::IndexOutOfRange)
+	);
+	assert_eq!(
+		VanillaSparseMerkleTree::from_leaves(p_params, 64, vec![]).err(),
+		Some(TreeError::InvalidValue)
+	);
+}
+
+#[test]
+fn test_quaternary_merkle_tree() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	// Holds as many leaves as a binary tree of depth 32
+	let depth = DEFAULT_TREE_DEPTH / 2;
+	let mut tree = QuaternarySparseMerkleTree::new(p_params.clone(), depth);
+	let leaves: Vec<Scalar> =
+		(0..6).map(|_| Scalar::random(&mut test_rng)).collect();
+	tree.add_leaves(leaves.iter().map(|l| l.to_bytes()).collect(), None)
+		.unwrap();
+	let huge = Scalar::from(u32::max_value()) + Scalar::one();
+	assert_eq!(
+		tree.update(huge, Scalar::one()),
+		Err(TreeError::IndexOutOfRange)
+	);
+	let last = Scalar::from(u32::max_value());
+	tree.update(last, leaves[0]).unwrap();
+
+	for &(idx, leaf) in
+		[(Scalar::from(5u32), leaves[5]), (last, leaves[0])].iter()
+	{
+		let mut proof = Some(Vec::new());
+		assert_eq!(tree.get(idx, tree.root, &mut proof).unwrap(), leaf);
+		let proof = proof.unwrap();
+		assert_eq!(proof.len(), depth);
+		assert!(tree.verify_proof(idx, leaf, &proof, None).unwrap());
+		assert!(!tree.verify_proof(idx, leaves[1], &proof, None).unwrap());
+	}
+
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+	for &idx in [
+		Scalar::from(4u32),
+		Scalar::from(5u32),
+		Scalar::from(10u32),
+		last,
+	]
+	.iter()
+	{
+		let mut prover_transcript = Transcript::new(b"QSMT");
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+		let (proof, commitments) =
+			tree.prove_zk_at(tree.root, idx, &bp_gens, prover).unwrap();
+
+		let mut verifier_transcript = Transcript::new(b"QSMT");
+		let mut verifier = Verifier::new(&mut verifier_transcript);
+		let commit =
+			|verifier: &mut Verifier<&mut Transcript>, c| AllocatedScalar {
+				variable: verifier.commit(c),
+				assignment: None,
+			};
+		let leaf = commit(&mut verifier, commitments.0);
+		let index = commit(&mut verifier, commitments.1);
+		let nodes = allocate_scalars(&mut verifier, 3 * depth, None).unwrap();
+		let statics = allocate_statics_for_verifier(&mut verifier, 2, &pc_gens);
+		quaternary_merkle_tree_verif_gadget(
+			&mut verifier,
+			depth,
+			&tree.root,
+			leaf,
+			index,
+			nodes,
+			statics,
+			&p_params,
+		)
+		.unwrap();
+		assert!(verifier
+			.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+			.is_ok());
+	}
+
+	// Half the levels, each with 5 more multipliers for the selection and
+	// decomposition of the extra index bit
+	let mut binary_transcript = Transcript::new(b"VSMT");
+	let mut binary = Prover::new(&pc_gens, &mut binary_transcript);
+	let leaf = commit_zeros(&mut binary, 1)[0];
+	let index = commit_zeros(&mut binary, 1)[0];
+	let nodes = commit_zeros(&mut binary, DEFAULT_TREE_DEPTH);
+	let statics = allocate_statics_for_prover(&mut binary, 4);
+	vanilla_merkle_merkle_tree_committed_index_verif_gadget(
+		&mut binary,
+		DEFAULT_TREE_DEPTH,
+		&Scalar::zero(),
+		leaf,
+		index,
+		nodes,
+		statics,
+		&p_params,
+	)
+	.unwrap();
+
+	let mut quaternary_transcript = Transcript::new(b"QSMT");
+	let mut quaternary = Prover::new(&pc_gens, &mut quaternary_transcript);
+	let leaf = commit_zeros(&mut quaternary, 1)[0];
+	let index = commit_zeros(&mut quaternary, 1)[0];
+	let nodes = commit_zeros(&mut quaternary, 3 * depth);
+	let statics = allocate_statics_for_prover(&mut quaternary, 2);
+	quaternary_merkle_tree_verif_gadget(
+		&mut quaternary,
+		depth,
+		&Scalar::zero(),
+		leaf,
+		index,
+		nodes,
+		statics,
+		&p_params,
+	)
+	.unwrap();
+
+	let hash_multipliers = (binary.num_multipliers() - 2 * DEFAULT_TREE_DEPTH)
+		/ DEFAULT_TREE_DEPTH;
+	assert_eq!(quaternary.num_multipliers(), depth * (7 + hash_multipliers));
+	assert!(quaternary.num_multipliers() < binary.num_multipliers());
+}
+
+/// Commit to `n` zeros, for the tests that only count the multipliers of a
+/// gadget
+fn commit_zeros(
+	prover: &mut Prover<&mut Transcript>,
+	n: usize,
+) -> Vec<AllocatedScalar> {
+	(0..n)
+		.map(|_| {
+			let (_, v) = prover.commit(Scalar::zero(), Scalar::zero());
+			AllocatedScalar {
+				variable: v,
+				assignment: Some(Scalar::zero()),
+			}
+		})
+		.collect()
+}
+
+/// `vanilla_merkle_merkle_tree_verif_gadget` as it was before it used
+/// `conditional_swap_constraints`, selecting each side with 2 multipliers:
+/// left = (1-leaf_side) * leaf + (leaf_side * proof_node)
+/// right = leaf_side * leaf + ((1-leaf_side) * proof_node))
+fn four_multiplier_path_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	depth: usize,
+	leaf_val: AllocatedScalar,
+	leaf_index_bits: Vec<AllocatedScalar>,
+	proof_nodes: Vec<AllocatedScalar>,
+	statics: Vec<AllocatedScalar>,
+	poseidon_params: &Poseidon,
+) -> Result<(), R1CSError> {
+	let mut prev_hash = LinearCombination::from(leaf_val.variable);
+	let statics: Vec<LinearCombination> =
+		statics.iter().map(|s| s.variable.into()).collect();
+	for i in 0..depth {
+		let one_minus_leaf_side: LinearCombination =
+			Variable::One() - leaf_index_bits[i].variable;
+		let (_, _, left_1) =
+			cs.multiply(one_minus_leaf_side.clone(), prev_hash.clone());
+		let (_, _, left_2) = cs.multiply(
+			leaf_index_bits[i].variable.into(),
+			proof_nodes[i].variable.into(),
+		);
+		let (_, _, right_1) =
+			cs.multiply(leaf_index_bits[i].variable.into(), prev_hash);
+		let (_, _, right_2) =
+			cs.multiply(one_minus_leaf_side, proof_nodes[i].variable.into());
+		prev_hash = Poseidon_hash_2_constraints::<CS>(
+			cs,
+			left_1 + left_2,
+			right_1 + right_2,
+			statics.clone(),
+			poseidon_params,
+		)?;
+	}
+	Ok(())
+}
+
+#[test]
+fn test_vsmt_path_gadget_multipliers() {
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let pc_gens = PedersenGens::default();
+	let depth = DEFAULT_TREE_DEPTH;
+	let multipliers = |four_multiplier_swap: bool| {
+		let mut prover_transcript = Transcript::new(b"VSMT");
+		let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+		let leaf = commit_zeros(&mut prover, 1)[0];
+		let bits = commit_zeros(&mut prover, depth);
+		let nodes = commit_zeros(&mut prover, depth);
+		let statics = allocate_statics_for_prover(&mut prover, 4);
+		if four_multiplier_swap {
+			four_multiplier_path_gadget(
+				&mut prover,
+				depth,
+				leaf,
+				bits,
+				nodes,
+				statics,
+				&p_params,
+			)
+		} else {
+			vanilla_merkle_merkle_tree_verif_gadget(
+				&mut prover,
+				depth,
+				&Scalar::zero(),
+				leaf,
+				bits,
+				nodes,
+				statics,
+				&p_params,
+			)
+		}
+		.unwrap();
+		prover.num_multipliers()
+	};
+
+	// One multiplier per level for the swap instead of 4, and one for the
+	// booleanity of the bit which wasn't checked before
+	let before = multipliers(true);
+	let after = multipliers(false);
+	assert_eq!(before - after, 2 * depth);
+}
+
+#[test]
+fn test_vsmt_index_bits_constraints() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.depth(16)
+		.hash_params(p_params.clone())
+		.build();
+	for i in 1..=10 {
+		let s = Scalar::from(i as u32);
+		tree.update(s, s).unwrap();
+	}
+
+	let k = Scalar::from(7u32);
+	let leaf = tree.leaf_at(k).unwrap();
+	let path = tree.path_at(k).unwrap();
+	let bits: Vec<Scalar> = get_bits(&k, tree.depth)
+		.iter()
+		.take(tree.depth)
+		.map(|b| Scalar::from(*b as u8))
+		.collect();
+	let root = tree.root;
+
+	let honest = (leaf, bits.clone(), path.clone());
+	assert!(prove_membership(&tree, root, &honest, None, &p_params));
+	assert!(prove_membership(&tree, root, &honest, Some(k), &p_params));
+	let wrong_index = Some(Scalar::from(6u32));
+	assert!(!prove_membership(
+		&tree,
+		root,
+		&honest,
+		wrong_index,
+		&p_params
+	));
+
+	// A non-binary bit blends the sibling into the node so that any leaf
+	// hashes to the same first node as the real leaf
+	let (l0, r0) = (path[0], leaf);
+	let fake_leaf = Scalar::random(&mut test_rng);
+	let fake_sibling = l0 + r0 - fake_leaf;
+	let fake_bit = (l0 - fake_leaf) * (fake_sibling - fake_leaf).invert();
+	let mut fake_bits = bits;
+	fake_bits[0] = fake_bit;
+	let mut fake_path = path;
+	fake_path[0] = fake_sibling;
+	assert_eq!(fake_leaf + fake_bit * (fake_sibling - fake_leaf), l0);
+	assert_eq!(fake_sibling - fake_bit * (fake_sibling - fake_leaf), r0);
+	let forged = (fake_leaf, fake_bits, fake_path);
+	assert!(!prove_membership(&tree, root, &forged, None, &p_params));
+}
+
+/// Prove and verify membership of `witness = (leaf, index_bits, path)`
+/// under `root`, binding the bits to the public `index` if given
+fn prove_membership(
+	tree: &VanillaSparseMerkleTree,
+	root: Scalar,
+	witness: &(Scalar, Vec<Scalar>, Vec<Scalar>),
+	index: Option<Scalar>,
+	p_params: &Poseidon,
+) -> bool {
+	let mut test_rng = ChaChaRng::from_seed([2u8; 32]);
+	let (leaf, bits, path) = witness;
+	let depth = tree.depth;
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16500, 1);
+
+	let mut prover_transcript = Transcript::new(b"VSMTMembership");
+	let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let values: Vec<Scalar> = [*leaf]
+		.iter()
+		.chain(bits.iter())
+		.chain(path.iter())
+		.cloned()
+		.collect();
+	let (comms, allocs): (Vec<_>, Vec<_>) = values
+		.iter()
+		.map(|v| {
+			let (c, var) = prover.commit(*v, Scalar::random(&mut test_rng));
+			(
+				c,
+				AllocatedScalar {
+					variable: var,
+					assignment: Some(*v),
+				},
+			)
+		})
+		.unzip();
+	let statics = allocate_statics_for_prover(&mut prover, 4);
+	let res = match index {
+		Some(index) => vanilla_merkle_merkle_tree_bound_index_verif_gadget(
+			&mut prover,
+			depth,
+			&root,
+			allocs[0],
+			index.into(),
+			allocs[1..1 + depth].to_vec(),
+			allocs[1 + depth..].to_vec(),
+			statics,
+			p_params,
+		),
+		None => vanilla_merkle_merkle_tree_verif_gadget(
+			&mut prover,
+			depth,
+			&root,
+			allocs[0],
+			allocs[1..1 + depth].to_vec(),
+			allocs[1 + depth..].to_vec(),
+			statics,
+			p_params,
+		),
+	};
+	assert!(res.is_ok());
+	let proof = prover.prove_with_rng(&bp_gens, &mut test_rng).unwrap();
+
+	let mut verifier_transcript = Transcript::new(b"VSMTMembership");
+	let mut verifier = Verifier::new(&mut verifier_transcript);
+	let vars: Vec<AllocatedScalar> = comms
+		.iter()
+		.map(|c| AllocatedScalar {
+			variable: verifier.commit(*c),
+			assignment: None,
+		})
+		.collect();
+	let statics = allocate_statics_for_verifier(&mut verifier, 4, &pc_gens);
+	let res = match index {
+		Some(index) => vanilla_merkle_merkle_tree_bound_index_verif_gadget(
+			&mut verifier,
+			depth,
+			&root,
+			vars[0],
+			index.into(),
+			vars[1..1 + depth].to_vec(),
+			vars[1 + depth..].to_vec(),
+			statics,
+			p_params,
+		),
+		None => vanilla_merkle_merkle_tree_verif_gadget(
+			&mut verifier,
+			depth,
+			&root,
+			vars[0],
+			vars[1..1 + depth].to_vec(),
+			vars[1 + depth..].to_vec(),
+			statics,
+			p_params,
+		),
+	};
+	assert!(res.is_ok());
+	verifier
+		.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+		.is_ok()
+}
+
+#[test]
+fn test_tree_sync() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let depth = 10;
+	let new_tree = || {
+		SparseMerkleTreeBuilder::new()
+			.hash_params(p_params.clone())
+			.depth(depth)
+			.build()
+	};
+
+	let leaves: Vec<[u8; 32]> = (0..12)
+		.map(|_| Scalar::random(&mut test_rng).to_bytes())
+		.collect();
+	let events: Vec<LeafEvent> = leaves
+		.iter()
+		.enumerate()
+		.map(|(i, leaf)| LeafEvent {
+			index: i as u64,
+			leaf: *leaf,
+		})
+		.collect();
+	let mut roots = vec![new_tree().root];
+	let mut tree = new_tree();
+	for leaf in &leaves {
+		tree.add_leaves(vec![*leaf], None).unwrap();
+		roots.push(tree.root);
+	}
+
+	// Events arrive SCALE encoded and in batches
+	let encoded = events.encode();
+	assert_eq!(TreeSync::decode_events(&encoded).unwrap(), events);
+	assert_eq!(
+		TreeSync::decode_events(&encoded[..encoded.len() - 1]),
+		Err(TreeError::InvalidEvent)
+	);
+	assert_eq!(
+		TreeSync::new(new_tree(), 0).err(),
+		Some(TreeError::InvalidValue)
+	);
+	let mut sync = TreeSync::new(new_tree(), 4).unwrap();
+	assert_eq!(sync.apply_all(&events[..6]).unwrap(), roots[6]);
+	assert_eq!(
+		sync.apply(&events[7]),
+		Err(TreeError::MissingEvents {
+			expected: 6,
+			found: 7
+		})
+	);
+	// Replaying events already synced is a no-op
+	assert_eq!(sync.apply_all(&events[2..9]).unwrap(), roots[9]);
+	assert_eq!(sync.leaf_count(), 9);
+
+	// Stop at the root the chain reports
+	assert!(sync.sync_to(&events[9..], &roots[10]).unwrap());
+	assert_eq!(sync.leaf_count(), 10);
+	assert!(!sync.sync_to(&[], &roots[12]).unwrap());
+	assert!(sync.sync_to(&events[10..], &roots[12]).unwrap());
+	assert_eq!(sync.tree().root, tree.root);
+	assert_eq!(sync.tree().frontier(), tree.frontier());
+
+	// Roots are checkpointed every 4 leaves
+	let checkpointed: Vec<u64> = sync.checkpoints().keys().copied().collect();
+	assert_eq!(checkpointed, vec![0, 4, 8, 12]);
+	sync.verify_root(8, &roots[8]).unwrap();
+	assert_eq!(sync.root_at(5), None);
+	assert_eq!(sync.verify_root(5, &roots[5]), Err(TreeError::UnknownRoot));
+	assert_eq!(
+		sync.verify_root(8, &roots[7]),
+		Err(TreeError::Diverged { leaf_count: 8 })
+	);
+
+	// A log that disagrees with the synced leaves is reported
+	let mut forged = events[3];
+	forged.leaf = leaves[4];
+	assert_eq!(
+		sync.apply(&forged),
+		Err(TreeError::Diverged { leaf_count: 3 })
+	);
+	let mut non_canonical = events[3];
+	non_canonical.leaf = [0xff; 32];
+	assert_eq!(sync.apply(&non_canonical), Err(TreeError::InvalidEvent));
+
+	// Snapshots round trip through SCALE and are checked against the root
+	let snapshot = sync.snapshot();
+	let decoded = TreeSnapshot::decode(&mut &snapshot.encode()[..]).unwrap();
+	assert_eq!(decoded, snapshot);
+	let restored = TreeSync::from_snapshot(new_tree(), &decoded, 1).unwrap();
+	assert_eq!(restored.root(), tree.root);
+	assert_eq!(restored.checkpoints().len(), leaves.len() + 1);
+	restored.verify_root(5, &roots[5]).unwrap();
+	let mut restored_tree = restored.into_tree();
+	assert_eq!(
+		restored_tree.leaf_at(Scalar::from(7u32)).unwrap(),
+		Scalar::from_bytes_mod_order(leaves[7])
+	);
+
+	let mut tampered = snapshot.clone();
+	tampered.leaves.swap(0, 1);
+	assert_eq!(
+		TreeSync::from_snapshot(new_tree(), &tampered, 1).err(),
+		Some(TreeError::InvalidSnapshot)
+	);
+	let mut shallow = snapshot;
+	shallow.depth -= 1;
+	assert_eq!(
+		TreeSync::from_snapshot(new_tree(), &shallow, 1).err(),
+		Some(TreeError::InvalidSnapshot)
+	);
+
+	// Only empty trees can be synced from the start of the log
+	restored_tree.add_leaves(vec![leaves[0]], None).unwrap();
+	assert_eq!(
+		TreeSync::new(restored_tree, 1).err(),
+		Some(TreeError::InvalidSnapshot)
+	);
+}
+
+#[test]
+fn test_vsmt_checkpoint_rollback() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params)
+		.depth(10)
+		.root_history_size(3)
+		.build();
+	let leaves: Vec<Scalar> =
+		(0..6).map(|_| Scalar::random(&mut test_rng)).collect();
+	let add = |tree: &mut VanillaSparseMerkleTree, leaves: &[Scalar]| {
+		tree.add_leaves(leaves.iter().map(|l| l.to_bytes()).collect(), None)
+			.unwrap()
+	};
+	let assert_same = |tree: &VanillaSparseMerkleTree,
+	                   expected: &VanillaSparseMerkleTree| {
+		assert_eq!(tree.root, expected.root);
+		assert_eq!(tree.known_roots(), expected.known_roots());
+		assert_eq!(tree.frontier(), expected.frontier());
+		for leaf in &leaves {
+			assert_eq!(tree.indices_of(*leaf), expected.indices_of(*leaf));
+		}
+		for i in 0..6u32 {
+			let idx = Scalar::from(i);
+			assert_eq!(tree.leaf_at(idx), expected.leaf_at(idx));
+		}
+	};
+
+	// One checkpoint per block
+	add(&mut tree, &leaves[..2]);
+	tree.checkpoint(1).unwrap();
+	let at_1 = tree.clone();
+	add(&mut tree, &leaves[2..4]);
+	tree.checkpoint(2).unwrap();
+	let at_2 = tree.clone();
+	tree.update(Scalar::zero(), leaves[3]).unwrap();
+	add(&mut tree, &leaves[4..5]);
+	assert_eq!(tree.indices_of(leaves[3]).len(), 2);
+	let stale_root = tree.root;
+
+	assert_eq!(tree.checkpoint(2), Err(TreeError::InvalidCheckpoint));
+	assert_eq!(tree.rollback_to(3), Err(TreeError::UnknownCheckpoint));
+
+	tree.rollback_to(2).unwrap();
+	assert_same(&tree, &at_2);
+	assert!(!tree.is_known_root(&stale_root));
+	assert_eq!(
+		tree.get(Scalar::zero(), stale_root, &mut None),
+		Err(TreeError::UnknownRoot)
+	);
+
+	// Rolling back twice to the same checkpoint works, later ones are gone
+	add(&mut tree, &leaves[5..]);
+	tree.rollback_to(1).unwrap();
+	assert_same(&tree, &at_1);
+	assert_eq!(tree.checkpoint_ids(), vec![1]);
+	add(&mut tree, &leaves[3..]);
+	tree.rollback_to(1).unwrap();
+	assert_same(&tree, &at_1);
+
+	// The tree keeps growing as if the rolled back leaves were never added
+	let mut expected = at_1;
+	add(&mut tree, &leaves[4..]);
+	add(&mut expected, &leaves[4..]);
+	assert_same(&tree, &expected);
+	let path = tree.path_at(Scalar::from(2u32)).unwrap();
+	assert!(tree
+		.verify_proof(Scalar::from(2u32), leaves[4], &path, None)
+		.unwrap());
+
+	tree.checkpoint(2).unwrap();
+	tree.checkpoint(3).unwrap();
+	tree.discard_checkpoints_before(3);
+	assert_eq!(tree.checkpoint_ids(), vec![3]);
+	assert_eq!(tree.rollback_to(1), Err(TreeError::UnknownCheckpoint));
+}
+
+#[test]
+fn test_sparse_merkle_map() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut map = SparseMerkleMap::new(p_params.clone(), DEFAULT_TREE_DEPTH);
+	let empty_root = map.root();
+	let (alice, bob) =
+		(Scalar::random(&mut test_rng), Scalar::random(&mut test_rng));
+	let (v1, v2, v3) =
+		(Scalar::from(10u32), Scalar::from(20u32), Scalar::zero());
+
+	assert_eq!(map.insert(alice, v1).unwrap(), None);
+	assert_eq!(map.insert(bob, v2).unwrap(), None);
+	assert_eq!(map.insert(alice, v3).unwrap(), Some(v1));
+	assert_eq!(map.get(alice), Some(v3));
+	assert_eq!(map.len(), 2);
+	assert_eq!(map.remove(bob).unwrap(), Some(v2));
+	assert_eq!(map.remove(bob).unwrap(), None);
+	assert_eq!(map.get(bob), None);
+	assert_eq!(map.len(), 1);
+	let index = map.index_of(alice).unwrap();
+	assert_eq!(map.tree().leaf_at(index).unwrap(), map.leaf(alice, v3));
+
+	let root = map.root();
+	let alice_path = map.prove(alice).unwrap();
+	let bob_path = map.prove(bob).unwrap();
+	assert!(map
+		.verify_membership(alice, v3, &alice_path, &root)
+		.unwrap());
+	assert!(!map
+		.verify_membership(alice, v1, &alice_path, &root)
+		.unwrap());
+	assert!(!map
+		.verify_non_membership(alice, &alice_path, &root)
+		.unwrap());
+	assert!(map.verify_non_membership(bob, &bob_path, &root).unwrap());
+	assert!(!map.verify_membership(bob, v2, &bob_path, &root).unwrap());
+
+	// Removing every key gives back the empty map
+	map.remove(alice).unwrap();
+	assert!(map.is_empty());
+	assert_eq!(map.root(), empty_root);
+
+	// With 4 indices, 5 keys can't all be stored
+	let mut small = SparseMerkleMap::new(p_params.clone(), 2);
+	let res: Result<Vec<_>, _> = (0..5u32)
+		.map(|i| small.insert(Scalar::from(i), Scalar::one()))
+		.collect();
+	assert_eq!(res, Err(MapError::KeyCollision));
+
+	map.insert(alice, v3).unwrap();
+	assert!(prove_map_lookup(
+		&map,
+		alice,
+		Some(v3),
+		&alice_path,
+		&p_params
+	));
+	assert!(!prove_map_lookup(
+		&map,
+		alice,
+		Some(v1),
+		&alice_path,
+		&p_params
+	));
+	assert!(!prove_map_lookup(&map, alice, None, &alice_path, &p_params));
+	assert!(prove_map_lookup(&map, bob, None, &bob_path, &p_params));
+	assert!(!prove_map_lookup(&map, bob, Some(v2), &bob_path, &p_params));
+}
+
+struct MapLookup<'a> {
+	map: &'a SparseMerkleMap,
+	has_value: bool,
+	path: &'a [Scalar],
+	p_params: &'a Poseidon,
+}
+
+impl<'a> TestCircuit for MapLookup<'a> {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		mut statics: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let depth = self.map.depth();
+		let path = if witness { Some(self.path) } else { None };
+		let nodes = allocate_scalars(cs, depth, path)?;
+		let statics = statics.remove(0);
+		if self.has_value {
+			sparse_merkle_map_membership_gadget(
+				cs,
+				depth,
+				&self.map.root(),
+				committed[0],
+				committed[1],
+				nodes,
+				statics,
+				self.p_params,
+			)
+		} else {
+			sparse_merkle_map_non_membership_gadget(
+				cs,
+				depth,
+				&self.map.root(),
+				committed[0],
+				nodes,
+				statics,
+				self.p_params,
+			)
+		}
+	}
+}
+
+/// Proves the value of `key` under the current root of `map` if `value` is
+/// set, else that `key` is not set
+fn prove_map_lookup(
+	map: &SparseMerkleMap,
+	key: Scalar,
+	value: Option<Scalar>,
+	path: &[Scalar],
+	p_params: &Poseidon,
+) -> bool {
+	let circuit = MapLookup {
+		map,
+		has_value: value.is_some(),
+		path,
+		p_params,
+	};
+	let values: Vec<Scalar> =
+		[key].iter().chain(value.iter()).cloned().collect();
+	prove_and_verify(&circuit, &values, &[4]).unwrap()
+}
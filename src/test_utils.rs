@@ -0,0 +1,82 @@
+use crate::{
+	poseidon::{allocate_statics_for_prover, allocate_statics_for_verifier},
+	utils::AllocatedScalar,
+};
+use bulletproofs::{
+	r1cs::{ConstraintSystem, Prover, R1CSError, Verifier},
+	BulletproofGens, PedersenGens,
+};
+use curve25519_dalek::scalar::Scalar;
+use merlin::Transcript;
+use rand_core::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+/// A gadget under test, synthesized once by the prover and once by the
+/// verifier of `prove_and_verify`
+pub trait TestCircuit {
+	/// Allocate the witnesses and the gadget. `committed` are the committed
+	/// values and `statics` the statics of each requested size. The
+	/// witnesses are only known when `witness` is set, on the prover side.
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		statics: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError>;
+}
+
+/// Commit to `values`, allocate statics of each size in `num_statics` and
+/// prove `circuit`, then verify the proof against the commitments. Errors of
+/// the gadget are returned, a proof that does not verify gives `Ok(false)`.
+pub fn prove_and_verify<C: TestCircuit>(
+	circuit: &C,
+	values: &[Scalar],
+	num_statics: &[usize],
+) -> Result<bool, R1CSError> {
+	let mut test_rng = ChaChaRng::from_seed([2u8; 32]);
+	let pc_gens = PedersenGens::default();
+
+	let mut prover_transcript = Transcript::new(b"TestCircuit");
+	let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let (comms, vars): (Vec<_>, Vec<_>) = values
+		.iter()
+		.map(|v| {
+			let (c, variable) =
+				prover.commit(*v, Scalar::random(&mut test_rng));
+			(
+				c,
+				AllocatedScalar {
+					variable,
+					assignment: Some(*v),
+				},
+			)
+		})
+		.unzip();
+	let statics = num_statics
+		.iter()
+		.map(|n| allocate_statics_for_prover(&mut prover, *n))
+		.collect();
+	circuit.synthesize(&mut prover, vars, statics, true)?;
+	let bp_gens =
+		BulletproofGens::new(prover.num_multipliers().next_power_of_two(), 1);
+	let proof = prover.prove_with_rng(&bp_gens, &mut test_rng)?;
+
+	let mut verifier_transcript = Transcript::new(b"TestCircuit");
+	let mut verifier = Verifier::new(&mut verifier_transcript);
+	let vars = comms
+		.iter()
+		.map(|c| AllocatedScalar {
+			variable: verifier.commit(*c),
+			assignment: None,
+		})
+		.collect();
+	let statics = num_statics
+		.iter()
+		.map(|n| allocate_statics_for_verifier(&mut verifier, *n, &pc_gens))
+		.collect();
+	circuit.synthesize(&mut verifier, vars, statics, false)?;
+	Ok(verifier
+		.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+		.is_ok())
+}
@@ -1,7 +1,35 @@
 use crate::{
-	poseidon::{builder::Poseidon, sbox::PoseidonSbox, PoseidonBuilder},
-	smt::{builder::DEFAULT_TREE_DEPTH, VanillaSparseMerkleTree},
+	poseidon::{
+		allocate_statics_for_prover, allocate_statics_for_verifier,
+		builder::Poseidon, sbox::PoseidonSbox, PoseidonBuilder,
+		Poseidon_hash_2, Poseidon_hash_4,
+	},
+	smt::{
+		builder::{
+			SparseMerkleTreeBuilder, DEFAULT_ROOT_HISTORY_SIZE,
+			DEFAULT_TREE_DEPTH,
+		},
+		error::TreeError,
+		VanillaSparseMerkleTree,
+	},
+	time_based_rewarding::{
+		schedule::{RewardRate, RewardSchedule},
+		time_based_reward_verif_gadget, AllocatedInputCoin,
+		AllocatedOutputCoin, AllocatedTimedDeposit, Transaction,
+	},
+	utils::{
+		allocate_scalars, get_bits, scalar_to_u64, AllocatedScalar, ScalarBytes,
+	},
 };
+use alloc::vec::Vec;
+use bulletproofs::{
+	r1cs::{Prover, R1CSError, R1CSProof, Verifier},
+	BulletproofGens, PedersenGens,
+};
+use core::fmt;
+use curve25519_dalek::{ristretto::CompressedRistretto, scalar::Scalar};
+use rand_core::{CryptoRng, RngCore};
+use sp_std::collections::btree_set::BTreeSet;
 
 #[derive(Clone)]
 pub struct RewardGadget {
@@ -60,3 +88,509 @@ impl RewardGadgetBuilder {
 		}
 	}
 }
+
+/// The secrets of a deposit, its leaf is `H(r, nullifier)` and it is
+/// rewarded by revealing `H(nullifier, nullifier)`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DepositNote {
+	pub r: Scalar,
+	pub nullifier: Scalar,
+}
+
+impl DepositNote {
+	pub fn random<R: RngCore + CryptoRng>(rng: &mut R) -> DepositNote {
+		DepositNote {
+			r: Scalar::random(rng),
+			nullifier: Scalar::random(rng),
+		}
+	}
+
+	pub fn leaf(&self, hash_params: &Poseidon) -> Scalar {
+		Poseidon_hash_2(self.r, self.nullifier, hash_params)
+	}
+
+	pub fn nullifier_hash(&self, hash_params: &Poseidon) -> Scalar {
+		Poseidon_hash_2(self.nullifier, self.nullifier, hash_params)
+	}
+}
+
+/// A note paying out part of a reward, committed to as
+/// `H(value, rho, r, nullifier)`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OutputNote {
+	pub value: u64,
+	pub rho: Scalar,
+	pub r: Scalar,
+	pub nullifier: Scalar,
+}
+
+impl OutputNote {
+	pub fn random<R: RngCore + CryptoRng>(
+		value: u64,
+		rng: &mut R,
+	) -> OutputNote {
+		OutputNote {
+			value,
+			rho: Scalar::random(rng),
+			r: Scalar::random(rng),
+			nullifier: Scalar::random(rng),
+		}
+	}
+
+	pub fn leaf(&self, hash_params: &Poseidon) -> Scalar {
+		Poseidon_hash_4(
+			[Scalar::from(self.value), self.rho, self.r, self.nullifier],
+			hash_params,
+		)
+	}
+}
+
+/// The public inputs of a reward proof
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RewardStatement {
+	pub deposit_root: Scalar,
+	pub time_root: Scalar,
+	pub nullifier_hash: Scalar,
+	/// The leaves of the output notes
+	pub output_leaves: Vec<Scalar>,
+}
+
+/// Commitments to r, nullifier, leaf, deposit time, deposit time leaf and
+/// to value, rho, r and nullifier of each output, and the public inputs
+pub type RewardProofComms = (Vec<CompressedRistretto>, RewardStatement);
+
+/// The commitments made before the outputs
+const INPUT_COMMS: usize = 5;
+/// The commitments made for each output
+const OUTPUT_COMMS: usize = 4;
+
+/// Errors returned by `RewardTree`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RewardError {
+	/// The deposit time does not fit in the time bits of the tree
+	InvalidTime,
+	/// The lock-up period of the deposit is not over
+	NoReward,
+	/// An output is zero or the outputs don't add up to the reward
+	InvalidOutputs,
+	/// The commitments don't match the statement of the proof
+	InvalidProof,
+	/// The nullifier hash of the statement was already rewarded
+	SpentNullifier,
+	/// The deposit trees failed, e.g. the leaf was not deposited or a root
+	/// of the statement is unknown
+	Tree(TreeError),
+	/// Building, proving or verifying the circuit failed
+	R1CSError(R1CSError),
+}
+
+impl fmt::Display for RewardError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RewardError::InvalidTime => write!(f, "Invalid deposit time"),
+			RewardError::NoReward => write!(f, "No reward"),
+			RewardError::InvalidOutputs => write!(f, "Invalid outputs"),
+			RewardError::InvalidProof => write!(f, "Invalid proof"),
+			RewardError::SpentNullifier => write!(f, "Spent nullifier"),
+			RewardError::Tree(e) => write!(f, "{}", e),
+			RewardError::R1CSError(e) => write!(f, "R1CS error: {:?}", e),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RewardError {}
+
+impl From<TreeError> for RewardError {
+	fn from(e: TreeError) -> RewardError { RewardError::Tree(e) }
+}
+
+impl From<R1CSError> for RewardError {
+	fn from(e: R1CSError) -> RewardError { RewardError::R1CSError(e) }
+}
+
+/// The deposit tree and the deposit time tree of time based rewarding. Each
+/// deposit `leaf` made at `time` is stored at the same index in both trees,
+/// as `leaf` and `H(leaf, time)`, so that a reward can be proven for the
+/// time the deposit has been locked.
+#[derive(Clone)]
+pub struct RewardTree {
+	pub hash_params: Poseidon,
+	pub(crate) deposit_tree: VanillaSparseMerkleTree,
+	pub(crate) time_tree: VanillaSparseMerkleTree,
+	pub schedule: RewardSchedule,
+	/// Deposits can only be rewarded this long after they were made
+	pub min_lockup: u64,
+	/// The times are range checked to this many bits, at most 64
+	pub time_bits: usize,
+	/// The time of each deposit, by index
+	deposit_times: Vec<u64>,
+	/// The nullifier hashes of the verified rewards
+	spent_nullifiers: BTreeSet<ScalarBytes>,
+}
+
+impl RewardTree {
+	/// Record the deposit of `leaf` at `time` and return its index. Errors
+	/// with `InvalidTime` if the time does not fit in `time_bits`.
+	pub fn deposit(
+		&mut self,
+		leaf: Scalar,
+		time: u64,
+	) -> Result<Scalar, RewardError> {
+		if self.time_bits < 64 && time >> self.time_bits != 0 {
+			return Err(RewardError::InvalidTime);
+		}
+		let index = self.deposit_tree.curr_index;
+		let time_leaf =
+			Poseidon_hash_2(leaf, Scalar::from(time), &self.hash_params);
+		self.deposit_tree.add_leaves(vec![leaf.to_bytes()], None)?;
+		self.time_tree
+			.add_leaves(vec![time_leaf.to_bytes()], None)?;
+		self.deposit_times.push(time);
+		Ok(index)
+	}
+
+	/// The tree of the deposit leaves, only updated through `deposit`
+	pub fn deposit_tree(&self) -> &VanillaSparseMerkleTree {
+		&self.deposit_tree
+	}
+
+	/// The tree of the `H(leaf, time)` leaves, only updated through
+	/// `deposit`
+	pub fn time_tree(&self) -> &VanillaSparseMerkleTree { &self.time_tree }
+
+	/// The time `leaf` was deposited at. If the leaf was deposited more than
+	/// once, the first deposit is used.
+	pub fn deposit_time(&self, leaf: Scalar) -> Result<u64, RewardError> {
+		self.find_deposit(leaf).map(|(_, time)| time)
+	}
+
+	/// The reward of `note` at `current_time`, or None if it wasn't deposited
+	/// or the lock-up period is not over
+	pub fn reward(&self, note: &DepositNote, current_time: u64) -> Option<u64> {
+		let deposit_time =
+			self.deposit_time(note.leaf(&self.hash_params)).ok()?;
+		let duration = current_time.checked_sub(deposit_time)?;
+		if duration < self.min_lockup {
+			return None;
+		}
+		self.schedule.reward(duration)
+	}
+
+	/// Prove that `note` was deposited under the current roots and that the
+	/// values of `outputs` add up to its reward at `current_time`, see
+	/// `time_based_reward_verif_gadget`. Errors with `NoReward` if the
+	/// lock-up period is not over and with `InvalidOutputs` if an output is
+	/// zero or the outputs don't add up to `reward`, as the proof would not
+	/// verify.
+	///
+	/// Like `prove_zk_at`, the caller passes the `prover` and `bp_gens`, so
+	/// that it owns the transcript the proof is bound to and can reuse the
+	/// generators across proofs.
+	pub fn prove_reward<R: RngCore + CryptoRng>(
+		&self,
+		note: &DepositNote,
+		current_time: u64,
+		outputs: &[OutputNote],
+		bp_gens: &BulletproofGens,
+		mut prover: Prover,
+		rng: &mut R,
+	) -> Result<(R1CSProof, RewardProofComms), RewardError> {
+		let leaf = note.leaf(&self.hash_params);
+		let root = self.deposit_tree.root;
+		let (index, deposit_time) = self.find_deposit(leaf)?;
+		let reward = self
+			.reward(note, current_time)
+			.ok_or(RewardError::NoReward)?;
+		let total = outputs.iter().try_fold(0u64, |total, o| {
+			if o.value == 0 {
+				return None;
+			}
+			total.checked_add(o.value)
+		});
+		if total != Some(reward) {
+			return Err(RewardError::InvalidOutputs);
+		}
+		let deposit_time = Scalar::from(deposit_time);
+		let time_leaf = Poseidon_hash_2(leaf, deposit_time, &self.hash_params);
+		let statement = RewardStatement {
+			deposit_root: root,
+			time_root: self.time_tree.root,
+			nullifier_hash: note.nullifier_hash(&self.hash_params),
+			output_leaves: outputs
+				.iter()
+				.map(|o| o.leaf(&self.hash_params))
+				.collect(),
+		};
+
+		let mut values =
+			vec![note.r, note.nullifier, leaf, deposit_time, time_leaf];
+		for o in outputs {
+			values.extend_from_slice(&[
+				Scalar::from(o.value),
+				o.rho,
+				o.r,
+				o.nullifier,
+			]);
+		}
+		let mut comms = Vec::with_capacity(values.len());
+		let mut committed = Vec::with_capacity(values.len());
+		for value in values {
+			let (com, var) = prover.commit(value, Scalar::random(rng));
+			comms.push(com);
+			committed.push(AllocatedScalar {
+				variable: var,
+				assignment: Some(value),
+			});
+		}
+
+		// The index bits are shared by both trees
+		let depth = self.deposit_tree.depth;
+		let mut witness: Vec<Scalar> = get_bits(&index, 256)[..depth]
+			.iter()
+			.map(|b| Scalar::from(*b as u8))
+			.collect();
+		witness.extend(self.deposit_tree.path_at(index)?);
+		witness.extend(self.time_tree.path_at(index)?);
+		witness.extend(outputs.iter().map(|o| Scalar::from(o.value).invert()));
+		let allocated = allocate_scalars(
+			&mut prover,
+			3 * depth + outputs.len(),
+			Some(&witness),
+		)?;
+
+		let statics_2 = allocate_statics_for_prover(&mut prover, 4);
+		let statics_4 = allocate_statics_for_prover(&mut prover, 2);
+		let transaction = self.transaction(
+			&statement,
+			current_time,
+			committed,
+			allocated,
+			statics_2,
+			statics_4,
+		);
+		time_based_reward_verif_gadget(
+			&mut prover,
+			vec![transaction],
+			&self.hash_params,
+		)?;
+
+		let proof = prover.prove_with_rng(bp_gens, rng)?;
+		Ok((proof, (comms, statement)))
+	}
+
+	/// Verify a proof of `prove_reward` at `current_time` and record its
+	/// nullifier hash as spent. The roots of the statement have to be known
+	/// roots of the trees and the nullifier hash must not have been spent
+	/// yet.
+	pub fn verify_reward<R: RngCore + CryptoRng>(
+		&mut self,
+		proof: &R1CSProof,
+		comms: &RewardProofComms,
+		current_time: u64,
+		pc_gens: &PedersenGens,
+		bp_gens: &BulletproofGens,
+		mut verifier: Verifier,
+		rng: &mut R,
+	) -> Result<(), RewardError> {
+		let (comms, statement) = comms;
+		if !self.deposit_tree.is_known_root(&statement.deposit_root)
+			|| !self.time_tree.is_known_root(&statement.time_root)
+		{
+			return Err(TreeError::UnknownRoot.into());
+		}
+		let num_outputs = statement.output_leaves.len();
+		if comms.len() != INPUT_COMMS + OUTPUT_COMMS * num_outputs {
+			return Err(RewardError::InvalidProof);
+		}
+		let nullifier_hash = statement.nullifier_hash.to_bytes();
+		if self.spent_nullifiers.contains(&nullifier_hash) {
+			return Err(RewardError::SpentNullifier);
+		}
+
+		let committed = comms
+			.iter()
+			.map(|com| AllocatedScalar {
+				variable: verifier.commit(*com),
+				assignment: None,
+			})
+			.collect();
+		let depth = self.deposit_tree.depth;
+		let allocated =
+			allocate_scalars(&mut verifier, 3 * depth + num_outputs, None)?;
+
+		let statics_2 =
+			allocate_statics_for_verifier(&mut verifier, 4, pc_gens);
+		let statics_4 =
+			allocate_statics_for_verifier(&mut verifier, 2, pc_gens);
+		let transaction = self.transaction(
+			statement,
+			current_time,
+			committed,
+			allocated,
+			statics_2,
+			statics_4,
+		);
+		time_based_reward_verif_gadget(
+			&mut verifier,
+			vec![transaction],
+			&self.hash_params,
+		)?;
+
+		verifier.verify_with_rng(proof, pc_gens, bp_gens, rng)?;
+		self.spent_nullifiers.insert(nullifier_hash);
+		Ok(())
+	}
+
+	/// The index and the time of the first deposit of `leaf` under the
+	/// current root
+	fn find_deposit(&self, leaf: Scalar) -> Result<(Scalar, u64), RewardError> {
+		let index = self.deposit_tree.index_of(leaf, self.deposit_tree.root)?;
+		let time = scalar_to_u64(&index)
+			.and_then(|i| self.deposit_times.get(i as usize))
+			.ok_or(TreeError::UnknownLeaf)?;
+		Ok((index, *time))
+	}
+
+	/// Assemble the transaction from the committed values and the allocated
+	/// index bits, paths and output inverses, in the order of `prove_reward`
+	fn transaction(
+		&self,
+		statement: &RewardStatement,
+		current_time: u64,
+		committed: Vec<AllocatedScalar>,
+		allocated: Vec<AllocatedScalar>,
+		statics_2: Vec<AllocatedScalar>,
+		statics_4: Vec<AllocatedScalar>,
+	) -> Transaction {
+		let depth = self.deposit_tree.depth;
+		let bits = allocated[..depth].to_vec();
+		let inverses = &allocated[3 * depth..];
+		let outputs = committed[INPUT_COMMS..]
+			.chunks(OUTPUT_COMMS)
+			.zip(inverses.iter())
+			.zip(statement.output_leaves.iter())
+			.map(|((coin, inv_value), leaf_cm)| AllocatedOutputCoin {
+				inv_value: *inv_value,
+				value: coin[0],
+				rho: coin[1],
+				r: coin[2],
+				nullifier: coin[3],
+				leaf_cm: *leaf_cm,
+			})
+			.collect();
+
+		Transaction {
+			depth,
+			deposit_root: statement.deposit_root,
+			input: AllocatedInputCoin {
+				r: committed[0],
+				nullifier: committed[1],
+				leaf_cm_val: committed[2],
+				leaf_index_bits: bits.clone(),
+				leaf_proof_nodes: allocated[depth..2 * depth].to_vec(),
+				sn: statement.nullifier_hash,
+			},
+			timed_deposit: AllocatedTimedDeposit {
+				time_root: statement.time_root,
+				schedule: self.schedule.clone(),
+				current_time: Scalar::from(current_time),
+				min_lockup: Scalar::from(self.min_lockup),
+				time_bits: self.time_bits,
+				deposit_time: committed[3],
+				deposit_time_cm_val: committed[4],
+				deposit_time_index_bits: bits,
+				deposit_time_proof_nodes: allocated[2 * depth..3 * depth]
+					.to_vec(),
+			},
+			outputs,
+			statics_2,
+			statics_4,
+		}
+	}
+}
+
+pub struct RewardTreeBuilder {
+	depth: Option<usize>,
+	hash_params: Option<Poseidon>,
+	root_history_size: Option<usize>,
+	schedule: Option<RewardSchedule>,
+	min_lockup: Option<u64>,
+	time_bits: Option<usize>,
+}
+
+impl Default for RewardTreeBuilder {
+	fn default() -> Self { Self::new() }
+}
+
+impl RewardTreeBuilder {
+	pub fn new() -> Self {
+		Self {
+			depth: None,
+			hash_params: None,
+			root_history_size: None,
+			schedule: None,
+			min_lockup: None,
+			time_bits: None,
+		}
+	}
+
+	pub fn depth(mut self, depth: usize) -> Self {
+		self.depth = Some(depth);
+		self
+	}
+
+	pub fn hash_params(mut self, hash_params: Poseidon) -> Self {
+		self.hash_params = Some(hash_params);
+		self
+	}
+
+	pub fn root_history_size(mut self, root_history_size: usize) -> Self {
+		self.root_history_size = Some(root_history_size);
+		self
+	}
+
+	pub fn schedule(mut self, schedule: RewardSchedule) -> Self {
+		self.schedule = Some(schedule);
+		self
+	}
+
+	pub fn min_lockup(mut self, min_lockup: u64) -> Self {
+		self.min_lockup = Some(min_lockup);
+		self
+	}
+
+	pub fn time_bits(mut self, time_bits: usize) -> Self {
+		self.time_bits = Some(time_bits);
+		self
+	}
+
+	/// Defaults to a linear schedule of one unit of reward per unit of
+	/// time, no lock-up and 64 bit times
+	pub fn build(self) -> RewardTree {
+		let depth = self.depth.unwrap_or(DEFAULT_TREE_DEPTH);
+		let hash_params = self.hash_params.unwrap_or_else(|| {
+			PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build()
+		});
+		let root_history_size =
+			self.root_history_size.unwrap_or(DEFAULT_ROOT_HISTORY_SIZE);
+		let tree = SparseMerkleTreeBuilder::new()
+			.depth(depth)
+			.hash_params(hash_params.clone())
+			.root_history_size(root_history_size)
+			.build();
+
+		RewardTree {
+			hash_params,
+			deposit_tree: tree.clone(),
+			time_tree: tree,
+			schedule: self.schedule.unwrap_or_else(|| {
+				RewardSchedule::Linear(RewardRate::new(1, 1))
+			}),
+			min_lockup: self.min_lockup.unwrap_or(0),
+			time_bits: self.time_bits.unwrap_or(64),
+			deposit_times: Vec::new(),
+			spent_nullifiers: BTreeSet::new(),
+		}
+	}
+}
@@ -2,16 +2,22 @@
 pub mod tests;
 
 pub mod builder;
+pub mod schedule;
 
 use crate::{
 	fixed_deposit_tree::fixed_deposit_tree_verif_gadget,
 	poseidon::{
 		builder::Poseidon, Poseidon_hash_2_constraints, Poseidon_hash_4_gadget,
 	},
-	utils::AllocatedScalar,
+	smt::smt::vanilla_merkle_merkle_tree_verif_gadget,
+	time_based_rewarding::schedule::{RewardSchedule, QUANTITY_BITS},
+	utils::{
+		allocate_quantity, get_bits, lc_bits_gadget, scalar_to_u64,
+		AllocatedScalar,
+	},
 	zero_nonzero::is_nonzero_gadget,
 };
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
 use curve25519_dalek::scalar::Scalar;
 
@@ -30,8 +36,12 @@ pub struct AllocatedInputCoin {
 #[derive(Debug, Clone)]
 pub struct AllocatedTimedDeposit {
 	time_root: Scalar,
-	multiplier: Scalar,
+	schedule: RewardSchedule,
 	current_time: Scalar,
+	/// Deposits can only be rewarded this long after they were made
+	min_lockup: Scalar,
+	/// The times are range checked to this many bits, at most 64
+	time_bits: usize,
 	deposit_time: AllocatedScalar,
 	deposit_time_cm_val: AllocatedScalar,
 	deposit_time_index_bits: Vec<AllocatedScalar>,
@@ -94,8 +104,24 @@ impl Transaction {
 		let deposit_time_cm_lc: LinearCombination =
 			self.timed_deposit.deposit_time_cm_val.variable.into();
 		cs.constrain(computed_deposit_time_cm - deposit_time_cm_lc);
-		let deposit_time_length = self.timed_deposit.current_time
-			- self.timed_deposit.deposit_time.variable;
+		// check the deposit time was recorded in the deposit time tree
+		vanilla_merkle_merkle_tree_verif_gadget(
+			cs,
+			self.depth,
+			&self.timed_deposit.time_root,
+			self.timed_deposit.deposit_time_cm_val,
+			self.timed_deposit.deposit_time_index_bits.clone(),
+			self.timed_deposit.deposit_time_proof_nodes.clone(),
+			self.statics_2.clone(),
+			poseidon_params,
+		)?;
+		let deposit_time_length = deposit_duration_constraints(
+			cs,
+			self.timed_deposit.deposit_time,
+			&self.timed_deposit.current_time,
+			&self.timed_deposit.min_lockup,
+			self.timed_deposit.time_bits,
+		)?;
 		// check output commitment is properly formed and reward calculation is
 		// properly formed
 		let mut summed_outputs: LinearCombination =
@@ -123,14 +149,64 @@ impl Transaction {
 			// sum the outputs for later checking
 			summed_outputs = summed_outputs + self.outputs[i].value.variable;
 		}
-		// TODO: Add better reward calculation
-		// basic reward calculation
-		let reward = self.timed_deposit.multiplier * (deposit_time_length);
-		cs.constrain(summed_outputs - reward);
+		let timed = &self.timed_deposit;
+		let duration = allocate_quantity(
+			cs,
+			timed
+				.deposit_time
+				.assignment
+				.and_then(|t| scalar_to_u64(&(timed.current_time - t))),
+		)?;
+		cs.constrain(deposit_time_length - duration.variable);
+		let reward = timed.schedule.synthesize_reward(cs, duration)?;
+		cs.constrain(summed_outputs - reward.variable);
 		Ok(())
 	}
 }
 
+/// Returns `current_time - deposit_time` and enforces that it is at least
+/// `min_lockup`. The deposit time is range checked to `time_bits`, and the
+/// public times have to fit as well, so that a deposit time in the future
+/// can't wrap around the field into a huge duration. Times have at most 64
+/// bits, the durations the reward schedules are computed on.
+pub fn deposit_duration_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	deposit_time: AllocatedScalar,
+	current_time: &Scalar,
+	min_lockup: &Scalar,
+	time_bits: usize,
+) -> Result<LinearCombination, R1CSError> {
+	if time_bits > QUANTITY_BITS {
+		return Err(R1CSError::GadgetError {
+			description: String::from("Times must have at most 64 bits"),
+		});
+	}
+	let fits =
+		|s: &Scalar| get_bits(s, 256)[time_bits..].iter().all(|b| *b == 0);
+	if !fits(current_time) || !fits(min_lockup) {
+		return Err(R1CSError::GadgetError {
+			description: String::from("Public time does not fit the bit width"),
+		});
+	}
+	lc_bits_gadget(
+		cs,
+		deposit_time.variable.into(),
+		deposit_time.assignment,
+		time_bits,
+	)?;
+	let duration = *current_time - deposit_time.variable;
+	// If negative, `duration - min_lockup` wraps around to more than 2^251
+	lc_bits_gadget(
+		cs,
+		duration.clone() - *min_lockup,
+		deposit_time
+			.assignment
+			.map(|t| current_time - t - min_lockup),
+		time_bits,
+	)?;
+	Ok(duration)
+}
+
 pub fn time_based_reward_verif_gadget<CS: ConstraintSystem>(
 	cs: &mut CS,
 	txes: Vec<Transaction>,
@@ -0,0 +1,206 @@
+use crate::{
+	fixed_point::div_rem_constraints,
+	utils::{
+		allocate_quantity, at_least_gadget, positive_no_gadget,
+		AllocatedQuantity,
+	},
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use bulletproofs::r1cs::{ConstraintSystem, LinearCombination, R1CSError};
+use core::convert::TryFrom;
+use curve25519_dalek::scalar::Scalar;
+
+/// Durations and rewards are range checked to this many bits
+pub const QUANTITY_BITS: usize = 64;
+
+/// Reward per unit of time, `numerator / denominator`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RewardRate {
+	pub numerator: u64,
+	pub denominator: u64,
+}
+
+impl RewardRate {
+	pub fn new(numerator: u64, denominator: u64) -> RewardRate {
+		RewardRate {
+			numerator,
+			denominator,
+		}
+	}
+
+	/// `duration * numerator / denominator` rounded down, or None if the
+	/// denominator is zero or the reward does not fit in 64 bits
+	pub fn reward(&self, duration: u64) -> Option<u64> {
+		self.divide(duration).map(|(reward, _)| reward)
+	}
+
+	/// The reward and the remainder of the division
+	fn divide(&self, duration: u64) -> Option<(u64, u64)> {
+		if self.denominator == 0 {
+			return None;
+		}
+		let product = duration as u128 * self.numerator as u128;
+		let denominator = self.denominator as u128;
+		let reward = u64::try_from(product / denominator).ok()?;
+		Some((reward, (product % denominator) as u64))
+	}
+}
+
+/// A rate that applies from `min_duration` on
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RewardTier {
+	pub min_duration: u64,
+	pub rate: RewardRate,
+}
+
+/// How the reward of a deposit is computed from the time it was locked.
+/// Each schedule is evaluated natively with `reward` and in circuit with
+/// `synthesize_reward`, both rounding down.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RewardSchedule {
+	/// The same rate whatever the duration
+	Linear(RewardRate),
+	/// The rate of the last tier whose `min_duration` is reached, applied to
+	/// the whole duration, and no reward below the first tier. The tiers
+	/// must have strictly increasing `min_duration`.
+	Tiered(Vec<RewardTier>),
+	/// The reward of `schedule`, up to `cap`
+	Capped {
+		schedule: Box<RewardSchedule>,
+		cap: u64,
+	},
+}
+
+impl RewardSchedule {
+	/// The reward of a deposit locked for `duration`, or None if the
+	/// schedule is invalid or the reward does not fit in 64 bits
+	pub fn reward(&self, duration: u64) -> Option<u64> {
+		match self {
+			RewardSchedule::Linear(rate) => rate.reward(duration),
+			RewardSchedule::Tiered(tiers) => {
+				if !valid_tiers(tiers) {
+					return None;
+				}
+				tier_rate(tiers, duration).reward(duration)
+			},
+			RewardSchedule::Capped { schedule, cap } => {
+				schedule.reward(duration).map(|reward| reward.min(*cap))
+			},
+		}
+	}
+
+	/// Constrain and return the reward of `duration`. The duration is range
+	/// checked to 64 bits, so is the reward.
+	pub fn synthesize_reward<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		duration: AllocatedQuantity,
+	) -> Result<AllocatedQuantity, R1CSError> {
+		positive_no_gadget(cs, duration, QUANTITY_BITS)?;
+		self.reward_constraints(cs, duration)
+	}
+
+	fn reward_constraints<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		duration: AllocatedQuantity,
+	) -> Result<AllocatedQuantity, R1CSError> {
+		match self {
+			RewardSchedule::Linear(rate) => {
+				let tier = RewardTier {
+					min_duration: 0,
+					rate: *rate,
+				};
+				tiered_reward_constraints(cs, duration, &[tier])
+			},
+			RewardSchedule::Tiered(tiers) => {
+				tiered_reward_constraints(cs, duration, tiers)
+			},
+			RewardSchedule::Capped { schedule, cap } => {
+				let reward = schedule.reward_constraints(cs, duration)?;
+				// reward + over_cap * (cap - reward)
+				let over_cap = at_least_gadget(cs, reward, *cap)?;
+				let (_, _, cut) = cs.multiply(
+					over_cap.variable.into(),
+					Scalar::from(*cap) - reward.variable,
+				);
+				let capped = allocate_quantity(
+					cs,
+					reward.assignment.map(|reward| reward.min(*cap)),
+				)?;
+				cs.constrain(reward.variable + cut - capped.variable);
+				Ok(capped)
+			},
+		}
+	}
+}
+
+fn valid_tiers(tiers: &[RewardTier]) -> bool {
+	tiers.iter().all(|t| t.rate.denominator != 0)
+		&& tiers
+			.windows(2)
+			.all(|w| w[0].min_duration < w[1].min_duration)
+}
+
+/// The rate of the last tier reached by `duration`, zero below the first
+fn tier_rate(tiers: &[RewardTier], duration: u64) -> RewardRate {
+	tiers
+		.iter()
+		.rev()
+		.find(|t| t.min_duration <= duration)
+		.map(|t| t.rate)
+		.unwrap_or_else(|| RewardRate::new(0, 1))
+}
+
+/// `duration * rate` rounded down, for the rate of the last tier reached.
+/// The rate is selected with one comparison per tier, as the sum of the
+/// differences between the rates of the tiers reached.
+fn tiered_reward_constraints<CS: ConstraintSystem>(
+	cs: &mut CS,
+	duration: AllocatedQuantity,
+	tiers: &[RewardTier],
+) -> Result<AllocatedQuantity, R1CSError> {
+	if !valid_tiers(tiers) {
+		return Err(R1CSError::GadgetError {
+			description: String::from(
+				"Tiers must have increasing durations and nonzero rates",
+			),
+		});
+	}
+	let mut numerator = LinearCombination::default();
+	let mut denominator = LinearCombination::from(Scalar::one());
+	let mut prev = RewardRate::new(0, 1);
+	for tier in tiers {
+		let reached: LinearCombination = if tier.min_duration == 0 {
+			Scalar::one().into()
+		} else {
+			at_least_gadget(cs, duration, tier.min_duration)?
+				.variable
+				.into()
+		};
+		numerator = numerator
+			+ reached.clone()
+				* (Scalar::from(tier.rate.numerator)
+					- Scalar::from(prev.numerator));
+		denominator = denominator
+			+ reached
+				* (Scalar::from(tier.rate.denominator)
+					- Scalar::from(prev.denominator));
+		prev = tier.rate;
+	}
+
+	// duration * numerator = reward * denominator + remainder, with the
+	// remainder below the denominator
+	let rate = duration.assignment.map(|d| tier_rate(tiers, d));
+	let (_, _, product) = cs.multiply(duration.variable.into(), numerator);
+	let (reward, _) = div_rem_constraints(
+		cs,
+		product.into(),
+		duration
+			.assignment
+			.and_then(|d| rate.map(|rate| d as u128 * rate.numerator as u128)),
+		denominator,
+		rate.map(|rate| rate.denominator),
+	)?;
+	Ok(reward)
+}
@@ -1,17 +1,22 @@
 use super::{
-	AllocatedInputCoin, AllocatedOutputCoin, AllocatedTimedDeposit, Transaction,
+	builder::*, deposit_duration_constraints, schedule::*, AllocatedInputCoin,
+	AllocatedOutputCoin, AllocatedTimedDeposit, Transaction,
 };
 use crate::{
 	poseidon::{
 		allocate_statics_for_prover, allocate_statics_for_verifier,
 		sbox::PoseidonSbox, PoseidonBuilder, Poseidon_hash_2, Poseidon_hash_4,
 	},
-	smt::builder::{SparseMerkleTreeBuilder, DEFAULT_TREE_DEPTH},
+	smt::{
+		builder::{SparseMerkleTreeBuilder, DEFAULT_TREE_DEPTH},
+		TreeError,
+	},
+	test_utils::{prove_and_verify, TestCircuit},
 	time_based_rewarding::time_based_reward_verif_gadget,
-	utils::{get_bits, AllocatedScalar},
+	utils::{allocate_scalars, get_bits, AllocatedQuantity, AllocatedScalar},
 };
 use bulletproofs::{
-	r1cs::{Prover, Verifier},
+	r1cs::{ConstraintSystem, Prover, R1CSError, Verifier},
 	BulletproofGens, PedersenGens,
 };
 use curve25519_dalek::scalar::Scalar;
@@ -45,7 +50,7 @@ fn test_time_based_reward_gadget_verification() {
 		let index = Scalar::from(i as u32);
 		let s = if i == 7 { expected_output } else { index };
 
-		deposit_tree.update(index, s);
+		deposit_tree.update(index, s).unwrap();
 	}
 
 	let mut merkle_proof_vec = Vec::<Scalar>::new();
@@ -53,21 +58,22 @@ fn test_time_based_reward_gadget_verification() {
 	let k = Scalar::from(7u32);
 	assert_eq!(
 		expected_output,
-		deposit_tree.get(k, deposit_tree.root, &mut merkle_proof)
+		deposit_tree
+			.get(k, deposit_tree.root, &mut merkle_proof)
+			.unwrap()
 	);
 	merkle_proof_vec = merkle_proof.unwrap();
-	assert!(deposit_tree.verify_proof(
-		k,
-		expected_output,
-		&merkle_proof_vec,
-		None
-	));
-	assert!(deposit_tree.verify_proof(
-		k,
-		expected_output,
-		&merkle_proof_vec,
-		Some(&deposit_tree.root)
-	));
+	assert!(deposit_tree
+		.verify_proof(k, expected_output, &merkle_proof_vec, None)
+		.unwrap());
+	assert!(deposit_tree
+		.verify_proof(
+			k,
+			expected_output,
+			&merkle_proof_vec,
+			Some(&deposit_tree.root)
+		)
+		.unwrap());
 
 	// compute hash for timing deposits/withdrawal lengths
 	let deposit_block_number = Scalar::from(1u32);
@@ -87,28 +93,29 @@ fn test_time_based_reward_gadget_verification() {
 			index
 		};
 
-		timed_tree.update(index, s);
+		timed_tree.update(index, s).unwrap();
 	}
 
 	let mut timed_merkle_proof_vec = Vec::<Scalar>::new();
 	let mut timed_merkle_proof = Some(timed_merkle_proof_vec);
 	assert_eq!(
 		timed_deposit_leaf_val,
-		timed_tree.get(k, timed_tree.root, &mut timed_merkle_proof)
+		timed_tree
+			.get(k, timed_tree.root, &mut timed_merkle_proof)
+			.unwrap()
 	);
 	timed_merkle_proof_vec = timed_merkle_proof.unwrap();
-	assert!(timed_tree.verify_proof(
-		k,
-		timed_deposit_leaf_val,
-		&timed_merkle_proof_vec,
-		None
-	));
-	assert!(timed_tree.verify_proof(
-		k,
-		timed_deposit_leaf_val,
-		&timed_merkle_proof_vec,
-		Some(&timed_tree.root)
-	));
+	assert!(timed_tree
+		.verify_proof(k, timed_deposit_leaf_val, &timed_merkle_proof_vec, None)
+		.unwrap());
+	assert!(timed_tree
+		.verify_proof(
+			k,
+			timed_deposit_leaf_val,
+			&timed_merkle_proof_vec,
+			Some(&timed_tree.root)
+		)
+		.unwrap());
 
 	let output_1 = Scalar::from(5u32);
 	let output_1_inverse = Scalar::from(5u32).invert();
@@ -135,7 +142,7 @@ fn test_time_based_reward_gadget_verification() {
 	);
 
 	let pc_gens = PedersenGens::default();
-	let bp_gens = BulletproofGens::new(16500, 1);
+	let bp_gens = BulletproofGens::new(32768, 1);
 
 	let (proof, commitments) = {
 		let mut prover_transcript = Transcript::new(b"RewardTree");
@@ -249,7 +256,7 @@ fn test_time_based_reward_gadget_verification() {
 		let mut deposit_time_proof_comms = vec![];
 		let mut deposit_time_proof_vars = vec![];
 		let mut deposit_time_proof_alloc_scalars = vec![];
-		for p in merkle_proof_vec.iter() {
+		for p in timed_merkle_proof_vec.iter() {
 			let (c, v) = prover.commit(*p, Scalar::random(&mut test_rng));
 			deposit_time_proof_comms.push(c);
 			deposit_time_proof_vars.push(v);
@@ -261,8 +268,10 @@ fn test_time_based_reward_gadget_verification() {
 
 		let timed_deposit = AllocatedTimedDeposit {
 			time_root: timed_tree.root,
-			multiplier: Scalar::from(1u32),
+			schedule: RewardSchedule::Linear(RewardRate::new(1, 1)),
 			current_time: current_block_number,
+			min_lockup: Scalar::from(5u32),
+			time_bits: 32,
 			deposit_time: alloc_deposit_time,
 			deposit_time_cm_val: alloc_deposit_time_leaf_val,
 			deposit_time_index_bits: deposit_time_index_alloc_scalars,
@@ -485,8 +494,10 @@ fn test_time_based_reward_gadget_verification() {
 
 	let timed_deposit = AllocatedTimedDeposit {
 		time_root: timed_tree.root,
-		multiplier: Scalar::from(1u32),
+		schedule: RewardSchedule::Linear(RewardRate::new(1, 1)),
 		current_time: current_block_number,
+		min_lockup: Scalar::from(5u32),
+		time_bits: 32,
 		deposit_time: timed_comms_alloc[0],
 		deposit_time_cm_val: timed_comms_alloc[1],
 		deposit_time_index_bits: timed_index_alloc_scalars,
@@ -555,3 +566,466 @@ fn test_time_based_reward_gadget_verification() {
 
 	println!("Verification time is {:?}", end);
 }
+
+#[test]
+fn test_deposit_duration_constraints() {
+	let t = |v: u64| Scalar::from(v);
+	assert!(prove_deposit_duration(t(1), t(11), t(5), 32).unwrap());
+	assert!(prove_deposit_duration(t(6), t(11), t(5), 32).unwrap());
+	assert!(prove_deposit_duration(t(0), t(0), t(0), 32).unwrap());
+	// The lock-up period is not over yet
+	assert!(!prove_deposit_duration(t(7), t(11), t(5), 32).unwrap());
+	// A deposit time in the future doesn't wrap around to a long duration
+	assert!(!prove_deposit_duration(t(12), t(11), t(0), 32).unwrap());
+	assert!(!prove_deposit_duration(t(1 << 32), t(11), t(0), 32).unwrap());
+	assert!(prove_deposit_duration(t(1), t(1 << 32), t(0), 32).is_err());
+	assert!(prove_deposit_duration(t(1), t(11), t(1 << 32), 32).is_err());
+	assert!(prove_deposit_duration(t(1), t(11), t(0), 65).is_err());
+	assert!(prove_deposit_duration(t(1), t(11), t(0), 64).unwrap());
+}
+
+struct DepositDuration {
+	current_time: Scalar,
+	min_lockup: Scalar,
+	time_bits: usize,
+}
+
+impl TestCircuit for DepositDuration {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		_: Vec<Vec<AllocatedScalar>>,
+		_: bool,
+	) -> Result<(), R1CSError> {
+		let duration = deposit_duration_constraints(
+			cs,
+			committed[0],
+			&self.current_time,
+			&self.min_lockup,
+			self.time_bits,
+		)?;
+		cs.constrain(duration - committed[1].variable);
+		Ok(())
+	}
+}
+
+fn prove_deposit_duration(
+	deposit_time: Scalar,
+	current_time: Scalar,
+	min_lockup: Scalar,
+	time_bits: usize,
+) -> Result<bool, R1CSError> {
+	let circuit = DepositDuration {
+		current_time,
+		min_lockup,
+		time_bits,
+	};
+	let values = [deposit_time, current_time - deposit_time];
+	prove_and_verify(&circuit, &values, &[])
+}
+
+#[test]
+fn test_reward_schedules() {
+	let linear = RewardSchedule::Linear(RewardRate::new(37, 10_000));
+	assert_eq!(linear.reward(0), Some(0));
+	assert_eq!(linear.reward(270), Some(0));
+	assert_eq!(linear.reward(271), Some(1));
+	assert_eq!(linear.reward(100_000), Some(370));
+	assert_eq!(RewardRate::new(u64::MAX, 1).reward(2), None);
+	assert_eq!(RewardRate::new(1, 0).reward(2), None);
+
+	let tier = |min_duration, numerator, denominator| RewardTier {
+		min_duration,
+		rate: RewardRate::new(numerator, denominator),
+	};
+	let tiered = RewardSchedule::Tiered(vec![
+		tier(10, 1, 1),
+		tier(100, 3, 2),
+		tier(1000, 2, 1),
+	]);
+	assert_eq!(tiered.reward(9), Some(0));
+	assert_eq!(tiered.reward(10), Some(10));
+	assert_eq!(tiered.reward(99), Some(99));
+	assert_eq!(tiered.reward(101), Some(151));
+	assert_eq!(tiered.reward(1000), Some(2000));
+	let unsorted = RewardSchedule::Tiered(vec![tier(10, 1, 1), tier(10, 2, 1)]);
+	assert_eq!(unsorted.reward(20), None);
+
+	let capped = RewardSchedule::Capped {
+		schedule: Box::new(tiered.clone()),
+		cap: 500,
+	};
+	assert_eq!(capped.reward(99), Some(99));
+	assert_eq!(capped.reward(333), Some(499));
+	assert_eq!(capped.reward(334), Some(500));
+	assert_eq!(capped.reward(5000), Some(500));
+
+	// The circuit rounds the same way
+	for &duration in [0, 271, 12_345].iter() {
+		let reward = linear.reward(duration).unwrap();
+		assert!(prove_reward(&linear, duration, reward).unwrap());
+	}
+	for &duration in [0, 9, 10, 101, 999, 1000, 1 << 40].iter() {
+		let reward = tiered.reward(duration).unwrap();
+		assert!(prove_reward(&tiered, duration, reward).unwrap());
+		assert!(!prove_reward(&tiered, duration, reward + 1).unwrap());
+	}
+	for &duration in [99, 333, 334, 5000].iter() {
+		let reward = capped.reward(duration).unwrap();
+		assert!(prove_reward(&capped, duration, reward).unwrap());
+		assert!(!prove_reward(&capped, duration, reward + 1).unwrap());
+	}
+	assert!(prove_reward(&unsorted, 20, 20).is_err());
+}
+
+/// Proves that the committed `reward` is the reward of the committed
+/// `duration` under `schedule`
+struct ScheduledReward<'a> {
+	schedule: &'a RewardSchedule,
+	duration: u64,
+}
+
+impl<'a> TestCircuit for ScheduledReward<'a> {
+	fn synthesize<CS: ConstraintSystem>(
+		&self,
+		cs: &mut CS,
+		committed: Vec<AllocatedScalar>,
+		_: Vec<Vec<AllocatedScalar>>,
+		witness: bool,
+	) -> Result<(), R1CSError> {
+		let duration = AllocatedQuantity {
+			variable: committed[0].variable,
+			assignment: if witness { Some(self.duration) } else { None },
+		};
+		let computed = self.schedule.synthesize_reward(cs, duration)?;
+		cs.constrain(computed.variable - committed[1].variable);
+		Ok(())
+	}
+}
+
+fn prove_reward(
+	schedule: &RewardSchedule,
+	duration: u64,
+	reward: u64,
+) -> Result<bool, R1CSError> {
+	let circuit = ScheduledReward { schedule, duration };
+	let values = [Scalar::from(duration), Scalar::from(reward)];
+	prove_and_verify(&circuit, &values, &[])
+}
+
+#[test]
+fn test_reward_tree() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let mut tree = RewardTreeBuilder::new()
+		.depth(8)
+		.min_lockup(5)
+		.time_bits(32)
+		.build();
+	let note = DepositNote::random(&mut test_rng);
+	let leaf = note.leaf(&tree.hash_params);
+	let empty_root = tree.deposit_tree().root;
+	for time in 0..3 {
+		let other = DepositNote::random(&mut test_rng);
+		tree.deposit(other.leaf(&tree.hash_params), time).unwrap();
+	}
+	assert_eq!(tree.deposit(leaf, 1), Ok(Scalar::from(3u32)));
+	assert_eq!(tree.deposit(leaf, 1 << 32), Err(RewardError::InvalidTime));
+	// Deposits are appended, so leaves added later don't overwrite them
+	assert_eq!(tree.deposit_tree().curr_index, Scalar::from(4u32));
+	assert_eq!(tree.time_tree().curr_index, Scalar::from(4u32));
+	assert_eq!(tree.deposit_time(leaf), Ok(1));
+	assert!(tree.deposit_tree().is_known_root(&empty_root));
+	assert_ne!(tree.deposit_tree().root, tree.time_tree().root);
+	assert_eq!(tree.reward(&note, 11), Some(10));
+	assert_eq!(tree.reward(&note, 5), None);
+
+	let unknown = DepositNote::random(&mut test_rng);
+	assert_eq!(
+		tree.deposit_time(unknown.leaf(&tree.hash_params)),
+		Err(RewardError::Tree(TreeError::UnknownLeaf))
+	);
+	assert_eq!(tree.reward(&unknown, 11), None);
+
+	let outputs = [
+		OutputNote::random(4, &mut test_rng),
+		OutputNote::random(6, &mut test_rng),
+	];
+	// The outputs have to add up to the reward at the time of verification
+	assert!(
+		prove_and_verify_reward(&mut tree, &note, 11, 12, &outputs).is_err()
+	);
+	assert_eq!(
+		prove_and_verify_reward(&mut tree, &note, 11, 11, &outputs),
+		Ok(())
+	);
+	// A note is only rewarded once
+	assert_eq!(
+		prove_and_verify_reward(&mut tree, &note, 11, 11, &outputs),
+		Err(RewardError::SpentNullifier)
+	);
+	let short = [outputs[0], OutputNote::random(5, &mut test_rng)];
+	assert_eq!(
+		prove_and_verify_reward(&mut tree, &note, 11, 11, &short),
+		Err(RewardError::InvalidOutputs)
+	);
+	let zero = [outputs[0], outputs[1], OutputNote::random(0, &mut test_rng)];
+	assert_eq!(
+		prove_and_verify_reward(&mut tree, &note, 11, 11, &zero),
+		Err(RewardError::InvalidOutputs)
+	);
+	// The lock-up period is not over
+	let early = [OutputNote::random(4, &mut test_rng)];
+	assert_eq!(
+		prove_and_verify_reward(&mut tree, &note, 5, 5, &early),
+		Err(RewardError::NoReward)
+	);
+	// A note that wasn't deposited can't be proven
+	assert_eq!(
+		prove_and_verify_reward(&mut tree, &unknown, 11, 11, &outputs),
+		Err(RewardError::Tree(TreeError::UnknownLeaf))
+	);
+}
+
+#[test]
+fn test_reward_tree_unknown_time() {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let mut tree = RewardTreeBuilder::new().depth(8).build();
+	let note = DepositNote::random(&mut test_rng);
+	tree.deposit(note.leaf(&tree.hash_params), 1).unwrap();
+	// A copy that claims a deposit time that was never recorded
+	let mut other = tree.clone();
+	other
+		.time_tree
+		.update(Scalar::zero(), Scalar::random(&mut test_rng))
+		.unwrap();
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16384, 1);
+	let outputs = [OutputNote::random(10, &mut test_rng)];
+
+	let mut prover_transcript = Transcript::new(b"RewardTree");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let (proof, (comms, statement)) = tree
+		.prove_reward(&note, 11, &outputs, &bp_gens, prover, &mut test_rng)
+		.unwrap();
+	let mut verify = |comms: &RewardProofComms| {
+		let mut verifier_transcript = Transcript::new(b"RewardTree");
+		let verifier = Verifier::new(&mut verifier_transcript);
+		let mut rng = ChaChaRng::from_seed([2u8; 32]);
+		tree.verify_reward(
+			&proof, comms, 11, &pc_gens, &bp_gens, verifier, &mut rng,
+		)
+	};
+	// The time root has to be one the tree knows of
+	let mut unknown_root = statement.clone();
+	unknown_root.time_root = Scalar::random(&mut test_rng);
+	assert_eq!(
+		verify(&(comms.clone(), unknown_root)),
+		Err(RewardError::Tree(TreeError::UnknownRoot))
+	);
+	let mut missing_output = statement.clone();
+	missing_output.output_leaves.pop();
+	assert_eq!(
+		verify(&(comms.clone(), missing_output)),
+		Err(RewardError::InvalidProof)
+	);
+	assert_eq!(verify(&(comms.clone(), statement.clone())), Ok(()));
+	assert_eq!(
+		verify(&(comms, statement)),
+		Err(RewardError::SpentNullifier)
+	);
+
+	// A deposit time that was never recorded doesn't verify, even under a
+	// known root
+	let mut prover_transcript = Transcript::new(b"RewardTree");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let (proof, comms) = other
+		.prove_reward(&note, 11, &outputs, &bp_gens, prover, &mut test_rng)
+		.unwrap();
+	let mut verifier_transcript = Transcript::new(b"RewardTree");
+	let verifier = Verifier::new(&mut verifier_transcript);
+	assert_eq!(
+		other.verify_reward(
+			&proof,
+			&comms,
+			11,
+			&pc_gens,
+			&bp_gens,
+			verifier,
+			&mut test_rng
+		),
+		Err(RewardError::R1CSError(R1CSError::VerificationError))
+	);
+}
+
+/// Proves the reward of `note` at `prove_time` and verifies it at
+/// `verify_time`
+fn prove_and_verify_reward(
+	tree: &mut RewardTree,
+	note: &DepositNote,
+	prove_time: u64,
+	verify_time: u64,
+	outputs: &[OutputNote],
+) -> Result<(), RewardError> {
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16384, 1);
+
+	let mut prover_transcript = Transcript::new(b"RewardTree");
+	let prover = Prover::new(&pc_gens, &mut prover_transcript);
+	let (proof, comms) = tree.prove_reward(
+		note,
+		prove_time,
+		outputs,
+		&bp_gens,
+		prover,
+		&mut test_rng,
+	)?;
+
+	let mut verifier_transcript = Transcript::new(b"RewardTree");
+	let verifier = Verifier::new(&mut verifier_transcript);
+	tree.verify_reward(
+		&proof,
+		&comms,
+		verify_time,
+		&pc_gens,
+		&bp_gens,
+		verifier,
+		&mut test_rng,
+	)
+}
+
+/// Only the deposit time recorded in the time tree can be claimed
+#[test]
+fn test_time_based_reward_unrecorded_time() {
+	let p_params = PoseidonBuilder::new(6).sbox(PoseidonSbox::Inverse).build();
+	let mut test_rng = ChaChaRng::from_seed([1u8; 32]);
+	let r = Scalar::random(&mut test_rng);
+	let nullifier = Scalar::random(&mut test_rng);
+	let leaf = Poseidon_hash_2(r, nullifier, &p_params);
+	let deposit_time = Scalar::from(5u32);
+	let current_time = Scalar::from(11u32);
+
+	let mut deposit_tree = SparseMerkleTreeBuilder::new()
+		.hash_params(p_params.clone())
+		.depth(8)
+		.build();
+	let mut time_tree = deposit_tree.clone();
+	let index = Scalar::one();
+	deposit_tree.update(index, leaf).unwrap();
+	let time_leaf = Poseidon_hash_2(leaf, deposit_time, &p_params);
+	time_tree.update(index, time_leaf).unwrap();
+
+	let depth = deposit_tree.depth;
+	let bits: Vec<Scalar> = get_bits(&index, depth)
+		.iter()
+		.take(depth)
+		.map(|b| Scalar::from(*b as u8))
+		.collect();
+	let deposit_path = deposit_tree.path_at(index).unwrap();
+	let time_path = time_tree.path_at(index).unwrap();
+	let pc_gens = PedersenGens::default();
+	let bp_gens = BulletproofGens::new(16384, 1);
+
+	// Claims the reward of a deposit at `claimed_time`, proving the time
+	// leaf under `time_root`
+	let prove = |claimed_time: Scalar, time_root: Scalar| {
+		let mut test_rng = ChaChaRng::from_seed([2u8; 32]);
+		let value = current_time - claimed_time;
+		let output: Vec<Scalar> =
+			(0..3).map(|_| Scalar::random(&mut test_rng)).collect();
+		let output_cm = Poseidon_hash_4(
+			[value, output[0], output[1], output[2]],
+			&p_params,
+		);
+		let mut values = vec![
+			r,
+			nullifier,
+			leaf,
+			claimed_time,
+			Poseidon_hash_2(leaf, claimed_time, &p_params),
+		];
+		values.extend(bits.iter().chain(deposit_path.iter()));
+		values.extend(bits.iter().chain(time_path.iter()));
+		values.extend(&[value.invert(), value]);
+		values.extend(output);
+
+		let transaction = |vars: Vec<AllocatedScalar>,
+		                   statics_2: Vec<AllocatedScalar>,
+		                   statics_4: Vec<AllocatedScalar>| {
+			let path =
+				|i: usize| vars[5 + i * depth..5 + (i + 1) * depth].to_vec();
+			let coin = &vars[5 + 4 * depth..];
+			Transaction {
+				depth,
+				deposit_root: deposit_tree.root,
+				input: AllocatedInputCoin {
+					r: vars[0],
+					nullifier: vars[1],
+					leaf_cm_val: vars[2],
+					leaf_index_bits: path(0),
+					leaf_proof_nodes: path(1),
+					sn: Poseidon_hash_2(nullifier, nullifier, &p_params),
+				},
+				timed_deposit: AllocatedTimedDeposit {
+					time_root,
+					schedule: RewardSchedule::Linear(RewardRate::new(1, 1)),
+					current_time,
+					min_lockup: Scalar::zero(),
+					time_bits: 32,
+					deposit_time: vars[3],
+					deposit_time_cm_val: vars[4],
+					deposit_time_index_bits: path(2),
+					deposit_time_proof_nodes: path(3),
+				},
+				outputs: vec![AllocatedOutputCoin {
+					inv_value: coin[0],
+					value: coin[1],
+					rho: coin[2],
+					r: coin[3],
+					nullifier: coin[4],
+					leaf_cm: output_cm,
+				}],
+				statics_2,
+				statics_4,
+			}
+		};
+
+		let mut prover_transcript = Transcript::new(b"TimedReward");
+		let mut prover = Prover::new(&pc_gens, &mut prover_transcript);
+		let vars =
+			allocate_scalars(&mut prover, values.len(), Some(&values)).unwrap();
+		let statics_2 = allocate_statics_for_prover(&mut prover, 4);
+		let statics_4 = allocate_statics_for_prover(&mut prover, 2);
+		time_based_reward_verif_gadget(
+			&mut prover,
+			vec![transaction(vars, statics_2, statics_4)],
+			&p_params,
+		)
+		.unwrap();
+		let proof = prover.prove_with_rng(&bp_gens, &mut test_rng).unwrap();
+
+		let mut verifier_transcript = Transcript::new(b"TimedReward");
+		let mut verifier = Verifier::new(&mut verifier_transcript);
+		let vars = allocate_scalars(&mut verifier, values.len(), None).unwrap();
+		let statics_2 =
+			allocate_statics_for_verifier(&mut verifier, 4, &pc_gens);
+		let statics_4 =
+			allocate_statics_for_verifier(&mut verifier, 2, &pc_gens);
+		time_based_reward_verif_gadget(
+			&mut verifier,
+			vec![transaction(vars, statics_2, statics_4)],
+			&p_params,
+		)
+		.unwrap();
+		verifier
+			.verify_with_rng(&proof, &pc_gens, &bp_gens, &mut test_rng)
+			.is_ok()
+	};
+
+	assert!(prove(deposit_time, time_tree.root));
+	// An earlier deposit time would earn a larger reward
+	assert!(!prove(Scalar::from(1u32), time_tree.root));
+	// The time leaf has to be under the time root
+	assert!(!prove(deposit_time, deposit_tree.root));
+}
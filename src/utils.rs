@@ -1,4 +1,4 @@
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use bulletproofs::r1cs::{
 	ConstraintSystem, LinearCombination, R1CSError, Variable,
 };
@@ -48,6 +48,17 @@ pub fn get_scalar_from_hex(hex_str: &str) -> Scalar {
 	Scalar::from_bytes_mod_order(result)
 }
 
+/// Returns the value of `scalar` if it fits in a u64
+pub fn scalar_to_u64(scalar: &Scalar) -> Option<u64> {
+	let bytes = scalar.as_bytes();
+	if bytes[8..].iter().any(|b| *b != 0) {
+		return None;
+	}
+	let mut low = [0u8; 8];
+	low.copy_from_slice(&bytes[..8]);
+	Some(u64::from_le_bytes(low))
+}
+
 /// Enforces that the quantity of v is in the range [0, 2^n).
 pub fn positive_no_gadget<CS: ConstraintSystem>(
 	cs: &mut CS,
@@ -81,6 +92,92 @@ pub fn positive_no_gadget<CS: ConstraintSystem>(
 	Ok(())
 }
 
+/// Decompose `v` into `bit_size` bits in circuit and return them, LSB
+/// first. The bits are allocated as the right wires of multipliers, as in
+/// `positive_no_gadget`, so they are boolean. At most 252 bits are allowed so
+/// that the decomposition is unique, which also range checks `v`.
+pub fn scalar_bits_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	v: AllocatedScalar,
+	bit_size: usize,
+) -> Result<Vec<AllocatedScalar>, R1CSError> {
+	lc_bits_gadget(cs, v.variable.into(), v.assignment, bit_size)
+}
+
+/// Same as `scalar_bits_gadget` for a linear combination, such as the output
+/// of a hash, whose value is `assignment`
+pub fn lc_bits_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	v: LinearCombination,
+	assignment: Option<Scalar>,
+	bit_size: usize,
+) -> Result<Vec<AllocatedScalar>, R1CSError> {
+	if bit_size > 252 {
+		return Err(R1CSError::GadgetError {
+			description: String::from("Scalar must have at most 252 bits"),
+		});
+	}
+	let bits = assignment.map(|s| get_bits(&s, bit_size));
+	let mut constraint_v = -v;
+	let mut allocated_bits = Vec::with_capacity(bit_size);
+	let mut exp_2 = Scalar::one();
+	for i in 0..bit_size {
+		let bit = bits.as_ref().map(|b| Scalar::from(b[i]));
+		let (a, b, o) =
+			cs.allocate_multiplier(bit.map(|b| (Scalar::one() - b, b)))?;
+
+		// Enforce a * b = 0 and a = 1 - b, so b is 0 or 1
+		cs.constrain(o.into());
+		cs.constrain(a + (b - 1u64));
+
+		constraint_v = constraint_v + b * exp_2;
+		allocated_bits.push(AllocatedScalar {
+			variable: b,
+			assignment: bit,
+		});
+		exp_2 = exp_2 + exp_2;
+	}
+
+	// Sum(b_i * 2^i, i = 0..n-1) = v
+	cs.constrain(constraint_v);
+
+	Ok(allocated_bits)
+}
+
+/// Allocate `n` witnesses that don't have to be committed to, two per
+/// multiplier. The prover passes the values, the verifier None.
+pub fn allocate_scalars<CS: ConstraintSystem>(
+	cs: &mut CS,
+	n: usize,
+	assignments: Option<&[Scalar]>,
+) -> Result<Vec<AllocatedScalar>, R1CSError> {
+	if let Some(values) = assignments {
+		if values.len() != n {
+			return Err(R1CSError::GadgetError {
+				description: String::from("Wrong number of assignments"),
+			});
+		}
+	}
+	let mut allocated = Vec::with_capacity(n);
+	for i in (0..n).step_by(2) {
+		let pair = assignments.map(|values| {
+			(values[i], values.get(i + 1).cloned().unwrap_or_default())
+		});
+		let (l, r, _) = cs.allocate_multiplier(pair)?;
+		allocated.push(AllocatedScalar {
+			variable: l,
+			assignment: pair.map(|p| p.0),
+		});
+		if i + 1 < n {
+			allocated.push(AllocatedScalar {
+				variable: r,
+				assignment: pair.map(|p| p.1),
+			});
+		}
+	}
+	Ok(allocated)
+}
+
 /// Constrain a linear combination to be equal to a scalar
 pub fn constrain_lc_with_scalar<CS: ConstraintSystem>(
 	cs: &mut CS,
@@ -90,6 +187,89 @@ pub fn constrain_lc_with_scalar<CS: ConstraintSystem>(
 	cs.constrain(lc - LinearCombination::from(*scalar));
 }
 
+/// Constrain a linear combination to be 0 or 1, `bit * (1 - bit) = 0`
+pub fn constrain_boolean<CS: ConstraintSystem>(
+	cs: &mut CS,
+	bit: LinearCombination,
+) {
+	let (_, _, o) = cs.multiply(bit.clone(), Variable::One() - bit);
+	cs.constrain(o.into());
+}
+
+/// Constrain `index` to be `Sum(bits_i * 2^i)`. The bits must be boolean and
+/// fewer than 253 for the index to have a single decomposition.
+pub fn leaf_index_recomposition_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	bits: &[AllocatedScalar],
+	index: LinearCombination,
+) -> Result<(), R1CSError> {
+	if bits.len() > 252 {
+		return Err(R1CSError::GadgetError {
+			description: String::from("Index must have at most 252 bits"),
+		});
+	}
+	let mut recomposed = LinearCombination::default();
+	let mut exp_2 = Scalar::one();
+	for bit in bits {
+		recomposed = recomposed + bit.variable * exp_2;
+		exp_2 = exp_2 + exp_2;
+	}
+	cs.constrain(recomposed - index);
+	Ok(())
+}
+
+/// Allocate a quantity that doesn't have to be committed to, see
+/// `allocate_scalars`
+pub fn allocate_quantity<CS: ConstraintSystem>(
+	cs: &mut CS,
+	assignment: Option<u64>,
+) -> Result<AllocatedQuantity, R1CSError> {
+	let (variable, ..) = cs.allocate_multiplier(
+		assignment.map(|q| (Scalar::from(q), Scalar::zero())),
+	)?;
+	Ok(AllocatedQuantity {
+		variable,
+		assignment,
+	})
+}
+
+/// Returns a bit that is 1 if `v >= threshold` and 0 otherwise. `v` must be
+/// range checked to 64 bits, e.g. with `positive_no_gadget`. The bit selects
+/// `v - threshold` or `threshold - 1 - v`, which is decomposed in 64 bits,
+/// so it can't be the wrong one as the other difference is negative.
+pub fn at_least_gadget<CS: ConstraintSystem>(
+	cs: &mut CS,
+	v: AllocatedQuantity,
+	threshold: u64,
+) -> Result<AllocatedScalar, R1CSError> {
+	let bit = v.assignment.map(|value| value >= threshold);
+	let (a, b, o) = cs.allocate_multiplier(bit.map(|bit| {
+		let bit = Scalar::from(bit as u8);
+		(Scalar::one() - bit, bit)
+	}))?;
+	// Enforce a * b = 0 and a = 1 - b, so b is 0 or 1
+	cs.constrain(o.into());
+	cs.constrain(a + (b - 1u64));
+
+	// b * (2 * diff + 1) - diff - 1 is diff if b is 1, -diff - 1 otherwise
+	let diff = v.variable - Scalar::from(threshold);
+	let (_, _, flipped) =
+		cs.multiply(b.into(), diff.clone() * Scalar::from(2u8) + Scalar::one());
+	let selected = v.assignment.map(|value| {
+		if value >= threshold {
+			Scalar::from(value - threshold)
+		} else {
+			Scalar::from(threshold - 1 - value)
+		}
+	});
+	lc_bits_gadget(cs, flipped - diff - Scalar::one(), selected, 64)?;
+
+	Ok(AllocatedScalar {
+		variable: b,
+		assignment: bit.map(|bit| Scalar::from(bit as u8)),
+	})
+}
+
 /// Get a bit array of this scalar, LSB is first element of this array
 #[derive(Clone)]
 pub struct ScalarBits {
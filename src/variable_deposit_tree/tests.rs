@@ -75,21 +75,20 @@ fn test_variable_deposit_tree_verification() {
 		let index = Scalar::from(i as u32);
 		let s = if i == 7 { input_cm } else { index };
 
-		tree.update(index, s);
+		tree.update(index, s).unwrap();
 	}
 
 	let mut merkle_proof_vec = Vec::<Scalar>::new();
 	let mut merkle_proof = Some(merkle_proof_vec);
 	let k = Scalar::from(7u32);
-	assert_eq!(input_cm, tree.get(k, tree.root, &mut merkle_proof));
+	assert_eq!(input_cm, tree.get(k, tree.root, &mut merkle_proof).unwrap());
 	merkle_proof_vec = merkle_proof.unwrap();
-	assert!(tree.verify_proof(k, input_cm, &merkle_proof_vec, None));
-	assert!(tree.verify_proof(
-		k,
-		input_cm,
-		&merkle_proof_vec,
-		Some(&tree.root)
-	));
+	assert!(tree
+		.verify_proof(k, input_cm, &merkle_proof_vec, None)
+		.unwrap());
+	assert!(tree
+		.verify_proof(k, input_cm, &merkle_proof_vec, Some(&tree.root))
+		.unwrap());
 
 	let pc_gens = PedersenGens::default();
 	let bp_gens = BulletproofGens::new(16500, 1);